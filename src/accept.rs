@@ -0,0 +1,172 @@
+// src/accept.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+use crate::config::Config;
+
+// An address or CIDR block from --allow-ip, e.g. "10.0.0.0/8" or "::1"
+#[derive(Debug, Clone, Copy)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+// What happens to a connection the filter turns away, from --reject-connections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reject {
+    Forbidden,
+    Drop,
+}
+
+// Connection-level policy by peer address, checked before anything is read: --allow-ip,
+// --connection-rate and --max-connections-per-ip. Unix socket clients have no address and
+// are always let in.
+pub struct Filter {
+    open: Mutex<HashMap<IpAddr, usize>>,
+    // A token bucket per address for --connection-rate
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Holds the peer's place under --max-connections-per-ip until the connection ends
+pub struct Admitted<'a> {
+    filter: &'a Filter,
+    peer: Option<IpAddr>,
+}
+
+pub enum Rejection {
+    NotAllowed,
+    TooFast,
+    TooMany(usize),
+}
+
+impl Network {
+    pub fn parse(raw: &str) -> Result<Network, String> {
+        let invalid = || format!("--allow-ip must be an IP address or CIDR block like 10.0.0.0/8, got {:?}", raw);
+        let (address, prefix) = match raw.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (raw, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|prefix| *prefix <= bits).ok_or_else(invalid)?,
+            None => bits,
+        };
+        // Peers are compared in their canonical form, so ::ffff:10.0.0.0/104 is kept as 10.0.0.0/8
+        if let IpAddr::V4(v4) = address.to_canonical() {
+            if address.is_ipv6() {
+                if prefix < 96 {
+                    return Err(format!("--allow-ip {:?} covers more than IPv4-mapped addresses; give those as IPv4, like 10.0.0.0/8", raw));
+                }
+                return Ok(Network { address: IpAddr::V4(v4), prefix: prefix - 96 });
+            }
+        }
+        Ok(Network { address, prefix })
+    }
+
+    fn contains(&self, peer: IpAddr) -> bool {
+        match (self.address, peer.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(peer)) => same_prefix(u32::from(network).into(), u32::from(peer).into(), 32, self.prefix),
+            (IpAddr::V6(network), IpAddr::V6(peer)) => same_prefix(network.into(), peer.into(), 128, self.prefix),
+            _ => false,
+        }
+    }
+}
+
+fn same_prefix(network: u128, peer: u128, bits: u8, prefix: u8) -> bool {
+    let host_bits = (bits - prefix) as u32;
+    network.checked_shr(host_bits).unwrap_or(0) == peer.checked_shr(host_bits).unwrap_or(0)
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::NotAllowed => write!(f, "address not in --allow-ip"),
+            Rejection::TooFast => write!(f, "over --connection-rate"),
+            Rejection::TooMany(max) => write!(f, "already {} connections open from this address", max),
+        }
+    }
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter { open: Mutex::new(HashMap::new()), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn check(&self, config: &Config, peer: Option<IpAddr>) -> Result<Admitted<'_>, Rejection> {
+        let Some(peer) = peer.map(|peer| peer.to_canonical()) else {
+            return Ok(Admitted { filter: self, peer: None });
+        };
+        if !config.allow_ips.is_empty() && !config.allow_ips.iter().any(|network| network.contains(peer)) {
+            return Err(Rejection::NotAllowed);
+        }
+        if let Some(rate) = config.connection_rate {
+            // Bursts of up to a second's worth
+            let burst = rate.max(1.0);
+            let now = Instant::now();
+            let mut buckets = self.buckets.lock().unwrap();
+            // Full buckets are the same as none
+            buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst);
+            let bucket = buckets.entry(peer).or_insert(Bucket { tokens: burst, updated: now });
+            bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                return Err(Rejection::TooFast);
+            }
+            bucket.tokens -= 1.0;
+        }
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(peer).or_insert(0);
+        if config.max_connections_per_ip.is_some_and(|max| *count >= max) {
+            return Err(Rejection::TooMany(*count));
+        }
+        *count += 1;
+        Ok(Admitted { filter: self, peer: Some(peer) })
+    }
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        let Some(peer) = self.peer else {
+            return;
+        };
+        let mut open = self.filter.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&peer) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_ipv4_mapped_networks() {
+        let network = Network::parse("::ffff:10.0.0.0/104").unwrap();
+        assert_eq!(network.to_string(), "10.0.0.0/8");
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+        assert_eq!(Network::parse("::ffff:127.0.0.1").unwrap().to_string(), "127.0.0.1/32");
+        assert!(Network::parse("::ffff:0.0.0.0/80").is_err());
+        assert_eq!(Network::parse("fd00::/8").unwrap().to_string(), "fd00::/8");
+    }
+}
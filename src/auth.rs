@@ -0,0 +1,14 @@
+// src/auth.rs for the Rust helper "FlurionsPythonBindings"
+
+// Checks an Authorization header against the configured bearer token
+pub fn bearer_matches(authorization: Option<&str>, token: &str) -> bool {
+    match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(presented) => constant_time_eq(presented.trim().as_bytes(), token.as_bytes()),
+        None => false,
+    }
+}
+
+// Doesn't stop at the first mismatch, so timing doesn't leak how much of the token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,37 @@
+// src/bytecode.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use log::debug;
+use crate::exec;
+
+// Compiles the source in argv[1] to <its dir>/<argv[2]>.<cache tag>.pyc unless that's there already,
+// and prints where it is. The tag names the interpreter and version, so a different python never
+// picks up this one's bytecode; the hash-based pyc is never checked against the source, which
+// our key already pins.
+const COMPILER: &str = r#"
+import os, py_compile, sys
+source, key = sys.argv[1], sys.argv[2]
+if sys.implementation.cache_tag is None:
+    sys.exit("this python doesn't cache bytecode")
+cfile = os.path.join(os.path.dirname(source), "%s.%s.pyc" % (key, sys.implementation.cache_tag))
+if not os.path.exists(cfile):
+    py_compile.compile(source, cfile=cfile, doraise=True, invalidation_mode=py_compile.PycInvalidationMode.UNCHECKED_HASH)
+print(cfile)
+"#;
+
+// The bytecode of `source`, kept next to it under `key`
+pub fn compile(mut python: Command, source: &Path, key: &str, timeout: Duration) -> Result<PathBuf, String> {
+    debug!("Compiling {:?}", source);
+    python.arg("-c").arg(COMPILER).arg(source).arg(key);
+    let out = exec::run(python, None, timeout, None).map_err(|e| format!("Failed to run python: {}", e))?;
+    if out.timed_out() {
+        return Err(format!("Compiling took longer than {:?}", timeout));
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    match stdout.lines().last() {
+        Some(path) if out.status.is_some_and(|status| status.success()) => Ok(PathBuf::from(path)),
+        _ => Err(String::from_utf8_lossy(&out.stderr).trim().lines().last().unwrap_or_default().to_string()),
+    }
+}
@@ -0,0 +1,57 @@
+// src/capabilities.rs for the Rust helper "FlurionsPythonBindings"
+
+use serde_json::{json, Value};
+use crate::config::{Config, Sandbox};
+
+// What this build and configuration support, for GET /api/capabilities. A feature the build
+// lacks is listed as false rather than left out, so clients can tell "off" from "too old to know".
+pub fn to_json(config: &Config) -> Value {
+    let python = config.interpreter_template.is_none();
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "sandbox": config.sandbox.map(|sandbox| match sandbox {
+            Sandbox::Docker => "docker",
+            Sandbox::Namespaces => "namespaces",
+        }),
+        "features": {
+            "interpreter": true,
+            "assert": true,
+            "pipeline": true,
+            "jobs": true,
+            "stored_scripts": true,
+            "streaming": true,
+            "multipart": true,
+            "gzip_bodies": true,
+            // These wrap the script in python code
+            "profile": python,
+            "track_resources": python,
+            "timing": python,
+            "interpreter_template": !python,
+            "merge_output": true,
+            "output_limits": true,
+            "warmup": true,
+            "lint": true,
+            "format": true,
+            "ping": true,
+            "pip": true,
+            "pip_install": config.sandbox.is_none(),
+            // A session's interpreter would run outside the sandbox
+            "sessions": config.sandbox.is_none(),
+            "websocket": config.sandbox.is_none(),
+            "sandbox": config.sandbox.is_some(),
+            "restricted_builtins": config.restricted_builtins,
+            "file_exec": config.allow_file_exec,
+            "admin": config.token.is_some(),
+            "run_as_user": config.run_as_user.is_some(),
+            "unix_sockets": cfg!(unix),
+            "graceful_shutdown": cfg!(unix),
+            "tls": false,
+        },
+        "limits": {
+            "max_body_bytes": config.max_body_bytes,
+            "max_script_bytes": config.max_script_bytes,
+            "max_timeout": config.max_timeout.as_secs_f64(),
+            "max_sessions": config.max_sessions,
+        },
+    })
+}
@@ -0,0 +1,139 @@
+// src/concurrency.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Execution slots handed out in arrival order
+pub struct Slots {
+    max: usize,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    running: usize,
+    next_ticket: u64,
+    next_to_run: u64,
+    // Moving average of how long a slot is held, for wait estimates
+    average_run: Duration,
+}
+
+pub struct Permit<'a> {
+    slots: &'a Slots,
+    acquired: Instant,
+    // Set when the request had to wait for a slot
+    pub queued: Option<Queued>,
+}
+
+pub struct Queued {
+    pub position: u64,
+    pub estimated_wait: Duration,
+}
+
+// A snapshot for GET /api/load
+pub struct Load {
+    pub running: usize,
+    pub queued: u64,
+    pub max: usize,
+    pub average_run: Duration,
+}
+
+impl Slots {
+    pub fn new(max: usize) -> Slots {
+        Slots {
+            max,
+            state: Mutex::new(State { running: 0, next_ticket: 0, next_to_run: 0, average_run: Duration::ZERO }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        let queued = if ticket != state.next_to_run || state.running >= self.max {
+            let position = ticket - state.next_to_run + 1;
+            let rounds = position.div_ceil(self.max as u64) as u32;
+            Some(Queued { position, estimated_wait: state.average_run * rounds })
+        } else {
+            None
+        };
+        while ticket != state.next_to_run || state.running >= self.max {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.next_to_run += 1;
+        state.running += 1;
+        // The next ticket may fit in a free slot too
+        self.changed.notify_all();
+        Permit { slots: self, acquired: Instant::now(), queued }
+    }
+
+    pub fn load(&self) -> Load {
+        let state = self.state.lock().unwrap();
+        Load {
+            running: state.running,
+            // Tickets handed out but not yet let through
+            queued: state.next_ticket - state.next_to_run,
+            max: self.max,
+            average_run: state.average_run,
+        }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let held = self.acquired.elapsed();
+        let mut state = self.slots.state.lock().unwrap();
+        state.running -= 1;
+        state.average_run = if state.average_run.is_zero() {
+            held
+        } else {
+            (state.average_run * 4 + held) / 5
+        };
+        self.slots.changed.notify_all();
+    }
+}
+
+impl Queued {
+    pub fn headers(&self) -> [(&'static str, String); 2] {
+        [
+            ("X-Queue-Position", self.position.to_string()),
+            ("X-Queue-Wait-Estimate-Ms", self.estimated_wait.as_millis().to_string()),
+        ]
+    }
+}
+
+// Spaces process spawns at least `interval` apart across all requests, against fork storms
+pub struct SpawnRate {
+    interval: Mutex<Option<Duration>>,
+    next: Mutex<Instant>,
+}
+
+impl SpawnRate {
+    pub fn new(interval: Option<Duration>) -> SpawnRate {
+        SpawnRate { interval: Mutex::new(interval), next: Mutex::new(Instant::now()) }
+    }
+
+    // For /admin/reload; spawns already waiting keep their turn
+    pub fn set_interval(&self, interval: Option<Duration>) {
+        *self.interval.lock().unwrap() = interval;
+    }
+
+    // Blocks until it's this spawn's turn and returns how long that took
+    pub fn wait(&self) -> Duration {
+        let Some(interval) = *self.interval.lock().unwrap() else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let turn = {
+            let mut next = self.next.lock().unwrap();
+            let turn = (*next).max(now);
+            *next = turn + interval;
+            turn
+        };
+        let wait = turn - now;
+        thread::sleep(wait);
+        wait
+    }
+}
@@ -0,0 +1,833 @@
+// src/config.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::accept::{Network, Reject};
+use crate::formatter::Formatter;
+use crate::lint::Linter;
+use crate::{http, interpreter};
+use crate::net::{self, Address, SocketOptions};
+
+// Where an option's value came from
+#[derive(Debug, Clone, Copy)]
+pub enum Source {
+    Default,
+    File,
+    // FPB_* variables
+    Env,
+    Cli,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::File => "file",
+            Source::Env => "env",
+            Source::Cli => "cli",
+        }
+    }
+}
+
+// Where scripts run instead of straight on the host
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Sandbox {
+    Docker,
+    // Linux namespaces around the child; see namespaces.rs
+    Namespaces,
+}
+
+// What to do with stdout that has NUL bytes in it, which doesn't survive as text
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOutput {
+    Base64,
+    // Each NUL as U+FFFD
+    Replace,
+    Error,
+}
+
+impl BinaryOutput {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinaryOutput::Base64 => "base64",
+            BinaryOutput::Replace => "replace",
+            BinaryOutput::Error => "error",
+        }
+    }
+}
+
+// What to do when a script dies of a crash signal; see exec::crash_signal
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OnCrash {
+    Fail,
+    // Once, in case it was a fluke
+    Retry,
+}
+
+impl OnCrash {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OnCrash::Fail => "fail",
+            OnCrash::Retry => "retry",
+        }
+    }
+}
+
+// --run or --exec: run this once and exit instead of serving
+#[derive(Clone)]
+pub enum OneShot {
+    File(PathBuf),
+    Code(String),
+}
+
+#[derive(Clone)]
+pub struct Listen {
+    pub address: Address,
+    // Off for trusted local sockets, where --token isn't asked for
+    pub require_auth: bool,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub log_level: &'static str,
+    // Instead of stderr; reopened on SIGHUP
+    pub log_file: Option<PathBuf>,
+    pub host: String,
+    pub port: u16,
+    pub timeout: Duration,
+    pub max_timeout: Duration,
+    // For snippets run in a session; --timeout and --max-timeout when unset
+    pub session_timeout: Option<Duration>,
+    pub max_session_timeout: Option<Duration>,
+    pub python: Option<String>,
+    // Runs scripts with something other than python; see interpreter::parse_template
+    pub interpreter_template: Option<Vec<String>>,
+    pub run_as_user: Option<String>,
+    pub headers: Vec<(String, String)>,
+    // Only on the root page, on top of or in place of ROOT_HEADERS
+    pub root_headers: Vec<(String, String)>,
+    pub no_root_headers: bool,
+    pub token: Option<String>,
+    pub history_size: usize,
+    pub max_concurrency: usize,
+    pub allow_file_exec: bool,
+    pub file_exec_root: Option<PathBuf>,
+    pub no_content_on_empty: bool,
+    pub binary_output: BinaryOutput,
+    pub on_crash: OnCrash,
+    pub pretty: bool,
+    // How long a finished job's result stays available
+    pub job_ttl: Duration,
+    pub max_completed_jobs: usize,
+    // How long a script from POST /api/scripts is kept after its last run
+    pub script_ttl: Duration,
+    // How long a response is replayed for a repeated Idempotency-Key
+    pub idempotency_ttl: Duration,
+    pub max_stored_scripts: usize,
+    pub max_idempotency_keys: usize,
+    // Soft limit on what the in-memory stores hold together; see memory.rs
+    pub max_server_memory: Option<usize>,
+    pub max_header_lines: usize,
+    // How long a read from the client may stall before the request is given up on
+    pub read_timeout: Duration,
+    // For accepted connections; see net::SocketOptions
+    pub tcp_nodelay: bool,
+    pub socket_send_buffer: Option<usize>,
+    pub socket_recv_buffer: Option<usize>,
+    // Read from requests, generated when missing, and echoed on responses
+    pub request_id_header: String,
+    // Like "/fpb", without a trailing slash; every route, the root page included, lives under it
+    pub route_prefix: Option<String>,
+    // Minimum gap between spawns, from --spawn-rate
+    pub spawn_interval: Option<Duration>,
+    // Applies to the decompressed size of gzip bodies too
+    pub max_body_bytes: usize,
+    // Take a body without Content-Length as everything up to the client's shutdown
+    pub read_to_eof: bool,
+    // Refuse JSON bodies with a key given twice in one object
+    pub strict_json: bool,
+    // For the code itself, which is written to disk; unlimited when unset
+    pub max_script_bytes: Option<usize>,
+    pub restricted_builtins: bool,
+    pub sandbox: Option<Sandbox>,
+    pub sandbox_image: String,
+    // Keep the host's network with --sandbox namespaces
+    pub sandbox_network: bool,
+    // How long /api/packages reuses a pip list
+    pub packages_ttl: Duration,
+    // For pip installs that hit a network error
+    pub pip_retries: usize,
+    pub pip_retry_backoff: Duration,
+    pub nice: Option<i32>,
+    // Defaults to <temp>/fpb
+    pub work_dir: Option<PathBuf>,
+    // Bytecode of modules scripts import, kept across runs and restarts
+    pub pycache_dir: Option<PathBuf>,
+    pub no_auto_mkdir: bool,
+    // Give each run its own empty HOME inside its run directory
+    pub isolated_home: bool,
+    // Pause after a failed accept, and how many in a row we put up with
+    pub accept_backoff: Duration,
+    pub max_accept_failures: usize,
+    // Connection policy by peer address; see accept.rs. Any address when allow_ips is empty
+    pub allow_ips: Vec<Network>,
+    // New connections per second per address
+    pub connection_rate: Option<f64>,
+    pub max_connections_per_ip: Option<usize>,
+    pub reject_connections: Reject,
+    // Start scripts with only the passthrough variables (plus per-request env)
+    pub clean_env: bool,
+    pub env_passthrough: Vec<String>,
+    // Request headers scripts see as HTTP_* variables
+    pub expose_headers_as_env: Vec<String>,
+    // From --listen; just --host and --port when none are given
+    pub listeners: Vec<Listen>,
+    // Unlimited when unset
+    pub max_sessions: Option<usize>,
+    // A GET /api/ws socket with no message for this long is closed
+    pub ws_idle_timeout: Duration,
+    // What POST /api/warmup imports when the request doesn't say
+    pub warmup_modules: Vec<String>,
+    // For POST /api/lint
+    pub linter: Linter,
+    // For POST /api/format
+    pub formatter: Formatter,
+    // Keyed by option name, e.g. "timeout" for --timeout; unset options are defaults
+    pub sources: HashMap<String, Source>,
+    // Between SIGTERM and SIGKILL for running scripts when the helper is stopped
+    pub shutdown_grace: Duration,
+    // Exit after this long without requests
+    pub idle_exit: Option<Duration>,
+    pub one_shot: Option<OneShot>,
+    // Request bodies and script output in the debug log, redacted and cut to the limit
+    pub log_bodies: bool,
+    pub log_body_limit: usize,
+    // Report how python was started on each response
+    pub debug_exec: bool,
+    // Options from --config are applied first, so the command line wins
+    pub config_file: Option<PathBuf>,
+    // As given, so /admin/reload can build the config again
+    pub args: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            log_level: "warn",
+            log_file: None,
+            host: "127.0.0.1".to_string(),
+            port: 6914,
+            timeout: Duration::from_secs(30),
+            max_timeout: Duration::from_secs(300),
+            session_timeout: None,
+            max_session_timeout: None,
+            python: None,
+            interpreter_template: None,
+            run_as_user: None,
+            headers: Vec::new(),
+            root_headers: Vec::new(),
+            no_root_headers: false,
+            token: None,
+            history_size: 50,
+            max_concurrency: thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            allow_file_exec: false,
+            file_exec_root: None,
+            no_content_on_empty: false,
+            binary_output: BinaryOutput::Base64,
+            on_crash: OnCrash::Fail,
+            pretty: false,
+            job_ttl: Duration::from_secs(300),
+            max_completed_jobs: 1000,
+            script_ttl: Duration::from_secs(600),
+            idempotency_ttl: Duration::from_secs(300),
+            max_stored_scripts: 1000,
+            max_idempotency_keys: 1000,
+            max_server_memory: None,
+            max_header_lines: 100,
+            read_timeout: Duration::from_secs(30),
+            tcp_nodelay: true,
+            socket_send_buffer: None,
+            socket_recv_buffer: None,
+            request_id_header: "X-Request-Id".to_string(),
+            route_prefix: None,
+            spawn_interval: None,
+            max_body_bytes: 10 * 1024 * 1024,
+            read_to_eof: false,
+            strict_json: false,
+            max_script_bytes: None,
+            restricted_builtins: false,
+            sandbox: None,
+            sandbox_image: "python:3-slim".to_string(),
+            sandbox_network: false,
+            packages_ttl: Duration::from_secs(300),
+            pip_retries: 2,
+            pip_retry_backoff: Duration::from_secs(1),
+            nice: None,
+            work_dir: None,
+            pycache_dir: None,
+            no_auto_mkdir: false,
+            isolated_home: false,
+            accept_backoff: Duration::from_millis(100),
+            max_accept_failures: 100,
+            allow_ips: Vec::new(),
+            connection_rate: None,
+            max_connections_per_ip: None,
+            reject_connections: Reject::Forbidden,
+            clean_env: false,
+            env_passthrough: Vec::new(),
+            expose_headers_as_env: Vec::new(),
+            listeners: Vec::new(),
+            max_sessions: None,
+            ws_idle_timeout: Duration::from_secs(300),
+            warmup_modules: Vec::new(),
+            linter: Linter::Ruff,
+            formatter: Formatter::Black,
+            sources: HashMap::new(),
+            shutdown_grace: Duration::from_secs(5),
+            idle_exit: None,
+            one_shot: None,
+            log_bodies: false,
+            log_body_limit: 1024,
+            debug_exec: false,
+            config_file: None,
+            args: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Config, String> {
+        let cli = args.get(1..).unwrap_or_default();
+        let env = env_vars()?;
+        let mut config = Config::default();
+        apply_env(&mut config, &env)?;
+        apply(&mut config, cli, Source::Cli)?;
+        if let Some(path) = config.config_file.clone() {
+            config = Config::default();
+            load_file(&mut config, &path)?;
+            apply_env(&mut config, &env)?;
+            apply(&mut config, cli, Source::Cli)?;
+        }
+        config.args = args.to_vec();
+        if config.max_concurrency == 0 {
+            return Err("--max-concurrency must be at least 1".to_string());
+        }
+        if config.interpreter_template.is_some() && config.restricted_builtins {
+            return Err("--restricted-builtins only works with python, not with --interpreter-template".to_string());
+        }
+        if config.listeners.is_empty() {
+            config.listeners.push(Listen { address: Address::Tcp(config.host.clone(), config.port), require_auth: true });
+        }
+        if config.max_accept_failures == 0 {
+            return Err("--max-accept-failures must be at least 1".to_string());
+        }
+        if config.timeout > config.max_timeout {
+            return Err("--timeout must not exceed --max-timeout".to_string());
+        }
+        if config.session_timeout.unwrap_or(config.timeout) > config.max_session_timeout.unwrap_or(config.max_timeout) {
+            return Err("--session-timeout must not exceed --max-session-timeout (or --max-timeout)".to_string());
+        }
+        Ok(config)
+    }
+
+    // A copy with the settings that can change at runtime taken from `fresh`, and the names of
+    // the changed options: those applied, and those that need a restart
+    pub fn reloaded(&self, fresh: &Config) -> (Config, Vec<String>, Vec<String>) {
+        let mut merged = self.clone();
+        merged.timeout = fresh.timeout;
+        merged.max_timeout = fresh.max_timeout;
+        merged.session_timeout = fresh.session_timeout;
+        merged.max_session_timeout = fresh.max_session_timeout;
+        merged.max_body_bytes = fresh.max_body_bytes;
+        merged.max_script_bytes = fresh.max_script_bytes;
+        merged.max_header_lines = fresh.max_header_lines;
+        merged.max_sessions = fresh.max_sessions;
+        merged.spawn_interval = fresh.spawn_interval;
+        for option in HOT_RELOADABLE {
+            match fresh.sources.get(*option) {
+                Some(source) => merged.sources.insert(option.to_string(), *source),
+                None => merged.sources.remove(*option),
+            };
+        }
+
+        let (current, fresh) = (self.to_json(), fresh.to_json());
+        let (applied, ignored) = fresh["options"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(option, setting)| current["options"][option.as_str()]["value"] != setting["value"])
+            .map(|(option, _)| option.clone())
+            .partition(|option| HOT_RELOADABLE.contains(&option.as_str()));
+        (merged, applied, ignored)
+    }
+
+    // Set on each accepted connection
+    pub fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            nodelay: self.tcp_nodelay,
+            #[cfg(unix)]
+            send_buffer: self.socket_send_buffer,
+            #[cfg(unix)]
+            recv_buffer: self.socket_recv_buffer,
+        }
+    }
+
+    // Every option with its effective value and where that came from, for /admin/config
+    pub fn to_json(&self) -> Value {
+        let listeners: Vec<String> = self
+            .listeners
+            .iter()
+            .map(|listen| if listen.require_auth { listen.address.to_string() } else { format!("{},no-auth", listen.address) })
+            .collect();
+        let headers: Vec<String> = self.headers.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+        let root_headers: Vec<String> = self.root_headers.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+        let values = [
+            ("log-level", json!(self.log_level)),
+            ("log-file", json!(self.log_file)),
+            ("host", json!(self.host)),
+            ("port", json!(self.port)),
+            ("listen", json!(listeners)),
+            ("timeout", json!(self.timeout.as_secs_f64())),
+            ("max-timeout", json!(self.max_timeout.as_secs_f64())),
+            ("session-timeout", json!(self.session_timeout.map(|timeout| timeout.as_secs_f64()))),
+            ("max-session-timeout", json!(self.max_session_timeout.map(|timeout| timeout.as_secs_f64()))),
+            ("python", json!(self.python)),
+            ("interpreter-template", json!(self.interpreter_template.as_ref().map(|template| template.join(" ")))),
+            ("run-as-user", json!(self.run_as_user)),
+            ("header", json!(headers)),
+            ("root-header", json!(root_headers)),
+            ("no-root-headers", json!(self.no_root_headers)),
+            // Only whether it's set
+            ("token", json!(self.token.as_ref().map(|_| "[redacted]"))),
+            ("history-size", json!(self.history_size)),
+            ("max-concurrency", json!(self.max_concurrency)),
+            ("allow-file-exec", json!(self.allow_file_exec)),
+            ("file-exec-root", json!(self.file_exec_root)),
+            ("no-content-on-empty", json!(self.no_content_on_empty)),
+            ("binary-output", json!(self.binary_output.name())),
+            ("on-crash", json!(self.on_crash.name())),
+            ("pretty", json!(self.pretty)),
+            ("job-ttl", json!(self.job_ttl.as_secs_f64())),
+            ("max-completed-jobs", json!(self.max_completed_jobs)),
+            ("script-ttl", json!(self.script_ttl.as_secs_f64())),
+            ("idempotency-ttl", json!(self.idempotency_ttl.as_secs_f64())),
+            ("max-stored-scripts", json!(self.max_stored_scripts)),
+            ("max-idempotency-keys", json!(self.max_idempotency_keys)),
+            ("max-server-memory", json!(self.max_server_memory)),
+            ("max-header-lines", json!(self.max_header_lines)),
+            ("read-timeout", json!(self.read_timeout.as_secs_f64())),
+            ("no-tcp-nodelay", json!(!self.tcp_nodelay)),
+            ("socket-send-buffer", json!(self.socket_send_buffer)),
+            ("socket-recv-buffer", json!(self.socket_recv_buffer)),
+            ("request-id-header", json!(self.request_id_header)),
+            ("route-prefix", json!(self.route_prefix)),
+            ("spawn-rate", json!(self.spawn_interval.map(|interval| 1.0 / interval.as_secs_f64()))),
+            ("max-body-bytes", json!(self.max_body_bytes)),
+            ("read-to-eof", json!(self.read_to_eof)),
+            ("strict-json", json!(self.strict_json)),
+            ("max-script-bytes", json!(self.max_script_bytes)),
+            ("restricted-builtins", json!(self.restricted_builtins)),
+            ("sandbox", json!(self.sandbox.map(|sandbox| match sandbox {
+                Sandbox::Docker => "docker",
+                Sandbox::Namespaces => "namespaces",
+            }))),
+            ("sandbox-network", json!(self.sandbox_network)),
+            ("sandbox-image", json!(self.sandbox_image)),
+            ("packages-ttl", json!(self.packages_ttl.as_secs_f64())),
+            ("pip-retries", json!(self.pip_retries)),
+            ("pip-retry-backoff", json!(self.pip_retry_backoff.as_secs_f64())),
+            ("nice", json!(self.nice)),
+            ("work-dir", json!(self.work_dir)),
+            ("pycache-dir", json!(self.pycache_dir)),
+            ("no-auto-mkdir", json!(self.no_auto_mkdir)),
+            ("isolated-home", json!(self.isolated_home)),
+            ("accept-backoff", json!(self.accept_backoff.as_secs_f64())),
+            ("max-accept-failures", json!(self.max_accept_failures)),
+            ("allow-ip", json!(self.allow_ips.iter().map(Network::to_string).collect::<Vec<_>>())),
+            ("connection-rate", json!(self.connection_rate)),
+            ("max-connections-per-ip", json!(self.max_connections_per_ip)),
+            ("reject-connections", json!(match self.reject_connections {
+                Reject::Forbidden => "403",
+                Reject::Drop => "drop",
+            })),
+            ("clean-env", json!(self.clean_env)),
+            ("env-passthrough", json!(self.env_passthrough)),
+            ("expose-headers-as-env", json!(self.expose_headers_as_env)),
+            ("max-sessions", json!(self.max_sessions)),
+            ("ws-idle-timeout", json!(self.ws_idle_timeout.as_secs_f64())),
+            ("warmup-modules", json!(self.warmup_modules)),
+            ("linter", json!(self.linter.name())),
+            ("formatter", json!(self.formatter.name())),
+            ("log-bodies", json!(self.log_bodies)),
+            ("log-body-limit", json!(self.log_body_limit)),
+            ("debug-exec", json!(self.debug_exec)),
+            ("shutdown-grace", json!(self.shutdown_grace.as_secs_f64())),
+            ("idle-exit", json!(self.idle_exit.map(|idle| idle.as_secs_f64()))),
+            ("config", json!(self.config_file)),
+        ];
+        let options: serde_json::Map<String, Value> = values
+            .into_iter()
+            .map(|(option, value)| {
+                let source = self.sources.get(option).copied().unwrap_or(Source::Default);
+                (option.to_string(), json!({"value": value, "source": source.as_str()}))
+            })
+            .collect();
+        json!({"options": options})
+    }
+
+    // What the root page is sent with: the defaults unless turned off, with --root-header
+    // replacing a default of the same name
+    pub fn root_page_headers(&self) -> Vec<(&str, String)> {
+        let defaults = if self.no_root_headers { &[][..] } else { ROOT_HEADERS };
+        defaults
+            .iter()
+            .filter(|(name, _)| !self.root_headers.iter().any(|(custom, _)| custom.eq_ignore_ascii_case(name)))
+            .map(|(name, value)| (*name, value.to_string()))
+            .chain(self.root_headers.iter().map(|(name, value)| (name.as_str(), value.clone())))
+            .collect()
+    }
+
+    // Per-request timeouts are clamped so clients can't ask for an unbounded wait
+    pub fn effective_timeout(&self, requested: Option<f64>) -> Result<Duration, String> {
+        clamp_timeout(requested, self.timeout, self.max_timeout)
+    }
+
+    // The same for session snippets, which have their own default and bound
+    pub fn effective_session_timeout(&self, requested: Option<f64>) -> Result<Duration, String> {
+        let default = self.session_timeout.unwrap_or(self.timeout);
+        clamp_timeout(requested, default, self.max_session_timeout.unwrap_or(self.max_timeout))
+    }
+}
+
+fn clamp_timeout(requested: Option<f64>, default: Duration, max: Duration) -> Result<Duration, String> {
+    match requested {
+        Some(secs) => seconds(secs)
+            .map(|timeout| timeout.min(max))
+            .ok_or_else(|| "Invalid field `timeout`: must be a positive number of seconds".to_string()),
+        None => Ok(default),
+    }
+}
+
+// The root page only says whether the helper is up, so it shouldn't be served from a cache
+const ROOT_HEADERS: &[(&str, &str)] = &[("Cache-Control", "no-store"), ("X-Content-Type-Options", "nosniff")];
+
+// Also kept from scripts; see Server::prepare
+pub const ENV_PREFIX: &str = "FPB_";
+
+// The options that can be given more than once
+const REPEATABLE: &[&str] = &["listen", "header", "root-header", "allow-ip", "env-passthrough", "expose-headers-as-env"];
+
+// What /admin/reload applies; everything else is fixed at startup
+const HOT_RELOADABLE: &[&str] = &[
+    "timeout",
+    "max-timeout",
+    "session-timeout",
+    "max-session-timeout",
+    "max-body-bytes",
+    "max-script-bytes",
+    "max-header-lines",
+    "max-sessions",
+    "spawn-rate",
+];
+
+fn apply(config: &mut Config, args: &[String], source: Source) -> Result<(), String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => config.log_level = "debug",
+            "--info" => config.log_level = "info",
+            "--error" => config.log_level = "error",
+            "--log-file" => config.log_file = Some(PathBuf::from(value(arg, args.next())?)),
+            "--host" => config.host = value(arg, args.next())?.to_string(),
+            "--port" => {
+                let raw = value(arg, args.next())?;
+                config.port = raw.parse().map_err(|_| format!("--port must be a port number, got {:?}", raw))?;
+            }
+            "--timeout" => config.timeout = parse_seconds(arg, args.next())?,
+            "--max-timeout" => config.max_timeout = parse_seconds(arg, args.next())?,
+            "--session-timeout" => config.session_timeout = Some(parse_seconds(arg, args.next())?),
+            "--max-session-timeout" => config.max_session_timeout = Some(parse_seconds(arg, args.next())?),
+            "--python" => config.python = Some(value(arg, args.next())?.to_string()),
+            "--interpreter-template" => config.interpreter_template = Some(interpreter::parse_template(value(arg, args.next())?)?),
+            "--run-as-user" => config.run_as_user = Some(value(arg, args.next())?.to_string()),
+            "--header" => config.headers.push(http::parse_header(value(arg, args.next())?)?),
+            "--root-header" => config.root_headers.push(http::parse_header(value(arg, args.next())?)?),
+            "--no-root-headers" => config.no_root_headers = true,
+            "--token" => config.token = Some(value(arg, args.next())?.to_string()),
+            "--history-size" => config.history_size = parse_count(arg, args.next())?,
+            "--max-concurrency" => config.max_concurrency = parse_count(arg, args.next())?,
+            "--allow-file-exec" => config.allow_file_exec = true,
+            "--file-exec-root" => config.file_exec_root = Some(PathBuf::from(value(arg, args.next())?)),
+            "--no-content-on-empty" => config.no_content_on_empty = true,
+            "--binary-output" => {
+                config.binary_output = match value(arg, args.next())? {
+                    "base64" => BinaryOutput::Base64,
+                    "replace" => BinaryOutput::Replace,
+                    "error" => BinaryOutput::Error,
+                    other => return Err(format!("--binary-output must be base64, replace or error, got {:?}", other)),
+                }
+            }
+            "--on-crash" => {
+                config.on_crash = match value(arg, args.next())? {
+                    "fail" => OnCrash::Fail,
+                    "retry" => OnCrash::Retry,
+                    other => return Err(format!("--on-crash must be fail or retry, got {:?}", other)),
+                }
+            }
+            "--pretty" => config.pretty = true,
+            "--packages-ttl" => config.packages_ttl = parse_seconds(arg, args.next())?,
+            "--pip-retries" => config.pip_retries = parse_count(arg, args.next())?,
+            "--pip-retry-backoff" => config.pip_retry_backoff = parse_seconds(arg, args.next())?,
+            "--job-ttl" => config.job_ttl = parse_seconds(arg, args.next())?,
+            "--max-completed-jobs" => config.max_completed_jobs = parse_count(arg, args.next())?,
+            "--script-ttl" => config.script_ttl = parse_seconds(arg, args.next())?,
+            "--idempotency-ttl" => config.idempotency_ttl = parse_seconds(arg, args.next())?,
+            "--max-stored-scripts" => config.max_stored_scripts = parse_count(arg, args.next())?.max(1),
+            "--max-idempotency-keys" => config.max_idempotency_keys = parse_count(arg, args.next())?,
+            "--max-server-memory" => config.max_server_memory = Some(parse_count(arg, args.next())?),
+            "--read-timeout" => config.read_timeout = parse_seconds(arg, args.next())?,
+            "--no-tcp-nodelay" => config.tcp_nodelay = false,
+            "--socket-send-buffer" | "--socket-recv-buffer" => {
+                let bytes = parse_count(arg, args.next())?;
+                if cfg!(not(unix)) {
+                    return Err(format!("{} is only supported on Unix", arg));
+                }
+                if bytes == 0 {
+                    return Err(format!("{} must be at least 1", arg));
+                }
+                match arg.as_str() {
+                    "--socket-send-buffer" => config.socket_send_buffer = Some(bytes),
+                    _ => config.socket_recv_buffer = Some(bytes),
+                }
+            }
+            "--max-header-lines" => config.max_header_lines = parse_count(arg, args.next())?,
+            "--request-id-header" => {
+                let name = value(arg, args.next())?;
+                if !http::is_token(name) {
+                    return Err(format!("--request-id-header {:?} is not a valid header name", name));
+                }
+                config.request_id_header = name.to_string();
+            }
+            "--route-prefix" => {
+                let raw = value(arg, args.next())?;
+                if !raw.starts_with('/') || raw.contains(['?', '#']) {
+                    return Err(format!("--route-prefix must be a path starting with /, got {:?}", raw));
+                }
+                let prefix = raw.trim_end_matches('/');
+                config.route_prefix = (!prefix.is_empty()).then(|| prefix.to_string());
+            }
+            "--work-dir" => config.work_dir = Some(PathBuf::from(value(arg, args.next())?)),
+            "--pycache-dir" => config.pycache_dir = Some(PathBuf::from(value(arg, args.next())?)),
+            "--no-auto-mkdir" => config.no_auto_mkdir = true,
+            "--isolated-home" => config.isolated_home = true,
+            "--accept-backoff" => config.accept_backoff = parse_seconds(arg, args.next())?,
+            "--max-accept-failures" => config.max_accept_failures = parse_count(arg, args.next())?,
+            "--allow-ip" => config.allow_ips.push(Network::parse(value(arg, args.next())?)?),
+            "--connection-rate" => {
+                let raw = value(arg, args.next())?;
+                let rate = raw.parse::<f64>().ok().filter(|rate| rate.is_finite() && *rate > 0.0);
+                config.connection_rate = Some(rate.ok_or_else(|| format!("--connection-rate must be a positive number per second, got {:?}", raw))?);
+            }
+            "--max-connections-per-ip" => config.max_connections_per_ip = Some(parse_count(arg, args.next())?),
+            "--reject-connections" => {
+                config.reject_connections = match value(arg, args.next())? {
+                    "403" => Reject::Forbidden,
+                    "drop" => Reject::Drop,
+                    other => return Err(format!("--reject-connections must be 403 or drop, got {:?}", other)),
+                }
+            }
+            "--clean-env" => config.clean_env = true,
+            "--env-passthrough" => config.env_passthrough.push(value(arg, args.next())?.to_string()),
+            "--expose-headers-as-env" => {
+                let name = value(arg, args.next())?;
+                if !http::is_token(name) {
+                    return Err(format!("--expose-headers-as-env must be a header name, got {:?}", name));
+                }
+                // HTTP_PROXY is where HTTP clients look for a proxy to send everything through
+                if name.eq_ignore_ascii_case("proxy") {
+                    return Err("--expose-headers-as-env Proxy would let clients set HTTP_PROXY for scripts".to_string());
+                }
+                config.expose_headers_as_env.push(name.to_string());
+            }
+            "--warmup-modules" => {
+                let modules = value(arg, args.next())?.split(',').map(str::trim).filter(|module| !module.is_empty());
+                config.warmup_modules.extend(modules.map(str::to_string));
+            }
+            "--linter" => {
+                config.linter = match value(arg, args.next())? {
+                    "ruff" => Linter::Ruff,
+                    "flake8" => Linter::Flake8,
+                    other => return Err(format!("--linter must be ruff or flake8, got {:?}", other)),
+                }
+            }
+            "--formatter" => {
+                config.formatter = match value(arg, args.next())? {
+                    "black" => Formatter::Black,
+                    "autopep8" => Formatter::Autopep8,
+                    other => return Err(format!("--formatter must be black or autopep8, got {:?}", other)),
+                }
+            }
+            "--max-sessions" => config.max_sessions = Some(parse_count(arg, args.next())?),
+            "--ws-idle-timeout" => config.ws_idle_timeout = parse_seconds(arg, args.next())?,
+            "--listen" => config.listeners.push(parse_listen(value(arg, args.next())?)?),
+            "--nice" => {
+                let raw = value(arg, args.next())?;
+                let nice = raw.parse::<i32>().ok().filter(|nice| (-20..=19).contains(nice));
+                config.nice = Some(nice.ok_or_else(|| format!("--nice must be between -20 and 19, got {:?}", raw))?);
+            }
+            "--restricted-builtins" => config.restricted_builtins = true,
+            "--sandbox" => {
+                config.sandbox = match value(arg, args.next())? {
+                    "docker" => Some(Sandbox::Docker),
+                    "namespaces" if cfg!(target_os = "linux") => Some(Sandbox::Namespaces),
+                    "namespaces" => return Err("--sandbox namespaces is only supported on Linux".to_string()),
+                    other => return Err(format!("Unknown --sandbox {:?}, expected docker or namespaces", other)),
+                };
+            }
+            "--sandbox-network" => config.sandbox_network = true,
+            "--sandbox-image" => config.sandbox_image = value(arg, args.next())?.to_string(),
+            "--max-body-bytes" => config.max_body_bytes = parse_count(arg, args.next())?,
+            "--read-to-eof" => config.read_to_eof = true,
+            "--strict-json" => config.strict_json = true,
+            "--max-script-bytes" => config.max_script_bytes = Some(parse_count(arg, args.next())?),
+            "--spawn-rate" => {
+                let raw = value(arg, args.next())?;
+                let interval = raw.parse::<f64>().ok().filter(|rate| *rate > 0.0).and_then(|rate| seconds(1.0 / rate));
+                config.spawn_interval = Some(interval.ok_or_else(|| format!("--spawn-rate must be a positive number per second, got {:?}", raw))?);
+            }
+            "--log-bodies" => config.log_bodies = true,
+            "--log-body-limit" => config.log_body_limit = parse_count(arg, args.next())?,
+            "--debug-exec" => config.debug_exec = true,
+            "--shutdown-grace" => config.shutdown_grace = parse_seconds(arg, args.next())?,
+            "--idle-exit" => config.idle_exit = Some(parse_seconds(arg, args.next())?),
+            "--run" | "--exec" => {
+                if config.one_shot.is_some() {
+                    return Err("Only one --run or --exec can be given".to_string());
+                }
+                let value = value(arg, args.next())?;
+                config.one_shot = Some(if arg == "--run" { OneShot::File(PathBuf::from(value)) } else { OneShot::Code(value.to_string()) });
+            }
+            "--config" => config.config_file = Some(PathBuf::from(value(arg, args.next())?)),
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+        let option = match arg.as_str() {
+            "--debug" | "--info" | "--error" => "log-level",
+            flag => flag.trim_start_matches("--"),
+        };
+        config.sources.insert(option.to_string(), source);
+    }
+    Ok(())
+}
+
+// One option per line, written like on the command line without the dashes:
+// "timeout 10", "pretty" or "header X-Frame-Options: DENY". Lines starting with # are skipped.
+fn load_file(config: &mut Config, path: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Can't read --config {:?}: {}", path, e))?;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = match line.split_once(char::is_whitespace) {
+            Some((name, value)) => (name, Some(value.trim())),
+            None => (line, None),
+        };
+        let name = name.trim_start_matches("--");
+        if name == "config" {
+            return Err(format!("{}:{}: a config file can't name another one", path.display(), number + 1));
+        }
+        let args: Vec<String> = std::iter::once(format!("--{}", name)).chain(value.map(str::to_string)).collect();
+        apply(config, &args, Source::File).map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+    }
+    Ok(())
+}
+
+// The FPB_* variables, by name. Only ours have to be UTF-8; the rest of the environment is
+// none of our business.
+fn env_vars() -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (name, value) in std::env::vars_os() {
+        if !name.as_encoded_bytes().starts_with(ENV_PREFIX.as_bytes()) {
+            continue;
+        }
+        let name = name.into_string().map_err(|name| format!("{} is not valid UTF-8", name.to_string_lossy()))?;
+        let value = value.into_string().map_err(|_| format!("{} is not valid UTF-8", name))?;
+        vars.push((name, value));
+    }
+    vars.sort();
+    Ok(vars)
+}
+
+// FPB_MAX_TIMEOUT=30 is --max-timeout 30. Switches like --pretty take 1, true or yes to turn them
+// on, and 0, false, no or nothing to leave them off. Repeatable options take more values as
+// FPB_LISTEN_1, FPB_LISTEN_2 and so on, in that order. Below the command line, above --config.
+fn apply_env(config: &mut Config, vars: &[(String, String)]) -> Result<(), String> {
+    let mut vars: Vec<(String, usize, &String, &String)> = vars
+        .iter()
+        .map(|(name, value)| {
+            let (flag, n) = env_flag(name);
+            (flag, n, name, value)
+        })
+        .collect();
+    vars.sort();
+    for (flag, _, name, value) in vars {
+        // Whatever works without a value is a switch
+        let is_switch = apply(&mut config.clone(), std::slice::from_ref(&flag), Source::Env).is_ok();
+        let args = match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" if is_switch => vec![flag],
+            "" | "0" | "false" | "no" if is_switch => continue,
+            _ if is_switch => return Err(format!("{}: {} is a switch, set it to 1 or 0, got {:?}", name, flag, value)),
+            _ => vec![flag, value.clone()],
+        };
+        apply(config, &args, Source::Env).map_err(|e| format!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+// FPB_ALLOW_IP is --allow-ip, and so is FPB_ALLOW_IP_2, the second of them
+fn env_flag(name: &str) -> (String, usize) {
+    let option = name[ENV_PREFIX.len()..].to_ascii_lowercase().replace('_', "-");
+    if let Some((base, n)) = option.rsplit_once('-') {
+        if let (true, Ok(n)) = (REPEATABLE.contains(&base), n.parse()) {
+            return (format!("--{}", base), n);
+        }
+    }
+    (format!("--{}", option), 0)
+}
+
+fn value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, String> {
+    value.map(|v| v.as_str()).ok_or_else(|| format!("{} requires a value", flag))
+}
+
+fn parse_count(flag: &str, raw: Option<&String>) -> Result<usize, String> {
+    let raw = value(flag, raw)?;
+    raw.parse::<usize>().map_err(|_| format!("{} must be a whole number, got {:?}", flag, raw))
+}
+
+// "host:port" or "unix:/path", optionally followed by ",no-auth"
+fn parse_listen(raw: &str) -> Result<Listen, String> {
+    let (address, require_auth) = match raw.split_once(',') {
+        None => (raw, true),
+        Some((address, "no-auth")) => (address, false),
+        Some((_, option)) => return Err(format!("Unknown --listen option {:?}", option)),
+    };
+    Ok(Listen { address: net::parse_address(address)?, require_auth })
+}
+
+fn parse_seconds(flag: &str, raw: Option<&String>) -> Result<Duration, String> {
+    let raw = value(flag, raw)?;
+    raw.parse::<f64>().ok().and_then(seconds)
+        .ok_or_else(|| format!("{} must be a positive number of seconds, got {:?}", flag, raw))
+}
+
+fn seconds(secs: f64) -> Option<Duration> {
+    if secs > 0.0 {
+        Duration::try_from_secs_f64(secs).ok()
+    } else {
+        None
+    }
+}
@@ -0,0 +1,97 @@
+// src/diff.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::fmt::Write;
+
+// Lines of context around each change, as in diff -u
+const CONTEXT: usize = 3;
+// Past this many line pairs the diff gives up on alignment and replaces the whole text
+const MAX_CELLS: usize = 4_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// A unified diff from `expected` to `actual`, empty when they're the same
+pub fn unified(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.split_inclusive('\n').collect();
+    let new: Vec<&str> = actual.split_inclusive('\n').collect();
+    let ops = edit_script(&old, &new);
+    let changes: Vec<usize> = ops.iter().enumerate().filter(|(_, (op, _))| *op != Op::Equal).map(|(i, _)| i).collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Op index ranges to print, with overlapping context merged into one hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &change in &changes {
+        let (start, end) = (change.saturating_sub(CONTEXT), (change + CONTEXT + 1).min(ops.len()));
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = String::from("--- expected\n+++ actual\n");
+    for (start, end) in hunks {
+        let count = |range: &[(Op, &str)], side: Op| range.iter().filter(|(op, _)| *op == Op::Equal || *op == side).count();
+        let (old_before, new_before) = (count(&ops[..start], Op::Delete), count(&ops[..start], Op::Insert));
+        let (old_len, new_len) = (count(&ops[start..end], Op::Delete), count(&ops[start..end], Op::Insert));
+        // An empty side is numbered by the line before it
+        let old_start = if old_len == 0 { old_before } else { old_before + 1 };
+        let new_start = if new_len == 0 { new_before } else { new_before + 1 };
+        let _ = writeln!(out, "@@ -{},{} +{},{} @@", old_start, old_len, new_start, new_len);
+        for (op, line) in &ops[start..end] {
+            let prefix = match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            };
+            out.push(prefix);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+// Longest common subsequence of lines, walked back into equal/delete/insert steps
+fn edit_script<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    if old.len().saturating_mul(new.len()) > MAX_CELLS {
+        return old.iter().map(|line| (Op::Delete, *line)).chain(new.iter().map(|line| (Op::Insert, *line))).collect();
+    }
+    let width = new.len() + 1;
+    // lcs[i * width + j]: common lines of old[i..] and new[j..]
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((Op::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            // Deletions first, like diff -u
+            ops.push((Op::Delete, old[i]));
+            i += 1;
+        } else {
+            ops.push((Op::Insert, new[j]));
+            j += 1;
+        }
+    }
+    ops
+}
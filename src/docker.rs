@@ -0,0 +1,105 @@
+// src/docker.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use log::{debug, warn};
+use crate::ids;
+
+// Where the run directory shows up inside the container
+const RUN_DIR: &str = "/fpb";
+// A --file-exec-root script, which lives outside the run directory
+const FILE_DIR: &str = "/fpb-file";
+
+// A `docker run` for one script. Killing the docker client doesn't stop the container, so
+// whoever kills it (timeout, cancel) must also remove it.
+pub struct Container {
+    name: String,
+    // Written by docker once the container exists; next to the run directory rather than in
+    // it, where the script could remove it
+    id_file: PathBuf,
+}
+
+// `<program> <args>` inside a fresh container of `image`, without network access. The run
+// directory is mounted writable for by-products like profile stats, the script itself
+// read-only. Paths in `args` are rewritten to where the container sees them.
+pub fn command(image: &str, program: &OsStr, run_dir: &Path, script: &Path, args: Vec<OsString>, env: &HashMap<String, String>) -> (Command, Container) {
+    let name = format!("fpb-{}", ids::random());
+    let mut id_file = run_dir.as_os_str().to_os_string();
+    id_file.push(".cid");
+    let id_file = PathBuf::from(id_file);
+    // docker refuses to overwrite one, say from an earlier attempt at this run
+    let _ = fs::remove_file(&id_file);
+    let mut docker = Command::new("docker");
+    docker.args(["run", "--rm", "-i", "--network", "none", "--name", &name]);
+    docker.arg("--cidfile").arg(&id_file);
+    let mut file_mount = None;
+    let args: Vec<OsString> = args
+        .into_iter()
+        .map(|arg| {
+            let path = Path::new(&arg);
+            if let Ok(relative) = path.strip_prefix(run_dir) {
+                return Path::new(RUN_DIR).join(relative).into_os_string();
+            }
+            if path == script {
+                let inside = Path::new(FILE_DIR).join(script.file_name().unwrap_or_default());
+                file_mount = Some(inside.clone());
+                return inside.into_os_string();
+            }
+            arg
+        })
+        .collect();
+    docker.arg("-v").arg(mount(run_dir, Path::new(RUN_DIR), false));
+    match &file_mount {
+        Some(inside) => docker.arg("-v").arg(mount(script, inside, true)),
+        None => docker.arg("-v").arg(mount(script, &Path::new(RUN_DIR).join("script.py"), true)),
+    };
+    docker.args(["-w", RUN_DIR]);
+    for (key, value) in env {
+        // Passed by name so values don't show up in `ps`
+        docker.arg("-e").arg(key);
+        docker.env(key, value);
+    }
+    docker.arg(image).arg(program).args(args);
+    debug!("Running in container {}", name);
+    (docker, Container { name, id_file })
+}
+
+fn mount(host: &Path, inside: &Path, read_only: bool) -> OsString {
+    let mut spec = host.as_os_str().to_os_string();
+    spec.push(":");
+    spec.push(inside);
+    if read_only {
+        spec.push(":ro");
+    }
+    spec
+}
+
+impl Container {
+    // docker exits 125 when it can't create the container, but so may the script. Only the
+    // first leaves no container id behind.
+    pub fn was_created(&self) -> bool {
+        self.id_file.exists()
+    }
+
+    // For a container whose client was killed or never got going
+    pub fn remove(&self) {
+        let removed = Command::new("docker")
+            .args(["rm", "-f", &self.name])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if let Err(e) = removed {
+            warn!("Failed to remove container {}: {}", self.name, e);
+        }
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.id_file);
+    }
+}
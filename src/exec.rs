@@ -0,0 +1,343 @@
+// src/exec.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::debug;
+
+// Process groups of the children still running, so shutdown can take them down
+static GROUPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+// How long past the deadline output is still read, once the child is gone
+const DRAIN_GRACE: Duration = Duration::from_millis(200);
+
+pub struct Outcome {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    // None when the child was killed, at the timeout or on cancel
+    pub status: Option<ExitStatus>,
+    pub cancelled: bool,
+    // Peak resident set size, where the platform reports it for the reaped child
+    pub max_rss_kb: Option<u64>,
+}
+
+impl Outcome {
+    pub fn timed_out(&self) -> bool {
+        self.status.is_none() && !self.cancelled
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    // Lines written to fd 3, when run_streaming opens it; not kept in the Outcome
+    Progress,
+}
+
+// The program and its args as `command` will spawn them, for --debug-exec
+pub fn argv(command: &Command) -> Vec<String> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+// Setting `cancel` kills the child, or keeps it from being spawned at all
+pub fn run(command: Command, stdin: Option<Vec<u8>>, timeout: Duration, cancel: Option<&AtomicBool>) -> io::Result<Outcome> {
+    run_streaming(command, stdin, timeout, cancel, false, false, &mut |_, _| {})
+}
+
+// Like run, but also hands each line of output to `on_output` as soon as it's read.
+// With `merge_output` stderr goes down the same pipe as stdout, so the two keep their
+// relative order, and everything ends up in Outcome::stdout.
+pub fn run_streaming(
+    mut command: Command,
+    stdin: Option<Vec<u8>>,
+    timeout: Duration,
+    cancel: Option<&AtomicBool>,
+    merge_output: bool,
+    // Give the child a pipe on fd 3 for Stream::Progress; Unix only
+    progress: bool,
+    on_output: &mut dyn FnMut(Stream, &[u8]),
+) -> io::Result<Outcome> {
+    let cancelled = || cancel.is_some_and(|cancel| cancel.load(Ordering::SeqCst));
+    if cancelled() {
+        return Ok(Outcome { stdout: Vec::new(), stderr: Vec::new(), status: None, cancelled: true, max_rss_kb: None });
+    }
+    command.stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() });
+    let merged = if merge_output {
+        let (reader, writer) = io::pipe()?;
+        command.stdout(writer.try_clone()?).stderr(writer);
+        Some(reader)
+    } else {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        None
+    };
+    #[cfg(unix)]
+    {
+        // Own process group, so a timeout also takes down anything the script spawned
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(unix)]
+    let progress = if progress { Some(progress_pipe(&mut command)?) } else { None };
+    // Scripts just don't get an fd 3 elsewhere
+    #[cfg(not(unix))]
+    let progress: Option<io::PipeReader> = {
+        let _ = progress;
+        None
+    };
+    let mut child = command.spawn()?;
+    // Lets go of our copies of a merged pipe's write end, or it would never see EOF
+    drop(command);
+    track(&child);
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // Feed stdin from a separate thread so a full stdout pipe can't deadlock us
+        thread::spawn(move || {
+            if let Err(e) = pipe.write_all(&input) {
+                debug!("Failed to write stdin: {}", e);
+            }
+        });
+    }
+    let (sender, output) = mpsc::channel();
+    read_in_background(progress, Stream::Progress, sender.clone());
+    if merged.is_some() {
+        read_in_background(merged, Stream::Stdout, sender);
+    } else {
+        read_in_background(child.stdout.take(), Stream::Stdout, sender.clone());
+        read_in_background(child.stderr.take(), Stream::Stderr, sender);
+    }
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut collect = |(stream, line): (Stream, Vec<u8>)| {
+        on_output(stream, &line);
+        match stream {
+            Stream::Stdout => stdout.extend(line),
+            Stream::Stderr => stderr.extend(line),
+            Stream::Progress => {}
+        }
+    };
+
+    let deadline = Instant::now() + timeout;
+    let (status, was_cancelled, max_rss_kb) = loop {
+        if let Some((status, max_rss_kb)) = try_wait(&mut child)? {
+            break (Some(status), false, max_rss_kb);
+        }
+        if cancelled() {
+            debug!("Killing cancelled python");
+            kill(&mut child);
+            break (None, true, None);
+        }
+        if Instant::now() >= deadline {
+            debug!("Killing python after {:?}", timeout);
+            kill(&mut child);
+            break (None, false, None);
+        }
+        // Waiting for output doubles as the polling interval
+        if let Ok(line) = output.recv_timeout(Duration::from_millis(10)) {
+            collect(line);
+        }
+    };
+    untrack(&child);
+    // The rest, up to the point every pipe closes. Something that left the group can still hold
+    // them, so no later than the deadline, with a moment to read what a kill left in the pipes.
+    let drain_until = deadline.max(Instant::now()) + DRAIN_GRACE;
+    loop {
+        match output.recv_timeout(drain_until.saturating_duration_since(Instant::now())) {
+            Ok(line) => collect(line),
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                debug!("Stopped reading output still held open past the deadline");
+                break;
+            }
+        }
+    }
+
+    Ok(Outcome {
+        stdout,
+        stderr,
+        status,
+        cancelled: was_cancelled,
+        max_rss_kb,
+    })
+}
+
+// The read end of a pipe that is fd 3 in the child. The write end goes with the Command, so the
+// parent's copy closes once that's dropped after spawning.
+#[cfg(unix)]
+fn progress_pipe(command: &mut Command) -> io::Result<io::PipeReader> {
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+    const PROGRESS_FD: i32 = 3;
+    let (reader, writer) = io::pipe()?;
+    let writer = OwnedFd::from(writer);
+    let hook = move || {
+        let fd = writer.as_raw_fd();
+        // dup2 onto itself would leave close-on-exec set
+        let moved = if fd == PROGRESS_FD {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, 0) }
+        } else {
+            unsafe { libc::dup2(fd, PROGRESS_FD) }
+        };
+        if moved == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    };
+    // Only async-signal-safe calls in there, as it runs between fork and exec
+    unsafe { command.pre_exec(hook) };
+    Ok(reader)
+}
+
+// The signal that killed the child, if one did
+#[cfg(unix)]
+pub fn signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+pub fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+// Signals the child dies of when it's broken itself (a bad C extension, say), as opposed to being
+// killed from outside
+#[cfg(unix)]
+pub fn crash_signal(status: ExitStatus) -> Option<i32> {
+    signal(status).filter(|signal| [libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE, libc::SIGABRT].contains(signal))
+}
+
+#[cfg(not(unix))]
+pub fn crash_signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+// Child::try_wait, but reaping with wait4 so the child's resource usage comes along.
+// Once this returns Some the pid is gone; the Child must not be waited on or killed again.
+// Anything the script left running in its group would hold the pipes open, and the slot with
+// them, past the timeout, so the group is killed first: while the child is still a zombie its
+// pid, and with it the group id, can't be handed to another child.
+#[cfg(unix)]
+fn try_wait(child: &mut Child) -> io::Result<Option<(ExitStatus, Option<u64>)>> {
+    use std::os::unix::process::ExitStatusExt;
+    let pid = child.id() as libc::pid_t;
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let peeked = unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT | libc::WNOHANG) };
+    if peeked == -1 {
+        let e = io::Error::last_os_error();
+        // Interrupted by a signal; the next poll tries again
+        return if e.kind() == io::ErrorKind::Interrupted { Ok(None) } else { Err(e) };
+    }
+    if unsafe { info.si_pid() } == 0 {
+        return Ok(None);
+    }
+    unsafe {
+        libc::kill(-pid, libc::SIGKILL);
+    }
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let reaped = loop {
+        match unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            reaped => break reaped,
+        }
+    };
+    match reaped {
+        -1 => Err(io::Error::last_os_error()),
+        _ => {
+            // Kilobytes on Linux and the BSDs, bytes on macOS
+            let max_rss = usage.ru_maxrss as u64;
+            let max_rss_kb = if cfg!(target_os = "macos") { max_rss / 1024 } else { max_rss };
+            Ok(Some((ExitStatus::from_raw(status), Some(max_rss_kb))))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn try_wait(child: &mut Child) -> io::Result<Option<(ExitStatus, Option<u64>)>> {
+    Ok(child.try_wait()?.map(|status| (status, None)))
+}
+
+// Sends the pipe's output line by line, the last line possibly without its newline
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>, stream: Stream, sender: Sender<(Stream, Vec<u8>)>) {
+    let Some(pipe) = pipe else { return };
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        loop {
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if sender.send((stream, line)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Children in their own process group (see run_streaming) register here while they run
+pub fn track(child: &Child) {
+    GROUPS.lock().unwrap().insert(child.id());
+}
+
+pub fn untrack(child: &Child) {
+    GROUPS.lock().unwrap().remove(&child.id());
+}
+
+// SIGTERM to every tracked group, then SIGKILL to whatever hasn't gone within `grace`
+#[cfg(unix)]
+pub fn terminate_all(grace: Duration) {
+    let signal_all = |signal| {
+        let groups = GROUPS.lock().unwrap().clone();
+        for &group in &groups {
+            unsafe {
+                libc::kill(-(group as libc::pid_t), signal);
+            }
+        }
+        groups.len()
+    };
+    let count = signal_all(libc::SIGTERM);
+    if count == 0 {
+        return;
+    }
+    debug!("Sent SIGTERM to {} process groups", count);
+    // Their runs untrack them as they're reaped
+    let deadline = Instant::now() + grace;
+    while !GROUPS.lock().unwrap().is_empty() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    let count = signal_all(libc::SIGKILL);
+    if count > 0 {
+        debug!("Sent SIGKILL to {} process groups that outlived the grace period", count);
+    }
+}
+
+// Kills the child along with anything it spawned
+pub fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(windows)]
+    {
+        // No process groups on Windows; taskkill /T walks the child's process tree instead
+        let _ = Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &child.id().to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
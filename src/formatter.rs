@@ -0,0 +1,57 @@
+// src/formatter.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::process::Command;
+use std::time::Duration;
+use log::debug;
+use crate::exec;
+
+// What POST /api/format runs, as `python -m <module>` reading the code from stdin
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Formatter {
+    Black,
+    Autopep8,
+}
+
+pub enum Error {
+    // The formatter module isn't installed for this python
+    Missing(String),
+    // It couldn't make sense of the code, e.g. a syntax error
+    Invalid(String),
+    Failed(String),
+}
+
+impl Formatter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Formatter::Black => "black",
+            Formatter::Autopep8 => "autopep8",
+        }
+    }
+
+    fn args(&self) -> &'static [&'static str] {
+        match self {
+            Formatter::Black => &["--quiet", "-"],
+            Formatter::Autopep8 => &["-"],
+        }
+    }
+}
+
+// The reformatted code
+pub fn run(formatter: Formatter, mut python: Command, code: &str, timeout: Duration) -> Result<String, Error> {
+    debug!("Running {}", formatter.name());
+    python.args(["-m", formatter.name()]).args(formatter.args());
+    let out = exec::run(python, Some(code.as_bytes().to_vec()), timeout, None).map_err(|e| Error::Failed(format!("Failed to run python: {}", e)))?;
+    if out.timed_out() {
+        return Err(Error::Failed(format!("{} took longer than {:?}", formatter.name(), timeout)));
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    match out.status.and_then(|status| status.code()) {
+        Some(0) => Ok(String::from_utf8_lossy(&out.stdout).to_string()),
+        _ if stderr.contains(&format!("No module named {}", formatter.name())) => {
+            Err(Error::Missing(format!("{} isn't installed for this python; pip install {}", formatter.name(), formatter.name())))
+        }
+        // What black exits with for code it can't parse
+        Some(123) if formatter == Formatter::Black => Err(Error::Invalid(stderr)),
+        _ => Err(Error::Failed(format!("{} failed: {}", formatter.name(), stderr))),
+    }
+}
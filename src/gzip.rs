@@ -0,0 +1,276 @@
+// src/gzip.rs for the Rust helper "FlurionsPythonBindings"
+
+// Just enough of RFC 1952 (gzip) and RFC 1951 (deflate) to unpack request bodies
+
+pub enum Error {
+    Malformed(&'static str),
+    // The output would exceed the caller's limit
+    TooLarge,
+}
+
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    let mut rest = data;
+    // Concatenated members decompress to the concatenation of their contents
+    loop {
+        let start = out.len();
+        let body = skip_header(rest)?;
+        let mut bits = Bits { data: body, pos: 0, bit: 0 };
+        inflate(&mut bits, &mut out, limit)?;
+        let trailer = body.get(bits.byte_end()..bits.byte_end() + 8).ok_or(Error::Malformed("truncated gzip trailer"))?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err(Error::Malformed("gzip checksum mismatch"));
+        }
+        rest = &body[bits.byte_end() + 8..];
+        if rest.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+fn skip_header(data: &[u8]) -> Result<&[u8], Error> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    let truncated = Error::Malformed("truncated gzip header");
+    if data.len() < 10 {
+        return Err(truncated);
+    }
+    if data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+        return Err(Error::Malformed("not gzip data"));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or(Error::Malformed("truncated gzip header"))?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            // Zero-terminated
+            let len = data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0)).ok_or(Error::Malformed("truncated gzip header"))?;
+            pos += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    data.get(pos..).ok_or(truncated)
+}
+
+// LSB-first bit reader over the deflate stream
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<u32, Error> {
+        let byte = *self.data.get(self.pos).ok_or(Error::Malformed("truncated deflate stream"))?;
+        let value = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(value as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, Error> {
+        let mut value = 0;
+        for i in 0..count {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    // First byte after the stream
+    fn byte_end(&self) -> usize {
+        self.pos + usize::from(self.bit != 0)
+    }
+}
+
+// Canonical Huffman code, decoded one bit at a time
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bit()? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied().ok_or(Error::Malformed("invalid Huffman code"));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Malformed("invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// Order in which a dynamic block lists the code length code's lengths
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn inflate(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<(), Error> {
+    loop {
+        let last = bits.bit()? == 1;
+        match bits.bits(2)? {
+            0 => stored(bits, out, limit)?,
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                codes(bits, out, limit, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(bits)?;
+                codes(bits, out, limit, &literals, &distances)?;
+            }
+            _ => return Err(Error::Malformed("invalid deflate block type")),
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<(), Error> {
+    bits.align();
+    let header = bits.data.get(bits.pos..bits.pos + 4).ok_or(Error::Malformed("truncated stored block"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    if len != !u16::from_le_bytes([header[2], header[3]]) {
+        return Err(Error::Malformed("corrupt stored block length"));
+    }
+    let start = bits.pos + 4;
+    let block = bits.data.get(start..start + len as usize).ok_or(Error::Malformed("truncated stored block"))?;
+    if out.len() + block.len() > limit {
+        return Err(Error::TooLarge);
+    }
+    out.extend_from_slice(block);
+    bits.pos = start + len as usize;
+    Ok(())
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman), Error> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(Error::Malformed("too many deflate codes"));
+    }
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or(Error::Malformed("repeat with no previous length"))?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > literal_count + distance_count {
+            return Err(Error::Malformed("too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err(Error::Malformed("no end-of-block code"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, limit: usize, literals: &Huffman, distances: &Huffman) -> Result<(), Error> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => {
+                if out.len() >= limit {
+                    return Err(Error::TooLarge);
+                }
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(Error::Malformed("invalid length code"));
+                }
+                let len = LENGTH_BASE[index] as usize + bits.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distances.decode(bits)? as usize;
+                if index >= DIST_BASE.len() {
+                    return Err(Error::Malformed("invalid distance code"));
+                }
+                let distance = DIST_BASE[index] as usize + bits.bits(DIST_EXTRA[index] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(Error::Malformed("distance reaches before the start"));
+                }
+                if out.len() + len > limit {
+                    return Err(Error::TooLarge);
+                }
+                // Byte by byte, since the copy may overlap what it's producing
+                for _ in 0..len {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
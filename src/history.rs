@@ -0,0 +1,114 @@
+// src/history.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde_json::{json, Value};
+use crate::memory;
+
+const MAX_COMMAND_CHARS: usize = 200;
+
+struct Entry {
+    timestamp: u64,
+    command: String,
+    label: Option<String>,
+    script_sha256: Option<String>,
+    status: u32,
+    duration: Duration,
+    // For --max-server-memory
+    at: Instant,
+    size: usize,
+}
+
+// What else an entry can say about a run, when the run has it
+#[derive(Default)]
+pub struct Tags<'a> {
+    pub label: Option<&'a str>,
+    pub script_sha256: Option<&'a str>,
+}
+
+// Ring buffer of the most recent executions; nothing is kept when capacity is 0
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn record(&mut self, command: &str, tags: Tags, status: u32, duration: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let command = truncate(command, MAX_COMMAND_CHARS);
+        let size = memory::estimate(&[&command, tags.label.unwrap_or_default(), tags.script_sha256.unwrap_or_default()]);
+        self.entries.push_back(Entry {
+            timestamp,
+            command,
+            label: tags.label.map(str::to_string),
+            script_sha256: tags.script_sha256.map(str::to_string),
+            status,
+            duration,
+            at: Instant::now(),
+            size,
+        });
+    }
+
+    // How many entries there were
+    pub fn clear(&mut self) -> usize {
+        let cleared = self.entries.len();
+        self.entries.clear();
+        cleared
+    }
+
+    // Newest first
+    pub fn to_json(&self) -> Value {
+        let entries: Vec<Value> = self.entries.iter().rev().map(|entry| {
+            let mut value = json!({
+                "timestamp": entry.timestamp,
+                "command": entry.command,
+                "status": entry.status,
+                "duration_ms": entry.duration.as_millis() as u64,
+            });
+            if let Some(label) = &entry.label {
+                value["label"] = json!(label);
+            }
+            if let Some(hash) = &entry.script_sha256 {
+                value["script_sha256"] = json!(hash);
+            }
+            value
+        }).collect();
+        json!({ "capacity": self.capacity, "entries": entries })
+    }
+}
+
+impl memory::Store for Mutex<History> {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn bytes(&self) -> usize {
+        self.lock().unwrap().entries.iter().map(|entry| entry.size).sum()
+    }
+
+    fn oldest(&self) -> Option<Instant> {
+        self.lock().unwrap().entries.front().map(|entry| entry.at)
+    }
+
+    fn evict_oldest(&self) -> usize {
+        self.lock().unwrap().entries.pop_front().map_or(0, |entry| entry.size)
+    }
+}
+
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
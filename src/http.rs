@@ -0,0 +1,452 @@
+// src/http.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::io::{self, Write};
+use crate::net::Socket;
+use log::{debug, error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Trace,
+    Connect,
+}
+
+impl Method {
+    fn parse(s: &str) -> Option<Method> {
+        match s {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            "OPTIONS" => Some(Method::Options),
+            "PATCH" => Some(Method::Patch),
+            "TRACE" => Some(Method::Trace),
+            "CONNECT" => Some(Method::Connect),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    fn parse(s: &str) -> Option<Version> {
+        match s {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            _ => None,
+        }
+    }
+}
+
+// Parses "METHOD /target HTTP/x.y". Components must be separated by exactly one space.
+pub fn parse_request_line(line: &str) -> Option<(Method, String, Version)> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let mut parts = line.split(' ');
+    let method = Method::parse(parts.next()?)?;
+    let target = parts.next()?;
+    let version = Version::parse(parts.next()?)?;
+    if parts.next().is_some() || !target.starts_with('/') {
+        return None;
+    }
+    Some((method, target.to_string(), version))
+}
+
+// "/api/interpreter?pretty=1" -> ("/api/interpreter", [("pretty", "1")])
+pub fn split_target(target: &str) -> (&str, Vec<(String, String)>) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect();
+    (path, params)
+}
+
+// `attachment; filename="..."`, with an RFC 6266 filename* as well for names that aren't plain
+// ASCII; older clients get the name with those characters replaced
+pub fn attachment(filename: &str) -> String {
+    if filename.is_ascii() {
+        return format!("attachment; filename=\"{}\"", filename);
+    }
+    let fallback: String = filename.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, percent_encode(filename))
+}
+
+fn percent_encode(raw: &str) -> String {
+    raw.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            // A stray % is kept as-is
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// Flags like ?pretty, ?pretty=1 or ?pretty=true; None when absent
+pub fn flag(params: &[(String, String)], name: &str) -> Option<bool> {
+    params
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| !matches!(value.as_str(), "0" | "false" | "no"))
+}
+
+pub struct Request {
+    // Names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    // The Content-Type without its parameters, lowercased
+    pub fn media_type(&self) -> Option<String> {
+        let content_type = self.header("content-type")?;
+        Some(content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+    }
+
+    // Picks from `offered` by the Accept header's q-values, the first offer when there's no header.
+    // Ties go to the more specific range, then to whichever the client listed first.
+    // None when the client accepts none of them.
+    pub fn negotiate(&self, offered: &[&'static str]) -> Option<&'static str> {
+        let Some(accept) = self.header("accept") else {
+            return offered.first().copied();
+        };
+        let ranges: Vec<(String, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let media_range = params.next()?.trim().to_ascii_lowercase();
+                let q = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!media_range.is_empty()).then_some((media_range, q))
+            })
+            .collect();
+        let mut best: Option<(&'static str, f32, u8, usize)> = None;
+        for &media_type in offered {
+            let kind = media_type.split('/').next().unwrap_or_default();
+            // Most specific matching range wins for each offer
+            let matched = ranges
+                .iter()
+                .enumerate()
+                .filter_map(|(position, (range, q))| {
+                    let specificity = match range.as_str() {
+                        "*/*" => 0,
+                        range if range == media_type => 2,
+                        range if range.strip_suffix("/*") == Some(kind) => 1,
+                        _ => return None,
+                    };
+                    Some((*q, specificity, position))
+                })
+                .max_by_key(|&(_, specificity, _)| specificity);
+            let Some((q, specificity, position)) = matched else { continue };
+            if q <= 0.0 {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((_, best_q, best_specificity, best_position)) => {
+                    q > best_q || (q == best_q && (specificity > best_specificity || (specificity == best_specificity && position < best_position)))
+                }
+            };
+            if better {
+                best = Some((media_type, q, specificity, position));
+            }
+        }
+        best.map(|(media_type, ..)| media_type)
+    }
+}
+
+// An accepted stream plus the headers every response on it carries
+pub struct Connection {
+    inner: Socket,
+    headers: Vec<(String, String)>,
+    pub pretty_json: bool,
+}
+
+impl Connection {
+    pub fn new(inner: Socket, headers: Vec<(String, String)>) -> Connection {
+        Connection { inner, headers, pretty_json: false }
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.inner
+    }
+
+    // For writing past HTTP, after switch_protocols
+    pub fn socket_mut(&mut self) -> &mut Socket {
+        &mut self.inner
+    }
+
+    // Adds a header to every response sent from here on
+    pub fn add_header(&mut self, name: &str, value: String) {
+        self.headers.push((name.to_string(), value));
+    }
+}
+
+// Whether this is a valid header name
+pub fn is_token(name: &str) -> bool {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    !name.is_empty() && name.chars().all(is_tchar)
+}
+
+// Parses a "Name: Value" header given on the command line
+pub fn parse_header(raw: &str) -> Result<(String, String), String> {
+    let (name, value) = raw.split_once(':').ok_or_else(|| format!("Header {:?} must look like \"Name: Value\"", raw))?;
+    let name = name.trim();
+    let value = value.trim();
+    if !is_token(name) {
+        return Err(format!("Header {:?} has an invalid name", raw));
+    }
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!("Header {:?} has an invalid value", raw));
+    }
+    // Framing headers are ours to set
+    if name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("content-type") || name.eq_ignore_ascii_case("transfer-encoding") {
+        return Err(format!("Header {} can't be overridden", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+pub fn reason_phrase(status: u32) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        507 => "Insufficient Storage",
+        // The reason phrase is optional, so an unlisted code just goes without one
+        _ => "",
+    }
+}
+
+pub fn send_response(stream: &mut Connection, status: u32, body: &str) -> io::Result<()> {
+    send_response_with_headers(stream, status, body, &[])
+}
+
+pub fn send_response_with_headers(stream: &mut Connection, status: u32, body: &str, extra_headers: &[(&str, String)]) -> io::Result<()> {
+    let content_type = if status == 200 && body.contains("<!DOCTYPE html") {
+        "text/html"
+    } else {
+        "text/plain"
+    };
+    write_response(stream, status, content_type, body, extra_headers)
+}
+
+pub fn send_json(stream: &mut Connection, status: u32, value: &serde_json::Value) -> io::Result<()> {
+    send_json_with_headers(stream, status, value, &[])
+}
+
+pub fn send_json_with_headers(stream: &mut Connection, status: u32, value: &serde_json::Value, extra_headers: &[(&str, String)]) -> io::Result<()> {
+    let body = if stream.pretty_json {
+        serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+    } else {
+        value.to_string()
+    };
+    write_response(stream, status, "application/json", &body, extra_headers)
+}
+
+// Ends HTTP on this connection; what follows is the upgraded protocol's
+pub fn switch_protocols(stream: &mut Connection, extra_headers: &[(&str, String)]) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 101 {}\r\n", reason_phrase(101));
+    for (name, value) in stream.headers.iter().map(|(n, v)| (n.as_str(), v)).chain(extra_headers.iter().map(|(n, v)| (*n, v))) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.inner.write_all(head.as_bytes())
+}
+
+// Starts a response of unknown length; follow with write_chunk calls and finish_chunked
+pub fn start_chunked(stream: &mut Connection, status: u32, content_type: &str, extra_headers: &[(&str, String)]) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\nTransfer-Encoding: chunked\r\nContent-Type: {}\r\n", status, reason_phrase(status), content_type);
+    for (name, value) in stream.headers.iter().map(|(n, v)| (n.as_str(), v)).chain(extra_headers.iter().map(|(n, v)| (*n, v))) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    if let Err(e) = stream.inner.write_all(head.as_bytes()) {
+        error!("Failed to send chunked response head: {}", e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+pub fn write_chunk(stream: &mut Connection, data: &[u8]) -> io::Result<()> {
+    // An empty chunk would end the body
+    if data.is_empty() {
+        return Ok(());
+    }
+    let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    stream.inner.write_all(&chunk)
+}
+
+pub fn finish_chunked(stream: &mut Connection) -> io::Result<()> {
+    stream.inner.write_all(b"0\r\n\r\n")?;
+    debug!("Finished chunked response");
+    Ok(())
+}
+
+fn write_response(stream: &mut Connection, status: u32, content_type: &str, body: &str, extra_headers: &[(&str, String)]) -> io::Result<()> {
+    let status_line = format!("HTTP/1.1 {} {}\r\n", status, reason_phrase(status));
+    // A 204 carries no body, and no Content-Length or Content-Type either
+    let (content_length, mut headers, body) = if status == 204 {
+        (String::new(), String::new(), "")
+    } else {
+        (format!("Content-Length: {}\r\n", body.len()), format!("Content-Type: {}\r\n", content_type), body)
+    };
+    for (name, value) in &stream.headers {
+        headers.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    for (name, value) in extra_headers {
+        headers.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    headers.push_str("\r\n");
+
+    if let Err(e) = stream.inner.write_all(status_line.as_bytes()) {
+        error!("Failed to send status line: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = stream.inner.write_all(content_length.as_bytes()) {
+        error!("Failed to send content length: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = stream.inner.write_all(headers.as_bytes()) {
+        error!("Failed to send headers: {}", e);
+        return Err(e);
+    }
+    if let Err(e) = stream.inner.write_all(body.as_bytes()) {
+        error!("Failed to send body: {}", e);
+        return Err(e);
+    }
+    debug!("Sent response with status: {}", status);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_lines() {
+        assert_eq!(parse_request_line("GET /api/ping HTTP/1.1\r\n"), Some((Method::Get, "/api/ping".to_string(), Version::Http11)));
+        assert_eq!(parse_request_line("POST /api/interpreter?pretty=1 HTTP/1.0"), Some((Method::Post, "/api/interpreter?pretty=1".to_string(), Version::Http10)));
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        for line in [
+            "",
+            "\r\n",
+            "GET /api/ping",
+            "GET /api/ping \r\n",
+            "GET  /api/ping HTTP/1.1",
+            "GET /api/ping  HTTP/1.1",
+            " GET /api/ping HTTP/1.1",
+            "GET /api/ping HTTP/1.1 ",
+            "GET /api/ping HTTP/2.0",
+            "BREW /api/ping HTTP/1.1",
+            "GET api/ping HTTP/1.1",
+        ] {
+            assert_eq!(parse_request_line(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn knows_the_reason_phrase_of_every_status_we_send() {
+        for (status, phrase) in [
+            (101, "Switching Protocols"),
+            (200, "OK"),
+            (201, "Created"),
+            (202, "Accepted"),
+            (204, "No Content"),
+            (400, "Bad Request"),
+            (401, "Unauthorized"),
+            (403, "Forbidden"),
+            (404, "Not Found"),
+            (405, "Method Not Allowed"),
+            (406, "Not Acceptable"),
+            (408, "Request Timeout"),
+            (409, "Conflict"),
+            (410, "Gone"),
+            (411, "Length Required"),
+            (413, "Payload Too Large"),
+            (415, "Unsupported Media Type"),
+            (422, "Unprocessable Content"),
+            (426, "Upgrade Required"),
+            (429, "Too Many Requests"),
+            (431, "Request Header Fields Too Large"),
+            (500, "Internal Server Error"),
+            (501, "Not Implemented"),
+            (503, "Service Unavailable"),
+            (507, "Insufficient Storage"),
+        ] {
+            assert_eq!(reason_phrase(status), phrase);
+        }
+        for status in [100, 302, 418, 499, 502, 599] {
+            assert_eq!(reason_phrase(status), "", "{}", status);
+        }
+    }
+}
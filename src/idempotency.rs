@@ -0,0 +1,140 @@
+// src/idempotency.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use crate::memory;
+
+// Responses by Idempotency-Key, so a client retrying after a network error gets the first
+// run's result back instead of running the script again. Kept for `ttl`, at most `max` at once.
+pub struct Responses {
+    entries: Mutex<HashMap<String, Entry>>,
+    ttl: Duration,
+    max: usize,
+}
+
+struct Entry {
+    // Of the request that took the key; a different request under the same key is refused
+    fingerprint: u64,
+    // None while that request is still running
+    response: Option<(Response, Instant)>,
+    size: usize,
+}
+
+#[derive(Clone)]
+pub struct Response {
+    pub status: u32,
+    pub body: Body,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+#[derive(Clone)]
+pub enum Body {
+    Json(Value),
+    Text(String),
+}
+
+pub enum Lookup<'a> {
+    // First time for this key; finish the reservation with the response
+    New(Reservation<'a>),
+    Replay(Response),
+    InProgress,
+    // The key was used for a different request
+    Mismatch,
+    Full,
+}
+
+// Gives the key up again if dropped without finish, e.g. when the request is rejected
+pub struct Reservation<'a> {
+    responses: &'a Responses,
+    key: String,
+    finished: bool,
+}
+
+// What a request is compared by when its key comes up again
+pub fn fingerprint(parts: &[&str]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    parts.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Responses {
+    pub fn new(ttl: Duration, max: usize) -> Responses {
+        Responses { entries: Mutex::new(HashMap::new()), ttl, max }
+    }
+
+    pub fn lookup(&self, key: &str, fingerprint: u64) -> Lookup<'_> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.response.as_ref().is_none_or(|(_, at)| at.elapsed() < self.ttl));
+        match entries.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Lookup::Mismatch,
+            Some(Entry { response: Some((response, _)), .. }) => Lookup::Replay(response.clone()),
+            Some(_) => Lookup::InProgress,
+            None if entries.len() >= self.max => Lookup::Full,
+            None => {
+                entries.insert(key.to_string(), Entry { fingerprint, response: None, size: memory::estimate(&[key]) });
+                Lookup::New(Reservation { responses: self, key: key.to_string(), finished: false })
+            }
+        }
+    }
+
+    // Drops every finished response; keys of requests still running stay taken
+    pub fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.response.is_none());
+        before - entries.len()
+    }
+}
+
+impl Response {
+    fn size(&self) -> usize {
+        let body = match &self.body {
+            Body::Json(value) => value.to_string().len(),
+            Body::Text(text) => text.len(),
+        };
+        body + self.headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>()
+    }
+}
+
+// Only finished responses go; a request still running keeps its key
+impl memory::Store for Responses {
+    fn name(&self) -> &'static str {
+        "idempotency"
+    }
+
+    fn bytes(&self) -> usize {
+        self.entries.lock().unwrap().values().map(|entry| entry.size).sum()
+    }
+
+    fn oldest(&self) -> Option<Instant> {
+        self.entries.lock().unwrap().values().filter_map(|entry| Some(entry.response.as_ref()?.1)).min()
+    }
+
+    fn evict_oldest(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let oldest = entries.iter().filter_map(|(key, entry)| Some((entry.response.as_ref()?.1, key))).min().map(|(_, key)| key.clone());
+        oldest.and_then(|key| entries.remove(&key)).map_or(0, |entry| entry.size)
+    }
+}
+
+impl Reservation<'_> {
+    pub fn finish(mut self, response: Response) {
+        if let Some(entry) = self.responses.entries.lock().unwrap().get_mut(&self.key) {
+            entry.size += response.size();
+            entry.response = Some((response, Instant::now()));
+        }
+        self.finished = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.responses.entries.lock().unwrap().remove(&self.key);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// src/idle.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::process;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::info;
+use crate::Server;
+
+// When the helper last had something to do, for --idle-exit
+pub struct Activity {
+    started: Instant,
+    // Milliseconds since `started`
+    last: AtomicU64,
+    // Requests being handled and jobs still running
+    busy: AtomicUsize,
+}
+
+// Held for as long as a request or job is going; counts as activity throughout
+pub struct Busy<'a> {
+    activity: &'a Activity,
+}
+
+impl Activity {
+    pub fn new() -> Activity {
+        Activity { started: Instant::now(), last: AtomicU64::new(0), busy: AtomicUsize::new(0) }
+    }
+
+    pub fn begin(&self) -> Busy<'_> {
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        self.touch();
+        Busy { activity: self }
+    }
+
+    fn touch(&self) {
+        self.last.store(self.started.elapsed().as_millis() as u64, Ordering::SeqCst);
+    }
+
+    // Zero while anything is going on
+    fn idle_for(&self) -> Duration {
+        if self.busy.load(Ordering::SeqCst) > 0 {
+            return Duration::ZERO;
+        }
+        self.started.elapsed().saturating_sub(Duration::from_millis(self.last.load(Ordering::SeqCst)))
+    }
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        // Idle time counts from when the work ended, not when it arrived
+        self.activity.touch();
+        self.activity.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Exits once nothing has happened for `limit`, closing sessions on the way out
+pub fn exit_when_idle(server: Arc<Server>, limit: Duration) {
+    let check_every = (limit / 4).clamp(Duration::from_millis(10), Duration::from_secs(1));
+    thread::spawn(move || loop {
+        thread::sleep(check_every);
+        let idle = server.activity.idle_for();
+        if idle >= limit {
+            info!("Exiting after {:?} without requests (--idle-exit)", idle);
+            server.sessions.close_all();
+            process::exit(0);
+        }
+    });
+}
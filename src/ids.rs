@@ -0,0 +1,12 @@
+// src/ids.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// 16 hex digits that are hard to guess, for job and request ids
+pub fn random() -> String {
+    // Each RandomState is seeded from the OS's randomness
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,113 @@
+// src/interpreter.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::ffi::OsString;
+use std::process::{Command, Stdio};
+use log::{info, warn};
+
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Interpreter {
+    fn new(program: &str, args: &[&str]) -> Interpreter {
+        Interpreter {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+
+    // "3.12.1" from `python --version`, which python 2 prints on stderr
+    pub fn version(&self) -> Option<String> {
+        let out = self.command().arg("--version").stdin(Stdio::null()).output().ok()?;
+        if !out.status.success() {
+            return None;
+        }
+        let printed = if out.stdout.is_empty() { &out.stderr } else { &out.stdout };
+        String::from_utf8_lossy(printed).trim().strip_prefix("Python ").map(str::to_string)
+    }
+
+    // The Windows Store "python" stub exits nonzero without running anything, so probe with --version
+    fn works(&self) -> bool {
+        self.command()
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(windows)]
+fn candidates() -> Vec<Interpreter> {
+    vec![Interpreter::new("py", &["-3"]), Interpreter::new("python", &[])]
+}
+
+#[cfg(not(windows))]
+fn candidates() -> Vec<Interpreter> {
+    vec![Interpreter::new("python", &[])]
+}
+
+// An explicit --python is used as-is; the path goes to Command as a single argument, so spaces need no quoting
+pub fn resolve(python: Option<&str>) -> Interpreter {
+    if let Some(path) = python {
+        info!("Using python from --python: {}", path);
+        return Interpreter::new(path, &[]);
+    }
+    let candidates = candidates();
+    for candidate in &candidates {
+        if candidate.works() {
+            info!("Using python: {} {}", candidate.program, candidate.args.join(" "));
+            return candidate.clone();
+        }
+    }
+    warn!("No working python found, falling back to {}", candidates[candidates.len() - 1].program);
+    candidates[candidates.len() - 1].clone()
+}
+
+// --interpreter-template, e.g. "node {script} {args}": split on whitespace, no shell involved.
+// {script} may be part of a word ("--file={script}"); {args}, where the request's args go, must
+// be a word of its own and defaults to the end.
+pub fn parse_template(raw: &str) -> Result<Vec<String>, String> {
+    let words: Vec<String> = raw.split_whitespace().map(str::to_string).collect();
+    if words.first().is_none_or(|program| program.contains('{')) {
+        return Err(format!("--interpreter-template must start with a program, got {:?}", raw));
+    }
+    if !words.iter().any(|word| word.contains("{script}")) {
+        return Err(format!("--interpreter-template must contain {{script}}, got {:?}", raw));
+    }
+    if words.iter().any(|word| word.contains("{args}") && word != "{args}") || words.iter().filter(|word| *word == "{args}").count() > 1 {
+        return Err(format!("--interpreter-template can have {{args}} once, as a word of its own, got {:?}", raw));
+    }
+    Ok(words)
+}
+
+// The program and its arguments for one run
+pub fn expand_template(template: &[String], script: &OsString, args: &[OsString]) -> (OsString, Vec<OsString>) {
+    let mut expanded = Vec::new();
+    for word in &template[1..] {
+        if word == "{args}" {
+            expanded.extend(args.iter().cloned());
+        } else if let Some((before, after)) = word.split_once("{script}") {
+            let mut arg = OsString::from(before);
+            arg.push(script);
+            arg.push(after);
+            expanded.push(arg);
+        } else {
+            expanded.push(word.into());
+        }
+    }
+    if !template.iter().any(|word| word == "{args}") {
+        expanded.extend(args.iter().cloned());
+    }
+    (template[0].clone().into(), expanded)
+}
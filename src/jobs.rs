@@ -0,0 +1,185 @@
+// src/jobs.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use crate::{ids, memory};
+
+// Ids of jobs whose result was dropped are remembered this long, newest kept
+const MAX_GONE_IDS: usize = 10_000;
+
+// Async runs by id; finished ones are kept for `ttl` so their result can be fetched,
+// at most `max_finished` at once
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+    // Locked after `jobs` wherever both are
+    gone: Mutex<Gone>,
+    ttl: Duration,
+    max_finished: usize,
+}
+
+// Jobs that finished and were dropped since, to tell "expired" from "never existed"
+#[derive(Default)]
+struct Gone {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+pub enum Status {
+    Found(Value),
+    Gone,
+    Unknown,
+}
+
+struct Job {
+    state: State,
+    cancel: Arc<AtomicBool>,
+}
+
+enum State {
+    Queued,
+    Running,
+    Finished { result: Value, failed: bool, at: Instant, size: usize },
+}
+
+// What the run itself holds on to
+pub struct Handle {
+    pub id: String,
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Jobs {
+    pub fn new(ttl: Duration, max_finished: usize) -> Jobs {
+        Jobs { jobs: Mutex::new(HashMap::new()), gone: Mutex::default(), ttl, max_finished }
+    }
+
+    pub fn start(&self) -> Handle {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.expire(&mut jobs);
+        let id = loop {
+            // Unguessable, so one client can't cancel another's jobs by counting
+            let id = ids::random();
+            if !jobs.contains_key(&id) {
+                break id;
+            }
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+        jobs.insert(id.clone(), Job { state: State::Queued, cancel: Arc::clone(&cancel) });
+        Handle { id, cancel }
+    }
+
+    // The job got its execution slot
+    pub fn running(&self, id: &str) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.state = State::Running;
+        }
+    }
+
+    pub fn finish(&self, id: &str, result: Value, failed: bool) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(id) {
+            let size = memory::estimate(&[id, &result.to_string()]);
+            job.state = State::Finished { result, failed, at: Instant::now(), size };
+        }
+        let finished = jobs.values().filter(|job| matches!(job.state, State::Finished { .. })).count();
+        for _ in self.max_finished..finished {
+            self.remove_oldest(&mut jobs);
+        }
+    }
+
+    // False when the job already finished or never existed
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.jobs.lock().unwrap().get(id) {
+            Some(job) if !matches!(job.state, State::Finished { .. }) => {
+                job.cancel.store(true, Ordering::SeqCst);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn status(&self, id: &str) -> Status {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.expire(&mut jobs);
+        let Some(job) = jobs.get(id) else {
+            return if self.gone.lock().unwrap().ids.contains(id) { Status::Gone } else { Status::Unknown };
+        };
+        Status::Found(match &job.state {
+            State::Queued => json!({"job_id": id, "status": "queued"}),
+            State::Running => json!({"job_id": id, "status": "running"}),
+            State::Finished { result, failed, .. } => json!({
+                "job_id": id,
+                "status": if *failed { "failed" } else { "done" },
+                "result": result,
+            }),
+        })
+    }
+
+    fn expire(&self, jobs: &mut HashMap<String, Job>) {
+        let mut gone = self.gone.lock().unwrap();
+        jobs.retain(|id, job| match job.state {
+            State::Finished { at, .. } if at.elapsed() >= self.ttl => {
+                gone.add(id);
+                false
+            }
+            _ => true,
+        });
+    }
+
+    // The size of the finished job that went, 0 when there was none
+    fn remove_oldest(&self, jobs: &mut HashMap<String, Job>) -> usize {
+        let oldest = jobs.iter().filter_map(|(id, job)| match job.state {
+            State::Finished { at, .. } => Some((at, id)),
+            _ => None,
+        }).min().map(|(_, id)| id.clone());
+        let Some(id) = oldest else {
+            return 0;
+        };
+        self.gone.lock().unwrap().add(&id);
+        match jobs.remove(&id).map(|job| job.state) {
+            Some(State::Finished { size, .. }) => size,
+            _ => 0,
+        }
+    }
+}
+
+impl Gone {
+    fn add(&mut self, id: &str) {
+        if self.order.len() == MAX_GONE_IDS {
+            if let Some(forgotten) = self.order.pop_front() {
+                self.ids.remove(&forgotten);
+            }
+        }
+        self.ids.insert(id.to_string());
+        self.order.push_back(id.to_string());
+    }
+}
+
+// Only finished jobs go; one still queued or running can't lose its status
+impl memory::Store for Jobs {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn bytes(&self) -> usize {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values().map(|job| match job.state {
+            State::Finished { size, .. } => size,
+            _ => 0,
+        }).sum()
+    }
+
+    fn oldest(&self) -> Option<Instant> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values().filter_map(|job| match job.state {
+            State::Finished { at, .. } => Some(at),
+            _ => None,
+        }).min()
+    }
+
+    fn evict_oldest(&self) -> usize {
+        self.remove_oldest(&mut self.jobs.lock().unwrap())
+    }
+}
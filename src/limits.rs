@@ -0,0 +1,61 @@
+// src/limits.rs for the Rust helper "FlurionsPythonBindings"
+
+// Per-request caps on how much of a script's output makes it into the response
+
+use serde_json::{json, Value};
+
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    pub max_lines: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+// What was cut off the end
+pub struct Dropped {
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+impl Dropped {
+    pub fn to_json(&self) -> Value {
+        json!({"lines": self.lines, "bytes": self.bytes})
+    }
+
+    pub fn marker(&self) -> String {
+        format!("[truncated: {} more lines, {} more bytes]\n", self.lines, self.bytes)
+    }
+}
+
+impl Limits {
+    // The output up to whichever limit comes first, then the marker
+    pub fn apply(&self, output: &[u8]) -> (Vec<u8>, Option<Dropped>) {
+        let mut cut = output.len();
+        if let Some(max_lines) = self.max_lines {
+            // Checked to be at least 1 in request.rs
+            if let Some((end, _)) = output.iter().enumerate().filter(|(_, &b)| b == b'\n').nth(max_lines - 1) {
+                cut = end + 1;
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if max_bytes < cut {
+                cut = max_bytes;
+                // Back to the start of a UTF-8 character
+                while cut > 0 && output[cut] & 0xc0 == 0x80 {
+                    cut -= 1;
+                }
+            }
+        }
+        if cut == output.len() {
+            return (output.to_vec(), None);
+        }
+        let rest = &output[cut..];
+        let lines = rest.iter().filter(|&&b| b == b'\n').count() + usize::from(!rest.ends_with(b"\n"));
+        let dropped = Dropped { lines, bytes: rest.len() };
+        let mut kept = output[..cut].to_vec();
+        if !kept.is_empty() && !kept.ends_with(b"\n") {
+            kept.push(b'\n');
+        }
+        kept.extend_from_slice(dropped.marker().as_bytes());
+        (kept, Some(dropped))
+    }
+}
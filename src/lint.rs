@@ -0,0 +1,86 @@
+// src/lint.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use log::debug;
+use serde_json::{json, Value};
+use crate::exec;
+
+// What POST /api/lint runs, as `python -m <module>` so it's the one installed for --python
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Linter {
+    Ruff,
+    Flake8,
+}
+
+pub enum Error {
+    // The linter module isn't installed for this python
+    Missing(String),
+    Failed(String),
+}
+
+impl Linter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Linter::Ruff => "ruff",
+            Linter::Flake8 => "flake8",
+        }
+    }
+
+    // Both exit 0 whatever they find, so anything else means the linter itself failed
+    fn args(&self) -> &'static [&'static str] {
+        match self {
+            Linter::Ruff => &["check", "--output-format=json", "--no-cache", "--exit-zero"],
+            Linter::Flake8 => &["--exit-zero", "--format=%(row)d:%(col)d:%(code)s:%(text)s"],
+        }
+    }
+}
+
+// Lints `script` from its own directory. Each finding is {"file", "line", "col", "code", "message"}.
+pub fn run(linter: Linter, mut python: Command, script: &Path, timeout: Duration) -> Result<Vec<Value>, Error> {
+    let (Some(dir), Some(file)) = (script.parent(), script.file_name()) else {
+        return Err(Error::Failed(format!("Can't lint {:?}", script)));
+    };
+    let file = file.to_string_lossy().to_string();
+    debug!("Running {} on {:?}", linter.name(), script);
+    python.current_dir(dir).args(["-m", linter.name()]).args(linter.args()).arg(&file);
+    let out = exec::run(python, None, timeout, None).map_err(|e| Error::Failed(format!("Failed to run python: {}", e)))?;
+    if out.timed_out() {
+        return Err(Error::Failed(format!("{} took longer than {:?}", linter.name(), timeout)));
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    if !out.status.is_some_and(|status| status.success()) {
+        if stderr.contains(&format!("No module named {}", linter.name())) {
+            return Err(Error::Missing(format!("{} isn't installed for this python; pip install {}", linter.name(), linter.name())));
+        }
+        return Err(Error::Failed(format!("{} failed: {}", linter.name(), stderr)));
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    match linter {
+        Linter::Ruff => {
+            let found: Vec<Value> = serde_json::from_str(&stdout).map_err(|e| Error::Failed(format!("Unexpected ruff output: {}", e)))?;
+            Ok(found.iter().map(|finding| json!({
+                "file": file,
+                "line": finding["location"]["row"],
+                "col": finding["location"]["column"],
+                // null for syntax errors
+                "code": finding["code"],
+                "message": finding["message"],
+            })).collect())
+        }
+        Linter::Flake8 => stdout.lines().map(|line| {
+            let mut parts = line.splitn(4, ':');
+            let (Some(row), Some(col), Some(code), Some(message)) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+                return Err(Error::Failed(format!("Unexpected flake8 output: {:?}", line)));
+            };
+            Ok(json!({
+                "file": file,
+                "line": row.parse::<u64>().ok(),
+                "col": col.parse::<u64>().ok(),
+                "code": code,
+                "message": message,
+            }))
+        }).collect(),
+    }
+}
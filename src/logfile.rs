@@ -0,0 +1,68 @@
+// src/logfile.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+// --log-file, kept open for appending until SIGHUP asks for it to be reopened (after logrotate
+// has moved it aside, say)
+struct LogFile {
+    #[cfg(unix)]
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+static LOG_FILE: OnceLock<LogFile> = OnceLock::new();
+
+// What env_logger writes to; every line goes to whichever file is open at the time
+pub struct Writer;
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+pub fn open(path: &Path) -> io::Result<Writer> {
+    let file = open_append(path)?;
+    let _ = LOG_FILE.set(LogFile {
+        #[cfg(unix)]
+        path: path.to_path_buf(),
+        file: Mutex::new(file),
+    });
+    Ok(Writer)
+}
+
+#[cfg(unix)]
+pub fn is_open() -> bool {
+    LOG_FILE.get().is_some()
+}
+
+// On failure the old file stays in use, so nothing gets lost
+#[cfg(unix)]
+pub fn reopen() -> io::Result<()> {
+    let Some(log) = LOG_FILE.get() else {
+        return Ok(());
+    };
+    let file = open_append(&log.path)?;
+    *log.file.lock().unwrap_or_else(|e| e.into_inner()) = file;
+    Ok(())
+}
+
+fn with_file<T>(f: impl FnOnce(&mut File) -> io::Result<T>) -> io::Result<T> {
+    match LOG_FILE.get() {
+        Some(log) => f(&mut log.file.lock().unwrap_or_else(|e| e.into_inner())),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no log file is open")),
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        with_file(|file| file.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        with_file(|file| file.flush())
+    }
+}
@@ -1,59 +1,676 @@
 // src/main.rs for the Rust helper "FlurionsPythonBindings"
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::io::{self, BufRead, Write, Read};
-use std::net::{TcpListener, TcpStream};
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::fs::{self, File};
 use std::env;
 use log::{info, debug, error, warn};
-use env_logger;
+use serde_json::json;
+
+mod accept;
+mod auth;
+mod base64;
+mod bytecode;
+mod capabilities;
+mod concurrency;
+mod config;
+mod diff;
+mod docker;
+mod exec;
+mod formatter;
+mod gzip;
+mod history;
+mod http;
+mod ids;
+mod idempotency;
+mod idle;
+mod interpreter;
+mod jobs;
+mod limits;
+mod lint;
+mod logfile;
+mod memory;
+mod metrics;
+mod multipart;
+#[cfg(target_os = "linux")]
+mod namespaces;
+mod net;
+mod packages;
+mod paths;
+#[cfg(unix)]
+mod privileges;
+mod priority;
+mod profile;
+mod redact;
+mod request;
+mod resources;
+mod restricted;
+mod scripts;
+mod sessions;
+mod sha1;
+mod sha256;
+mod strict_json;
+mod timing;
+mod warmup;
+mod websocket;
+#[cfg(unix)]
+mod shutdown;
+
+use concurrency::{Slots, SpawnRate};
+use config::{BinaryOutput, Config, OnCrash, OneShot, Sandbox};
+use history::{History, Tags};
+use request::Source;
+use http::{send_json, send_json_with_headers, send_response, send_response_with_headers, Connection, Method, Request};
+use interpreter::Interpreter;
+use jobs::Jobs;
+use net::{Listener, Socket};
+use metrics::Metrics;
+use packages::Packages;
+use scripts::Scripts;
+use sessions::Sessions;
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
-    let log_level = if args.contains(&"--debug".to_string()) {
-        "debug"
-    } else if args.contains(&"--info".to_string()) {
-        "info"
-    } else if args.contains(&"--error".to_string()) {
-        "error"
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    env::set_var("RUST_LOG", config.log_level);
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(path) = &config.log_file {
+        match logfile::open(path) {
+            Ok(writer) => logger.target(env_logger::Target::Pipe(Box::new(writer))),
+            Err(e) => {
+                eprintln!("Failed to open --log-file {:?}: {}", path, e);
+                process::exit(2);
+            }
+        };
+    }
+    logger.init();
+
+    let server = match Server::new(config) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    if let Some(one_shot) = &server.config().one_shot {
+        process::exit(run_once(&server, one_shot));
+    }
+
+    let server = Arc::new(server);
+    #[cfg(unix)]
+    shutdown::install(Arc::clone(&server));
+    if let Some(limit) = server.config().idle_exit {
+        idle::exit_when_idle(Arc::clone(&server), limit);
+    }
+    let mut accepting = Vec::new();
+    for listen in &server.config().listeners {
+        let listener = match Listener::bind(&listen.address) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to listen on {}: {}", listen.address, e);
+                process::exit(1);
+            }
+        };
+        info!("Flurion's Python Bindings listening on {}", listener.local_address()?);
+        let server = Arc::clone(&server);
+        let require_auth = listen.require_auth;
+        accepting.push(thread::spawn(move || serve(listener, &server, require_auth)));
+    }
+    for listener in accepting {
+        let _ = listener.join();
+    }
+
+    Ok(())
+}
+
+// Accept loop for one listener, a thread per connection
+fn serve(listener: Listener, server: &Arc<Server>, require_auth: bool) {
+    let mut failures = 0;
+    loop {
+        // Errors like EMFILE tend to pass, so wait a moment rather than going down
+        let socket = match listener.accept() {
+            Ok(socket) => {
+                failures = 0;
+                socket
+            }
+            Err(e) => {
+                failures += 1;
+                if failures >= server.config().max_accept_failures {
+                    error!("Giving up after {} failed accepts in a row: {}", failures, e);
+                    process::exit(1);
+                }
+                warn!("Failed to accept a connection ({} in a row): {}", failures, e);
+                thread::sleep(server.config().accept_backoff);
+                continue;
+            }
+        };
+        let server = Arc::clone(server);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(socket, &server, require_auth) {
+                error!("Error handling connection: {}", e);
+            }
+        });
+    }
+}
+
+// --run and --exec: the same execution as /api/interpreter, with its JSON result printed to
+// stdout. Returns the script's exit code, or 1 if it didn't get to exit on its own.
+fn run_once(server: &Server, one_shot: &OneShot) -> i32 {
+    let command = match one_shot {
+        OneShot::Code(code) => code.clone(),
+        OneShot::File(path) => match fs::read_to_string(path) {
+            Ok(command) => command,
+            Err(e) => {
+                error!("Failed to read {:?}: {}", path, e);
+                return 2;
+            }
+        },
+    };
+    let script_dir = match write_script(server, &command) {
+        Ok(dir) => dir,
+        Err(_) => return 1,
+    };
+    let run_request = request::RunRequest { command: Some(command), ..Default::default() };
+    let script_path = script_dir.script();
+    let execution = execute(server, &run_request, &script_dir, &script_path, server.config().timeout, None, &mut |_, _, _| {});
+    let result = if server.config().pretty {
+        serde_json::to_string_pretty(&execution.result)
     } else {
-        "warn"
+        serde_json::to_string(&execution.result)
     };
-    env::set_var("RUST_LOG", log_level);
-    env_logger::init();
+    println!("{}", result.unwrap_or_default());
+    execution.result["exit_code"].as_i64().map_or(1, |code| code as i32)
+}
+
+struct Server {
+    // Swapped whole by /admin/reload
+    config: RwLock<Arc<Config>>,
+    interpreter: Interpreter,
+    // Asked the first time a result reports it
+    interpreter_version: OnceLock<Option<String>>,
+    #[cfg(unix)]
+    run_as: Option<privileges::RunAs>,
+    history: Mutex<History>,
+    metrics: Metrics,
+    activity: idle::Activity,
+    accept_filter: accept::Filter,
+    slots: Slots,
+    spawn_rate: SpawnRate,
+    jobs: Jobs,
+    scripts: Scripts<StoredScript>,
+    // By Idempotency-Key
+    responses: idempotency::Responses,
+    packages: Packages,
+    sessions: Sessions,
+    // Canonical; only set with --allow-file-exec
+    file_exec_root: Option<PathBuf>,
+    // Where per-run directories go
+    work_dir: PathBuf,
+    // Canonical; from --pycache-dir
+    pycache_dir: Option<PathBuf>,
+    // For /api/ping's clock
+    started: Instant,
+    // Set when writing there last failed for lack of space, cleared by the next write that works
+    disk_full: AtomicBool,
+}
+
+thread_local! {
+    // The config of the request being handled on this thread; see Server::config
+    static PINNED_CONFIG: RefCell<Option<Arc<Config>>> = const { RefCell::new(None) };
+}
+
+// Makes `config` what Server::config returns on this thread until dropped
+struct PinnedConfig;
+
+fn pin_config(config: Arc<Config>) -> PinnedConfig {
+    PINNED_CONFIG.with(|pinned| *pinned.borrow_mut() = Some(config));
+    PinnedConfig
+}
+
+impl Drop for PinnedConfig {
+    fn drop(&mut self) {
+        PINNED_CONFIG.with(|pinned| pinned.borrow_mut().take());
+    }
+}
+
+impl Server {
+    // A snapshot. While a request is being handled it's the one the request started with, so a
+    // reload only applies to requests that arrive after it: nothing already running, jobs
+    // included, has its timeout or limits changed under it.
+    fn config(&self) -> Arc<Config> {
+        PINNED_CONFIG
+            .with(|pinned| pinned.borrow().clone())
+            .unwrap_or_else(|| Arc::clone(&self.config.read().unwrap()))
+    }
+
+    fn new(config: Config) -> Result<Server, String> {
+        #[cfg(unix)]
+        let run_as = match &config.run_as_user {
+            Some(name) => Some(privileges::RunAs::lookup(name)?),
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if config.run_as_user.is_some() {
+            return Err("--run-as-user is only supported on Unix".to_string());
+        }
+        #[cfg(not(any(unix, windows)))]
+        if config.nice.is_some() {
+            log::warn!("--nice is not supported on this platform and will be ignored");
+        }
+        let interpreter = interpreter::resolve(config.python.as_deref());
+        #[cfg(target_os = "linux")]
+        if config.sandbox == Some(Sandbox::Namespaces) {
+            let program = match &config.interpreter_template {
+                Some(template) => Command::new(&template[0]),
+                None => interpreter.command(),
+            };
+            namespaces::probe(program)?;
+        }
+        let history = Mutex::new(History::new(config.history_size));
+        let slots = Slots::new(config.max_concurrency);
+        let spawn_rate = SpawnRate::new(config.spawn_interval);
+        let jobs = Jobs::new(config.job_ttl, config.max_completed_jobs);
+        let scripts = Scripts::new(config.script_ttl, config.max_stored_scripts);
+        let responses = idempotency::Responses::new(config.idempotency_ttl, config.max_idempotency_keys);
+        let packages = Packages::new(config.packages_ttl);
+        let work_dir = config.work_dir.clone().unwrap_or_else(|| env::temp_dir().join("fpb"));
+        if config.no_auto_mkdir {
+            check_work_dir(&work_dir)?;
+        }
+        let file_exec_root = if config.allow_file_exec {
+            let root = match &config.file_exec_root {
+                Some(root) => root.clone(),
+                // Scripts bundled alongside the helper
+                None => env::current_exe()
+                    .ok()
+                    .and_then(|exe| exe.parent().map(PathBuf::from))
+                    .ok_or("Can't determine the helper's directory, set --file-exec-root")?,
+            };
+            let root = fs::canonicalize(&root).map_err(|e| format!("Invalid --file-exec-root {:?}: {}", root, e))?;
+            info!("Allowing file execution under {:?}", root);
+            Some(root)
+        } else {
+            None
+        };
+        let pycache_dir = match &config.pycache_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir.join(STORED_BYTECODE)).map_err(|e| format!("Can't create --pycache-dir {:?}: {}", dir, e))?;
+                let dir = fs::canonicalize(dir).map_err(|e| format!("Invalid --pycache-dir {:?}: {}", dir, e))?;
+                // Scripts write their imports' bytecode there, and stored scripts are compiled by one
+                #[cfg(unix)]
+                if let Some(run_as) = run_as {
+                    for path in [dir.clone(), dir.join(STORED_BYTECODE)] {
+                        run_as.chown(&path).map_err(|e| format!("Can't hand --pycache-dir {:?} to --run-as-user: {}", path, e))?;
+                    }
+                }
+                Some(dir)
+            }
+            None => None,
+        };
+        let sessions = Sessions::new(config.max_sessions);
+        Ok(Server {
+            config: RwLock::new(Arc::new(config)),
+            interpreter,
+            interpreter_version: OnceLock::new(),
+            #[cfg(unix)]
+            run_as,
+            history,
+            metrics: Metrics::default(),
+            activity: idle::Activity::new(),
+            accept_filter: accept::Filter::new(),
+            slots,
+            spawn_rate,
+            jobs,
+            scripts,
+            responses,
+            packages,
+            sessions,
+            file_exec_root,
+            work_dir,
+            pycache_dir,
+            started: Instant::now(),
+            disk_full: AtomicBool::new(false),
+        })
+    }
 
-    let listener = TcpListener::bind("127.0.0.1:6914")?;
-    info!("Flurion's Python Bindings listening on localhost:6914");
+    // Resolves a "file" against the allowed root; relative paths are taken relative to it
+    fn resolve_exec_file(&self, file: &str) -> Result<PathBuf, (u32, String)> {
+        let root = match &self.file_exec_root {
+            Some(root) => root,
+            None => return Err((403, "Forbidden: file execution is disabled (see --allow-file-exec)".to_string())),
+        };
+        paths::within(root, "file", file).map_err(|e| (400, format!("Bad Request: {}", e)))
+    }
 
-    for stream in listener.incoming() {
-        let stream = stream?;
-        if let Err(e) = handle_connection(stream) {
-            error!("Error handling connection: {}", e);
+    // With --token set every route but the landing page and public ones needs it (except on no-auth
+    // listeners); admin routes need it to be set at all
+    fn reject(&self, route: &Route, request: &Request, require_auth: bool) -> Option<(u32, &'static str)> {
+        match &self.config().token {
+            Some(_) if route.is_public() => None,
+            Some(token) if require_auth && !auth::bearer_matches(request.header("authorization"), token) => Some((401, "Unauthorized")),
+            Some(_) => None,
+            None if route.is_admin() => Some((403, "Forbidden: admin endpoints require --token")),
+            None => None,
         }
     }
 
-    Ok(())
+    // For --max-server-memory
+    fn trim_memory(&self) {
+        if let Some(limit) = self.config().max_server_memory {
+            memory::trim(limit, &[&self.history, &self.scripts, &self.jobs, &self.responses]);
+        }
+    }
+
+    // Passes `result` through, keeping track of whether the work directory is full
+    fn note_storage<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        match &result {
+            Err(e) if is_disk_full(e) => {
+                self.metrics.disk_full();
+                if !self.disk_full.swap(true, Ordering::Relaxed) {
+                    error!("The work directory {:?} is out of space: {}", self.work_dir, e);
+                }
+            }
+            Ok(_) => {
+                if self.disk_full.swap(false, Ordering::Relaxed) {
+                    info!("The work directory {:?} has space again", self.work_dir);
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    // Bodies carry the user's code and may hold secrets; only with --log-bodies, and only at debug
+    fn log_body(&self, what: &str, body: &str) {
+        let config = self.config();
+        if config.log_bodies && log::log_enabled!(log::Level::Debug) {
+            debug!("{}: {}", what, redact::redact(body, config.log_body_limit));
+        }
+    }
+
+    // What runs a prepared script: python with `args` (launch args first), and what the
+    // sandbox needs looked at after the run when sandboxed. With --interpreter-template, `args` are just the
+    // script and the request's args, and go into the template instead.
+    fn script_command(&self, script_dir: &ScriptDir, script_path: &Path, args: Vec<OsString>, env: &HashMap<String, String>) -> (Command, Option<Sandboxed>) {
+        let config = self.config();
+        let (program, args) = match (&config.interpreter_template, args.split_first()) {
+            (Some(template), Some((script, rest))) => {
+                let (program, args) = interpreter::expand_template(template, script, rest);
+                (Some(program), args)
+            }
+            _ => (None, args),
+        };
+        match config.sandbox {
+            Some(Sandbox::Docker) => {
+                let program = program.unwrap_or_else(|| "python".into());
+                let (command, container) = docker::command(&config.sandbox_image, &program, &script_dir.path, script_path, args, env);
+                (command, Some(Sandboxed::Docker(container)))
+            }
+            sandbox => {
+                let (mut command, sandboxed) = match program {
+                    Some(program) => self.prepare(Command::new(program), sandbox),
+                    None => self.python_in(sandbox),
+                };
+                if let Some(home) = &script_dir.home {
+                    command.env("HOME", home).env("USERPROFILE", home);
+                }
+                // The request's env still wins, HOME included
+                command.args(args).envs(env);
+                (command, sandboxed)
+            }
+        }
+    }
+
+    // {"path", "version"} of the python scripts run with; None when that isn't ours to tell, with
+    // --interpreter-template or inside docker
+    fn interpreter_info(&self) -> Option<serde_json::Value> {
+        let config = self.config();
+        if config.interpreter_template.is_some() || config.sandbox == Some(Sandbox::Docker) {
+            return None;
+        }
+        let version = self.interpreter_version.get_or_init(|| self.interpreter.version());
+        Some(json!({"path": self.interpreter.program, "version": version}))
+    }
+
+    // The interpreter with its launcher args, ready for a script path or -m/-c
+    fn python(&self) -> Command {
+        self.python_in(None).0
+    }
+
+    // python(), inside the sandbox when it's one set up around the process itself
+    fn python_in(&self, sandbox: Option<Sandbox>) -> (Command, Option<Sandboxed>) {
+        self.prepare(self.interpreter.command(), sandbox)
+    }
+
+    // The environment, sandbox, priority and user every child runs with
+    fn prepare(&self, mut python: Command, sandbox: Option<Sandbox>) -> (Command, Option<Sandboxed>) {
+        let config = self.config();
+        if config.clean_env {
+            python.env_clear();
+            // Python can't even start on Windows without SYSTEMROOT
+            let required = if cfg!(windows) { &["SYSTEMROOT"][..] } else { &[] };
+            for name in config.env_passthrough.iter().map(String::as_str).chain(required.iter().copied()) {
+                if let Some(value) = env::var_os(name) {
+                    python.env(name, value);
+                }
+            }
+        }
+        // Our own settings, --token among them, whatever --clean-env says
+        for (name, _) in env::vars_os().filter(|(name, _)| name.to_string_lossy().starts_with(config::ENV_PREFIX)) {
+            python.env_remove(name);
+        }
+        // Nor may one of the helper's own pass for a header the request didn't send
+        for name in &config.expose_headers_as_env {
+            python.env_remove(header_var(name));
+        }
+        // Python checks these against the source and its own version, so they can be kept for good
+        if let Some(dir) = &self.pycache_dir {
+            python.env("PYTHONPYCACHEPREFIX", dir).env_remove("PYTHONDONTWRITEBYTECODE");
+        }
+        // While we still have the privileges for it, before --run-as-user drops them
+        #[cfg(target_os = "linux")]
+        let sandboxed = (sandbox == Some(Sandbox::Namespaces)).then(|| Sandboxed::Namespaces(namespaces::apply(&mut python, config.sandbox_network)));
+        #[cfg(not(target_os = "linux"))]
+        let sandboxed = {
+            let _ = sandbox;
+            None
+        };
+        if let Some(nice) = config.nice {
+            priority::apply(&mut python, nice);
+        }
+        #[cfg(unix)]
+        if let Some(run_as) = self.run_as {
+            run_as.apply(&mut python);
+        }
+        (python, sandboxed)
+    }
+}
+
+// What a sandboxed run leaves to look at once it's over
+enum Sandboxed {
+    Docker(docker::Container),
+    #[cfg(target_os = "linux")]
+    Namespaces(namespaces::Setup),
+}
+
+impl Sandboxed {
+    // For a run that was killed or never got going; only a container outlives its process
+    fn clean_up(&self) {
+        match self {
+            Sandboxed::Docker(container) => container.remove(),
+            #[cfg(target_os = "linux")]
+            Sandboxed::Namespaces(_) => {}
+        }
+    }
+
+    // Whether the sandbox failed before the script could start, which a script exiting with
+    // docker's 125 mustn't pass for
+    fn failed(&self) -> bool {
+        match self {
+            Sandboxed::Docker(container) => !container.was_created(),
+            #[cfg(target_os = "linux")]
+            Sandboxed::Namespaces(setup) => setup.failed(),
+        }
+    }
+}
+
+enum Route {
+    Interpreter,
+    Assert,
+    Pipeline,
+    Jobs,
+    // These carry the job id
+    Job(String),
+    Cancel(String),
+    NewScript,
+    // The stored script's id
+    RunScript(String),
+    NewSession,
+    // These carry the session id
+    SessionExec(String),
+    SessionStatus(String),
+    CloseSession(String),
+    // A session driven over a WebSocket
+    WebSocket,
+    Packages,
+    InstallPackages,
+    Capabilities,
+    Load,
+    Ping,
+    Warmup,
+    Lint,
+    Format,
+    History,
+    Metrics,
+    Config,
+    Reload,
+    Flush,
+}
+
+impl Route {
+    // Answered without --token, like the landing page
+    fn is_public(&self) -> bool {
+        matches!(self, Route::Ping)
+    }
+
+    fn is_admin(&self) -> bool {
+        matches!(self, Route::History | Route::Metrics | Route::Config | Route::Reload | Route::Flush)
+    }
+
+    fn needs_body(&self) -> bool {
+        matches!(self, Route::Interpreter | Route::Assert | Route::Pipeline | Route::Jobs | Route::NewScript | Route::InstallPackages | Route::SessionExec(_) | Route::Lint | Route::Format)
+    }
 }
 
-fn handle_connection(mut stream: TcpStream) -> io::Result<()> {
-    debug!("Received connection from: {:?}", stream.peer_addr());
+// For the Allow header of a 405; None for paths that aren't routes at all
+fn allowed_methods(path: &str) -> Option<&'static str> {
+    match path {
+        "/api/packages" => Some("GET, POST"),
+        "/" | "/api/capabilities" | "/api/load" | "/api/ping" | "/api/ws" | "/admin/history" | "/admin/metrics" | "/admin/config" => Some("GET"),
+        "/api/interpreter" | "/api/assert" | "/api/pipeline" | "/api/jobs" | "/api/scripts" | "/api/sessions" | "/api/warmup" | "/api/lint" | "/api/format" | "/admin/reload" | "/admin/flush" => Some("POST"),
+        path if path.starts_with("/api/jobs/") => Some("GET"),
+        path if path.starts_with("/api/cancel/") || path.starts_with("/api/scripts/") => Some("POST"),
+        path if path.starts_with("/api/sessions/") => Some("GET, POST, DELETE"),
+        _ => None,
+    }
+}
+
+fn handle_connection(stream: Socket, server: &Arc<Server>, require_auth: bool) -> io::Result<()> {
+    debug!("Received connection from: {}", stream.peer());
+    let _busy = server.activity.begin();
+    // One snapshot for the whole request, even if a reload lands meanwhile
+    let config = server.config();
+    let _pinned = pin_config(Arc::clone(&config));
+    // A client that stops sending mid-request would otherwise hold the thread forever
+    stream.set_read_timeout(Some(config.read_timeout))?;
+    // Small responses would otherwise wait on Nagle's algorithm
+    if let Err(e) = stream.configure(&config.socket_options()) {
+        warn!("Failed to set socket options for {}: {}", stream.peer(), e);
+    } else if log::log_enabled!(log::Level::Debug) {
+        match stream.options() {
+            Ok(options) => debug!("Socket options for {}: {:?}", stream.peer(), options),
+            Err(e) => debug!("Failed to read socket options for {}: {}", stream.peer(), e),
+        }
+    }
+    let admitted = server.accept_filter.check(&config, stream.peer_ip());
+    let mut stream = Connection::new(stream, config.headers.clone());
+    let _admitted = match admitted {
+        Ok(admitted) => admitted,
+        Err(rejection) => {
+            info!("Turned away {}: {}", stream.socket().peer(), rejection);
+            if config.reject_connections == accept::Reject::Forbidden {
+                send_response(&mut stream, 403, &format!("Forbidden: {}", rejection))?;
+            }
+            return Ok(());
+        }
+    };
 
     let mut buffer = Vec::new();
-    let mut reader = io::BufReader::new(&stream);
+    // A handle of its own, so the connection stays free to update while we read
+    let mut reader = io::BufReader::new(stream.socket().try_clone()?);
     let mut line = String::new();
 
     // Read request line
-    if reader.read_line(&mut line).is_err() {
-        error!("Failed to read request line");
-        send_response(&mut stream, 500, "Internal Server Error")?;
-        return Ok(());
+    match reader.read_line(&mut line) {
+        // Hung up without a word, like TCP health checks and port scanners do
+        Ok(0) => {
+            debug!("Connection closed before a request line");
+            return Ok(());
+        }
+        Ok(_) => {}
+        Err(_) => {
+            error!("Failed to read request line");
+            send_response(&mut stream, 500, "Internal Server Error")?;
+            return Ok(());
+        }
     }
     let request_line = line.trim().to_string();
     debug!("Request line: {}", request_line);
+
+    let parsed = http::parse_request_line(&line);
     line.clear();
+    let (method, target, _version) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            info!("Malformed request line: {}", request_line);
+            send_response(&mut stream, 400, "Bad Request")?;
+            return Ok(());
+        }
+    };
+
+    let (path, query) = http::split_target(&target);
+    // Behind a proxy that forwards a subpath as is; anything outside the prefix isn't ours
+    let path = match config.route_prefix.as_deref() {
+        None => Some(path),
+        Some(prefix) => path
+            .strip_prefix(prefix)
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+            .map(|rest| if rest.is_empty() { "/" } else { rest }),
+    };
+    let Some(path) = path else {
+        info!("Outside --route-prefix: {}", request_line);
+        send_response(&mut stream, 404, "Not Found")?;
+        return Ok(());
+    };
 
-    if request_line.starts_with("GET / HTTP/1.1") {
-        let html = r#"<!DOCTYPE html>
+    let route = match (method, path) {
+        (Method::Get, "/") => {
+            let html = r#"<!DOCTYPE html>
 <html lang="en">
 <head>
     <meta charset="UTF-8">
@@ -64,18 +681,51 @@ fn handle_connection(mut stream: TcpStream) -> io::Result<()> {
     <h1>Helper is running.</h1>
 </body>
 </html>"#;
-        send_response(&mut stream, 200, html)?;
-        return Ok(());
-    }
-
-    if !request_line.starts_with("POST /api/interpreter HTTP/1.1") {
-        info!("Invalid request path: {}", request_line);
-        send_response(&mut stream, 404, "Not Found")?;
-        return Ok(());
-    }
+            send_response_with_headers(&mut stream, 200, html, &config.root_page_headers())?;
+            return Ok(());
+        }
+        (Method::Post, "/api/interpreter") => Route::Interpreter,
+        (Method::Post, "/api/assert") => Route::Assert,
+        (Method::Post, "/api/pipeline") => Route::Pipeline,
+        (Method::Post, "/api/jobs") => Route::Jobs,
+        (Method::Get, path) if path.starts_with("/api/jobs/") => Route::Job(path["/api/jobs/".len()..].to_string()),
+        (Method::Post, path) if path.starts_with("/api/cancel/") => Route::Cancel(path["/api/cancel/".len()..].to_string()),
+        (Method::Post, "/api/scripts") => Route::NewScript,
+        (Method::Post, path) if path.starts_with("/api/scripts/") => Route::RunScript(path["/api/scripts/".len()..].to_string()),
+        (Method::Get, "/api/packages") => Route::Packages,
+        (Method::Post, "/api/packages") => Route::InstallPackages,
+        (Method::Get, "/api/capabilities") => Route::Capabilities,
+        (Method::Get, "/api/load") => Route::Load,
+        (Method::Get, "/api/ping") => Route::Ping,
+        (Method::Post, "/api/warmup") => Route::Warmup,
+        (Method::Post, "/api/lint") => Route::Lint,
+        (Method::Post, "/api/format") => Route::Format,
+        (Method::Post, "/api/sessions") => Route::NewSession,
+        (Method::Post, path) if path.starts_with("/api/sessions/") => Route::SessionExec(path["/api/sessions/".len()..].to_string()),
+        (Method::Get, path) if path.starts_with("/api/sessions/") => Route::SessionStatus(path["/api/sessions/".len()..].to_string()),
+        (Method::Delete, path) if path.starts_with("/api/sessions/") => Route::CloseSession(path["/api/sessions/".len()..].to_string()),
+        (Method::Get, "/api/ws") => Route::WebSocket,
+        (Method::Get, "/admin/history") => Route::History,
+        (Method::Get, "/admin/metrics") => Route::Metrics,
+        (Method::Get, "/admin/config") => Route::Config,
+        (Method::Post, "/admin/reload") => Route::Reload,
+        (Method::Post, "/admin/flush") => Route::Flush,
+        (_, path) => {
+            if let Some(allow) = allowed_methods(path) {
+                info!("Method not allowed: {}", request_line);
+                send_response_with_headers(&mut stream, 405, "Method Not Allowed", &[("Allow", allow.to_string())])?;
+            } else {
+                info!("Invalid request path: {}", request_line);
+                send_response(&mut stream, 404, "Not Found")?;
+            }
+            return Ok(());
+        }
+    };
 
     // Read headers
+    let mut headers = Vec::new();
     let mut content_length = 0;
+    let mut header_lines = 0;
     loop {
         if reader.read_line(&mut line).is_err() {
             error!("Failed to read headers");
@@ -85,157 +735,1583 @@ fn handle_connection(mut stream: TcpStream) -> io::Result<()> {
         if line.trim().is_empty() {
             break;
         }
-        if line.to_lowercase().starts_with("content-length:") {
-            content_length = line.split(':').nth(1).unwrap().trim().parse::<usize>().unwrap_or(0);
-            debug!("Content-Length: {}", content_length);
+        header_lines += 1;
+        if header_lines > config.max_header_lines {
+            info!("Too many header lines in {}", request_line);
+            send_response(&mut stream, 431, "Request Header Fields Too Large")?;
+            return Ok(());
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim().to_lowercase();
+            let value = value.trim().to_string();
+            if name == "content-length" {
+                content_length = value.parse::<usize>().unwrap_or(0);
+                debug!("Content-Length: {}", content_length);
+            }
+            headers.push((name, value));
         }
         line.clear();
     }
+    let mut request = Request { headers, query, body: String::new() };
+
+    // Correlates our log lines with the client's tracing
+    let request_id = match request.header(&config.request_id_header) {
+        Some(id) if !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && !id.chars().any(char::is_control) => id.to_string(),
+        _ => ids::random(),
+    };
+    info!("{} has request id {}", request_line, request_id);
+    stream.add_header(&config.request_id_header, request_id);
+    if route.needs_body() {
+        // Lets clients size their requests up front instead of finding out from a 413. Output
+        // has no server-wide limit to advertise; `max_bytes` is the request's own.
+        stream.add_header("X-Max-Body-Bytes", config.max_body_bytes.to_string());
+        if let Some(max_script_bytes) = config.max_script_bytes {
+            stream.add_header("X-Max-Script-Bytes", max_script_bytes.to_string());
+        }
+    }
+
+    if let Some((status, message)) = server.reject(&route, &request, require_auth) {
+        info!("Rejected {} with {}", request_line, status);
+        let challenge = [("WWW-Authenticate", "Bearer".to_string())];
+        send_response_with_headers(&mut stream, status, message, if status == 401 { &challenge } else { &[] })?;
+        return Ok(());
+    }
 
     // Read body
+    if content_length > config.max_body_bytes {
+        info!("Body of {} bytes is over the limit", content_length);
+        send_response(&mut stream, 413, "Payload Too Large")?;
+        return Ok(());
+    }
     if content_length > 0 {
         buffer.resize(content_length, 0);
-        if reader.read_exact(&mut buffer).is_err() {  // Now works with Read in scope
-            error!("Failed to read body");
-            send_response(&mut stream, 500, "Internal Server Error")?;
-            return Ok(());
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => {}
+            // The client closed early or went quiet past --read-timeout
+            Err(e) if matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                info!("Body shorter than its Content-Length of {}: {}", content_length, e);
+                send_response(&mut stream, 400, "Bad Request: body shorter than Content-Length")?;
+                return Ok(());
+            }
+            Err(e) => {
+                error!("Failed to read body: {}", e);
+                send_response(&mut stream, 500, "Internal Server Error")?;
+                return Ok(());
+            }
         }
         debug!("Read body of length: {}", buffer.len());
-    } else {
+    } else if route.needs_body() && config.read_to_eof && request.header("content-length").is_none() && request.header("transfer-encoding").is_none() {
+        // One byte past the limit tells a body that's too big from one that's just big enough
+        match reader.by_ref().take(config.max_body_bytes as u64 + 1).read_to_end(&mut buffer) {
+            Ok(_) if buffer.len() > config.max_body_bytes => {
+                info!("Body read to EOF is over the limit");
+                send_response(&mut stream, 413, "Payload Too Large")?;
+                return Ok(());
+            }
+            Ok(_) => debug!("Read body of length {} up to EOF", buffer.len()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                info!("Body without Content-Length never ended: {}", e);
+                send_response(&mut stream, 400, "Bad Request: no Content-Length, and the body didn't end; with --read-to-eof the client must close its side")?;
+                return Ok(());
+            }
+            Err(e) => {
+                error!("Failed to read body: {}", e);
+                send_response(&mut stream, 500, "Internal Server Error")?;
+                return Ok(());
+            }
+        }
+    }
+    if buffer.is_empty() && route.needs_body() {
         info!("Missing body in request");
         send_response(&mut stream, 400, "Bad Request: Missing body")?;
         return Ok(());
     }
 
-    let body = String::from_utf8_lossy(&buffer).to_string();
-    debug!("Request body: {}", body);
-
-    // Simple JSON parsing (assuming {"command": "code here"})
-    let command = match extract_command(&body) {
-        Some(cmd) => {
-            debug!("Extracted command: {}", cmd);
-            cmd
+    match request.header("content-encoding").map(str::to_ascii_lowercase).as_deref() {
+        None | Some("identity") => {}
+        Some("gzip") | Some("x-gzip") => match gzip::decompress(&buffer, config.max_body_bytes) {
+            // The limit applies to what we unpack, so a small bomb can't blow up
+            Ok(decompressed) => buffer = decompressed,
+            Err(gzip::Error::TooLarge) => {
+                info!("Decompressed body is over the limit");
+                send_response(&mut stream, 413, "Payload Too Large")?;
+                return Ok(());
+            }
+            Err(gzip::Error::Malformed(e)) => {
+                info!("Malformed gzip body: {}", e);
+                send_response(&mut stream, 400, &format!("Bad Request: malformed gzip body: {}", e))?;
+                return Ok(());
+            }
+        },
+        Some(other) => {
+            info!("Unsupported Content-Encoding: {}", other);
+            send_response(&mut stream, 415, "Unsupported Media Type: only gzip bodies are supported")?;
+            return Ok(());
         }
-        None => {
-            info!("Invalid JSON in body");
-            send_response(&mut stream, 400, "Bad Request: Invalid JSON")?;
+    }
+
+    request.body = String::from_utf8_lossy(&buffer).to_string();
+    // Some clients (.NET, a few editors) lead with a UTF-8 BOM, which serde_json rejects
+    if let Some(body) = request.body.strip_prefix('\u{feff}') {
+        request.body = body.to_string();
+    }
+    server.log_body("Request body", &request.body);
+    // Raw code and multipart uploads aren't JSON, whatever they happen to contain
+    let json_body = !matches!(request.media_type().as_deref(), Some(media_type) if media_type.starts_with("text/") || media_type.starts_with("multipart/"));
+    if config.strict_json && json_body {
+        if let Err(e) = strict_json::check(&request.body) {
+            info!("Rejected request body: {}", e);
+            send_response(&mut stream, 400, &format!("Bad Request: {}", e))?;
             return Ok(());
         }
+    }
+    stream.pretty_json = http::flag(&request.query, "pretty").unwrap_or(config.pretty);
+
+    let handled = match route {
+        Route::Interpreter => handle_interpreter(&mut stream, &request, server, false, None),
+        Route::NewScript => handle_new_script(&mut stream, &request, server),
+        Route::RunScript(id) => handle_interpreter(&mut stream, &request, server, false, Some(&id)),
+        Route::Assert => handle_assert(&mut stream, &request, server),
+        Route::Pipeline => handle_pipeline(&mut stream, &request, server),
+        Route::Jobs => handle_interpreter(&mut stream, &request, server, true, None),
+        Route::Job(id) => match server.jobs.status(&id) {
+            jobs::Status::Found(status) => send_json(&mut stream, 200, &status),
+            jobs::Status::Gone => send_response(&mut stream, 410, &format!("Gone: the result of job {:?} has expired", id)),
+            jobs::Status::Unknown => send_response(&mut stream, 404, &format!("Not Found: no job {:?}", id)),
+        },
+        Route::Cancel(id) => {
+            if server.jobs.cancel(&id) {
+                info!("Cancelling job {}", id);
+                send_json(&mut stream, 200, &json!({"job_id": id, "cancelled": true}))
+            } else {
+                send_response(&mut stream, 404, &format!("Not Found: no running job {:?}", id))
+            }
+        }
+        Route::History => {
+            let history = server.history.lock().unwrap().to_json();
+            send_json(&mut stream, 200, &history)
+        }
+        // A session's interpreter would run on the host
+        Route::NewSession if config.sandbox.is_some() => {
+            send_json(&mut stream, 501, &json!({"error": "sandboxed", "message": "Sessions aren't available with --sandbox"}))
+        }
+        Route::NewSession => match create_session(&mut stream, server)? {
+            Some(id) => send_json(&mut stream, 201, &json!({"session_id": id})),
+            None => Ok(()),
+        },
+        Route::SessionExec(id) => handle_session_exec(&mut stream, &request, server, &id),
+        Route::WebSocket if config.sandbox.is_some() => {
+            send_json(&mut stream, 501, &json!({"error": "sandboxed", "message": "Sessions aren't available with --sandbox"}))
+        }
+        Route::WebSocket => handle_websocket(&mut stream, &mut reader, &request, server),
+        Route::SessionStatus(id) => match server.sessions.status(&id) {
+            Some(status) => send_json(&mut stream, 200, &status),
+            None => send_response(&mut stream, 404, &format!("Not Found: no session {:?}", id)),
+        },
+        Route::CloseSession(id) => {
+            if server.sessions.close(&id) {
+                info!("Closed session {}", id);
+                send_json(&mut stream, 200, &json!({"session_id": id, "closed": true}))
+            } else {
+                send_response(&mut stream, 404, &format!("Not Found: no session {:?}", id))
+            }
+        }
+        Route::Packages => match server.packages.list(server.python()) {
+            Ok(packages) => send_json(&mut stream, 200, &json!({"packages": packages})),
+            Err(e) => {
+                error!("{}", e);
+                send_json(&mut stream, 500, &json!({"error": "pip_failed", "message": e}))
+            }
+        },
+        Route::InstallPackages => handle_install(&mut stream, &request, server),
+        Route::Warmup => handle_warmup(&mut stream, &request, server),
+        Route::Lint => handle_lint(&mut stream, &request, server),
+        Route::Format => handle_format(&mut stream, &request, server),
+        Route::Capabilities => send_json(&mut stream, 200, &capabilities::to_json(&config)),
+        Route::Load => {
+            let load = server.slots.load();
+            send_json(&mut stream, 200, &json!({
+                "in_flight": load.running,
+                "queued": load.queued,
+                "max_concurrency": load.max,
+                "average_run_ms": load.average_run.as_millis() as u64,
+                // "full" until a run's files can be written again
+                "storage": if server.disk_full.load(Ordering::Relaxed) { "full" } else { "ok" },
+            }))
+        }
+        Route::Ping => handle_ping(&mut stream, &request, server),
+        Route::Metrics => send_json(&mut stream, 200, &server.metrics.to_json()),
+        Route::Config => send_json(&mut stream, 200, &config.to_json()),
+        Route::Reload => handle_reload(&mut stream, server),
+        Route::Flush => handle_flush(&mut stream, server),
     };
+    // Whatever the request added to the in-memory stores
+    server.trim_memory();
+    handled
+}
 
-    // Get temp dir and create fpb if needed
-    let mut temp_path = std::env::temp_dir();
-    temp_path.push("fpb");
-    if let Err(e) = fs::create_dir_all(&temp_path) {
-        error!("Failed to create temp dir: {}", e);
-        send_response(&mut stream, 500, "Internal Server Error")?;
+// Answers right away with the helper's monotonic clock, for measuring round trips; runs nothing.
+// ?nonce=... is echoed back so replies can be matched to pings.
+fn handle_ping(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let nonce = request.query.iter().find(|(name, _)| name == "nonce").map(|(_, nonce)| nonce.as_str());
+    if nonce.is_some_and(|nonce| nonce.len() > MAX_NONCE_LEN) {
+        info!("Rejected a ping nonce over {} bytes", MAX_NONCE_LEN);
+        send_response(stream, 400, &format!("Bad Request: nonce must be at most {} bytes", MAX_NONCE_LEN))?;
         return Ok(());
     }
-    debug!("Created temp dir: {:?}", temp_path);
-
-    let mut script_path = temp_path.clone();
-    script_path.push("script.py");
+    let mut response = json!({"ok": true, "monotonic_ms": server.started.elapsed().as_millis() as u64});
+    if let Some(nonce) = nonce {
+        response["nonce"] = json!(nonce);
+    }
+    send_json_with_headers(stream, 200, &response, &[("Cache-Control", "no-store".to_string())])
+}
 
-    // Write code to file
-    let mut file = match File::create(&script_path) {
-        Ok(f) => f,
+// Re-reads --config and applies what can change without a restart
+fn handle_reload(stream: &mut Connection, server: &Arc<Server>) -> io::Result<()> {
+    // Held throughout so concurrent reloads can't interleave
+    let mut config = server.config.write().unwrap();
+    if config.config_file.is_none() {
+        return send_json(stream, 409, &json!({"error": "no_config_file", "message": "Start the helper with --config to use reloading"}));
+    }
+    let fresh = match Config::from_args(&config.args) {
+        Ok(fresh) => fresh,
         Err(e) => {
-            error!("Failed to create script file: {}", e);
-            send_response(&mut stream, 500, "Internal Server Error")?;
-            return Ok(());
+            warn!("Not reloading: {}", e);
+            return send_json(stream, 400, &json!({"error": "invalid_config", "message": e}));
         }
     };
-    if let Err(e) = file.write_all(command.as_bytes()) {
-        error!("Failed to write to script file: {}", e);
-        send_response(&mut stream, 500, "Internal Server Error")?;
-        return Ok(());
+    let (reloaded, applied, ignored) = config.reloaded(&fresh);
+    server.spawn_rate.set_interval(reloaded.spawn_interval);
+    server.sessions.set_max(reloaded.max_sessions);
+    *config = Arc::new(reloaded);
+    info!("Reloaded the config, applied {:?}, ignored {:?}", applied, ignored);
+    let mut result = json!({"applied": applied, "ignored": ignored});
+    if !ignored.is_empty() {
+        result["note"] = json!("Ignored options only take effect after a restart");
     }
-    debug!("Wrote script to: {:?}", script_path);
+    send_json(stream, 200, &result)
+}
 
-    // Run python
-    debug!("Executing python on {:?}", script_path);
-    let output = Command::new("python")
-        .arg(script_path.to_str().unwrap())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output();
+// Clears what's only kept to save work, not anything a client still needs: stored scripts,
+// sessions, jobs and the runs in progress are left alone
+fn handle_flush(stream: &mut Connection, server: &Server) -> io::Result<()> {
+    let history = server.history.lock().unwrap().clear();
+    let idempotency = server.responses.flush();
+    let package_list = server.packages.flush();
+    let temp_dirs = remove_stale_run_dirs(&server.work_dir);
+    info!("Flushed {} history entries, {} idempotent responses and {} stale temp dirs", history, idempotency, temp_dirs);
+    send_json(stream, 200, &json!({
+        "history": history,
+        "idempotency": idempotency,
+        "package_list": usize::from(package_list),
+        "temp_dirs": temp_dirs,
+    }))
+}
 
-    let (status, response_body) = match output {
-        Ok(out) => {
-            let stdout = String::from_utf8_lossy(&out.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&out.stderr).to_string();
-            debug!("Python stdout: {}", stdout);
-            if !stderr.is_empty() {
-                warn!("Python stderr: {}", stderr);
-                (200, format!("Error: {}\nOutput: {}", stderr, stdout))
-            } else {
-                (200, stdout)
-            }
+// Run dirs are named <pid>-<n>, so those left by a helper that's no longer running (one
+// that crashed, or was killed before it could clean up) can be told apart from ours
+fn remove_stale_run_dirs(work_dir: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(work_dir) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some((pid, n)) = name.to_str().and_then(|name| name.split_once('-')) else {
+            continue;
+        };
+        let Ok(pid) = pid.parse::<u32>() else {
+            continue;
+        };
+        if n.parse::<u64>().is_err() || pid == process::id() || process_exists(pid) || !entry.path().is_dir() {
+            continue;
+        }
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("Failed to remove stale {:?}: {}", entry.path(), e),
+        }
+    }
+    removed
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    // Signal 0 only checks; EPERM means it exists under another user
+    let signalled = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Without a way to check, another helper's dirs are left alone
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+// --expose-headers-as-env: X-User-Id as HTTP_X_USER_ID, the way CGI names them
+fn header_var(name: &str) -> String {
+    format!("HTTP_{}", name.to_ascii_uppercase().replace(|c: char| !c.is_ascii_alphanumeric(), "_"))
+}
+
+// Overrides any the body's env sets
+fn expose_headers(env: &mut HashMap<String, String>, request: &Request, names: &[String]) {
+    for name in names {
+        let var = header_var(name);
+        match request.header(name) {
+            Some(value) => env.insert(var, value.to_string()),
+            None => env.remove(&var),
+        };
+    }
+}
+
+// With `background` (or "async" in the body) the run becomes a job and only its id is sent back.
+// With `script_id` the code is the script of that id from POST /api/scripts.
+fn handle_interpreter(stream: &mut Connection, request: &Request, server: &Arc<Server>, background: bool, script_id: Option<&str>) -> io::Result<()> {
+    let stored = match script_id.map(|id| (id, server.scripts.get(id))) {
+        None => None,
+        Some((_, Some(script))) => Some(script),
+        Some((id, None)) => {
+            info!("No stored script {:?}", id);
+            send_response(stream, 404, &format!("Not Found: no script {:?}, it may have expired", id))?;
+            return Ok(());
+        }
+    };
+    let boundary = request.header("content-type").and_then(multipart::boundary);
+    // The body is the script itself, for clients that would rather not build JSON
+    let raw_code = matches!(request.media_type().as_deref(), Some("text/x-python" | "text/plain"));
+    let parsed = match (&stored, boundary) {
+        (Some(script), _) => request::parse_script_run_request(&request.body).map(|req| request::RunRequest {
+            // So logs and history show what ran
+            command: Some(script.source.clone()),
+            ..req
+        }),
+        (None, Some(boundary)) => multipart::run_request(&request.body, &boundary),
+        (None, None) if raw_code => Ok(request::RunRequest { command: Some(request.body.clone()), ..Default::default() }),
+        (None, None) => request::parse_run_request(&request.body),
+    };
+    let mut run_request = match parsed {
+        Ok(req) => {
+            server.log_body("Extracted command", &req.describe());
+            req
         }
         Err(e) => {
-            error!("Failed to execute python: {}", e);
-            (500, format!("Failed to execute python: {}", e))
+            info!("Rejected request body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
         }
     };
+    // After the body's env, so it can't pass for a header that a proxy in front of us set
+    expose_headers(&mut run_request.env, request, &server.config().expose_headers_as_env);
 
-    debug!("Sending response: {}", response_body);
-    send_response(&mut stream, status, &response_body)?;
-    Ok(())
-}
+    if server.config().interpreter_template.is_some() {
+        if let Some(option) = run_request.python_only_option() {
+            info!("Rejected `{}` with --interpreter-template", option);
+            send_response(stream, 400, &format!("Bad Request: `{}` needs python and isn't available with --interpreter-template", option))?;
+            return Ok(());
+        }
+    }
+    let timeout = match server.config().effective_timeout(run_request.timeout) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            info!("Rejected timeout: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    debug!("Effective timeout: {:?}", timeout);
 
-fn send_response(stream: &mut TcpStream, status: u32, body: &str) -> io::Result<()> {
-    let status_text = if status == 200 {
-        "OK"
-    } else if status == 500 {
-        "Internal Server Error"
-    } else if status == 404 {
-        "Not Found"
+    // Jobs always answer with JSON
+    let media_type = if background || run_request.run_async {
+        "application/json"
     } else {
-        "Bad Request"
+        match request.negotiate(&["text/plain", "application/json", "application/x-ndjson"]) {
+            Some(media_type) => media_type,
+            None => {
+                info!("Nothing acceptable in Accept: {:?}", request.header("accept"));
+                send_response(stream, 406, "Not Acceptable: the output is available as text/plain, application/json or application/x-ndjson")?;
+                return Ok(());
+            }
+        }
     };
-    let status_line = format!("HTTP/1.1 {} {}\r\n", status, status_text);
-    let content_type = if status == 200 && body.contains("<!DOCTYPE html") {
-        "Content-Type: text/html\r\n"
-    } else {
-        "Content-Type: text/plain\r\n"
+
+    // Streams aren't kept, so a retried stream just runs again
+    let reservation = match request.header("idempotency-key") {
+        Some(key) if media_type != "application/x-ndjson" => {
+            let fingerprint = idempotency::fingerprint(&[if background { "job" } else { "run" }, script_id.unwrap_or_default(), media_type, &request.body]);
+            match server.responses.lookup(key, fingerprint) {
+                idempotency::Lookup::New(reservation) => Some(reservation),
+                idempotency::Lookup::Replay(response) => {
+                    info!("Replaying the response for Idempotency-Key {:?}", key);
+                    let mut headers = response.headers;
+                    headers.push(("X-Idempotent-Replay", "true".to_string()));
+                    return match response.body {
+                        idempotency::Body::Json(value) => send_json_with_headers(stream, response.status, &value, &headers),
+                        idempotency::Body::Text(text) => send_response_with_headers(stream, response.status, &text, &headers),
+                    };
+                }
+                idempotency::Lookup::InProgress => {
+                    return send_json(stream, 409, &json!({"error": "in_progress", "message": "A request with this Idempotency-Key is still running"}));
+                }
+                idempotency::Lookup::Mismatch => {
+                    return send_json(stream, 422, &json!({"error": "idempotency_key_reused", "message": "This Idempotency-Key was used for a different request"}));
+                }
+                idempotency::Lookup::Full => {
+                    warn!("Not deduplicating, {} Idempotency-Keys are already kept (--max-idempotency-keys)", server.config().max_idempotency_keys);
+                    None
+                }
+            }
+        }
+        _ => None,
     };
-    let content_length = format!("Content-Length: {}\r\n", body.len());
-    let headers = format!("{}\r\n", content_type);
 
-    if let Err(e) = stream.write_all(status_line.as_bytes()) {
-        error!("Failed to send status line: {}", e);
-        return Err(e);
-    }
-    if let Err(e) = stream.write_all(content_length.as_bytes()) {
-        error!("Failed to send content length: {}", e);
-        return Err(e);
-    }
-    if let Err(e) = stream.write_all(headers.as_bytes()) {
-        error!("Failed to send headers: {}", e);
-        return Err(e);
-    }
-    if let Err(e) = stream.write_all(body.as_bytes()) {
-        error!("Failed to send body: {}", e);
-        return Err(e);
+    if let (Source::Command(command), None) = (run_request.source(), &stored) {
+        if let Some(message) = check_script_size(server, command) {
+            info!("{}", message);
+            send_response(stream, 413, &format!("Payload Too Large: {}", message))?;
+            return Ok(());
+        }
     }
-    debug!("Sent response with status: {}", status);
-    Ok(())
-}
 
-fn extract_command(body: &str) -> Option<String> {
-    let trimmed = body.trim();
-    if !trimmed.starts_with('{') || !trimmed.ends_with('}') {
-        return None;
+    let prepared = match (run_request.source(), &stored) {
+        // Already on disk; the run only needs a directory of its own
+        (_, Some(script)) => create_run_dir(server).map(|dir| {
+            // The harnesses read the script as source
+            let script_path = match &script.bytecode {
+                Some(bytecode) if dir.harness.is_none() && run_request.python_only_option().is_none() => bytecode.clone(),
+                _ => script.dir.script(),
+            };
+            (dir, script_path)
+        }),
+        (Source::Command(command), None) => write_script(server, command).map(|dir| {
+            let script_path = dir.script();
+            (dir, script_path)
+        }),
+        (Source::File(file), None) => {
+            let script_path = match server.resolve_exec_file(file) {
+                Ok(path) => path,
+                Err((status, message)) => {
+                    info!("Rejected file {:?}: {}", file, message);
+                    send_response(stream, status, &message)?;
+                    return Ok(());
+                }
+            };
+            create_run_dir(server).map(|dir| (dir, script_path))
+        }
+    };
+    // Even a file run gets a directory, for by-products like profile stats
+    let (script_dir, script_path) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            send_storage_error(stream, &e)?;
+            return Ok(());
+        }
+    };
+
+    // Counted last, so only runs that actually start use the quota
+    if let Some(limit) = stored.as_ref().and_then(|script| script.limit.as_ref()) {
+        if let Err(wait) = limit.try_start() {
+            info!("Stored script {:?} is over its max_per_minute of {}", script_id.unwrap_or_default(), limit.max());
+            let retry_after = (wait.as_secs_f64().ceil() as u64).max(1);
+            let body = json!({
+                "error": "rate_limited",
+                "message": format!("This script runs at most {} times a minute", limit.max()),
+                "retry_after": retry_after,
+            });
+            return send_json_with_headers(stream, 429, &body, &[("Retry-After", retry_after.to_string())]);
+        }
     }
-    let inner = &trimmed[1..trimmed.len()-1];
-    if !inner.trim().starts_with("\"command\":") {
+
+    if background || run_request.run_async {
+        let job = server.jobs.start();
+        info!("Started job {}", job.id);
+        let id = job.id.clone();
+        let server = Arc::clone(server);
+        let config = server.config();
+        thread::spawn(move || {
+            let _pinned = pin_config(config);
+            let _busy = server.activity.begin();
+            // Held until the run is over, even if the script expires meanwhile
+            let _stored = stored;
+            let execution = execute(&server, &run_request, &script_dir, &script_path, timeout, Some(&job), &mut |_, _, _| {});
+            info!("Job {} finished with {}", job.id, execution.status);
+            server.jobs.finish(&job.id, execution.result, execution.status >= 400);
+            server.trim_memory();
+        });
+        let response = json!({"job_id": id});
+        if let Some(reservation) = reservation {
+            reservation.finish(idempotency::Response { status: 202, body: idempotency::Body::Json(response.clone()), headers: Vec::new() });
+        }
+        return send_json(stream, 202, &response);
+    }
+
+    if media_type == "application/x-ndjson" {
+        run_request.progress = true;
+        return stream_ndjson(stream, server, &run_request, &script_dir, &script_path, timeout);
+    }
+
+    let mut execution = execute(server, &run_request, &script_dir, &script_path, timeout, None, &mut |_, _, _| {});
+    // Errors stay inline, where the client shows them instead of saving them
+    if run_request.as_attachment && execution.status == 200 {
+        let default = if media_type == "application/json" { "output.json" } else { "output.txt" };
+        let filename = run_request.filename.as_deref().unwrap_or(default);
+        execution.headers.push(("Content-Disposition", http::attachment(filename)));
+    }
+    server.log_body("Sending response", &execution.text);
+    if media_type == "application/json" {
+        send_json_with_headers(stream, execution.status, &execution.result, &execution.headers)?;
+    } else {
+        send_response_with_headers(stream, execution.status, &execution.text, &execution.headers)?;
+    }
+    if let Some(reservation) = reservation {
+        let body = if media_type == "application/json" {
+            idempotency::Body::Json(execution.result)
+        } else {
+            idempotency::Body::Text(execution.text)
+        };
+        reservation.finish(idempotency::Response { status: execution.status, body, headers: execution.headers });
+    }
+    Ok(())
+}
+
+// Writes the script once and answers with the id to run it by
+fn handle_new_script(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let script = match request::parse_new_script_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected script body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    if let Some(message) = check_script_size(server, &script.command) {
+        info!("{}", message);
+        send_response(stream, 413, &format!("Payload Too Large: {}", message))?;
+        return Ok(());
+    }
+    let dir = match write_script(server, &script.command) {
+        Ok(dir) => dir,
+        Err(e) => {
+            send_storage_error(stream, &e)?;
+            return Ok(());
+        }
+    };
+    let limit = script.max_per_minute.map(scripts::RunLimit::per_minute);
+    let bytecode = compile_stored(server, &script.command);
+    let id = server.scripts.add(StoredScript { dir, source: script.command.clone(), bytecode, limit }, &script.command);
+    info!("Stored script {}", id);
+    let mut response = json!({"script_id": id, "ttl": server.scripts.ttl().as_secs_f64()});
+    if let Some(max) = script.max_per_minute {
+        response["max_per_minute"] = json!(max);
+    }
+    send_json(stream, 201, &response)
+}
+
+// The stored script's bytecode under --pycache-dir, compiled unless an earlier store of the same
+// source by the same python left it there. None when it's not for this python to run or doesn't
+// compile; the run then shows why.
+fn compile_stored(server: &Server, command: &str) -> Option<PathBuf> {
+    let dir = server.pycache_dir.as_ref()?.join(STORED_BYTECODE);
+    let config = server.config();
+    if config.interpreter_template.is_some() || config.sandbox == Some(Sandbox::Docker) {
         return None;
     }
-    let value_start = inner.find(':')? + 1;
-    let value = inner[value_start..].trim();
-    if value.starts_with('"') && value.ends_with('"') {
-        Some(value[1..value.len()-1].to_string())
+    let key = sha256::hex(command.as_bytes());
+    // Tracebacks quote it from here, so it stays next to the bytecode. Written under a name of its
+    // own first, so a concurrent store of the same script never compiles a half-written file.
+    let source = dir.join(format!("{}.py", key));
+    if !source.exists() {
+        let partial = dir.join(format!("{}.py.{}-{}", key, process::id(), NEXT_SCRIPT_ID.fetch_add(1, Ordering::Relaxed)));
+        let written = server.note_storage(write_script_file(&partial, command).and_then(|_| fs::rename(&partial, &source)));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&partial);
+            warn!("Failed to keep the stored script's source for compiling: {}", e);
+            return None;
+        }
+    }
+    match bytecode::compile(server.python(), &source, &key, config.timeout) {
+        Ok(path) => {
+            debug!("Stored script bytecode at {:?}", path);
+            Some(path)
+        }
+        Err(e) => {
+            info!("Not compiling the stored script: {}", e);
+            None
+        }
+    }
+}
+
+// Sends {"stream": "stdout", "line": "..."} per line of output as it's printed, then {"stream": "exit", "code": N},
+// the output ones with a "t_ms" when the request asks for timestamps. Each JSON line the script
+// writes to fd 3 comes through as {"progress": ...}, timestamped the same way.
+// With a chunk_size it's {"stream": "stdout", "chunk": "..."} per that many bytes instead, the
+// last chunk of each stream possibly shorter.
+fn stream_ndjson(stream: &mut Connection, server: &Server, run_request: &request::RunRequest, script_dir: &ScriptDir, script_path: &Path, timeout: Duration) -> io::Result<()> {
+    let timeout_secs = timeout.as_secs_f64();
+    http::start_chunked(stream, 200, "application/x-ndjson", &[("X-Effective-Timeout", timeout_secs.to_string())])?;
+    let mut write_error = None;
+    let mut send = |stream: &mut Connection, event: serde_json::Value| {
+        if write_error.is_some() {
+            return;
+        }
+        if let Err(e) = http::write_chunk(stream, format!("{}\n", event).as_bytes()) {
+            info!("Client went away mid-stream: {}", e);
+            write_error = Some(e);
+        }
+    };
+    let name = |source| match source {
+        exec::Stream::Stdout => "stdout",
+        exec::Stream::Stderr => "stderr",
+        exec::Stream::Progress => "progress",
+    };
+    // Held back until a whole chunk is there, per stream
+    let mut pending: [Vec<u8>; 2] = Default::default();
+    // With "timestamps": ms since the script started, when the output came in
+    let stamp = |mut event: serde_json::Value, elapsed: Duration| {
+        if run_request.timestamps {
+            event["t_ms"] = json!(elapsed.as_millis() as u64);
+        }
+        event
+    };
+    let mut last_output = Duration::ZERO;
+    let execution = execute(server, run_request, script_dir, script_path, timeout, None, &mut |source, line, elapsed| {
+        if source == exec::Stream::Progress {
+            match serde_json::from_slice::<serde_json::Value>(line) {
+                Ok(progress) => send(stream, stamp(json!({"progress": progress}), elapsed)),
+                Err(_) => debug!("Dropped a progress line that isn't JSON: {:?}", String::from_utf8_lossy(line)),
+            }
+            return;
+        }
+        last_output = elapsed;
+        let Some(chunk_size) = run_request.chunk_size else {
+            let line = String::from_utf8_lossy(line);
+            send(stream, stamp(json!({"stream": name(source), "line": line.strip_suffix('\n').unwrap_or(&line)}), elapsed));
+            return;
+        };
+        let buffer = &mut pending[source as usize];
+        buffer.extend_from_slice(line);
+        while buffer.len() >= chunk_size {
+            let chunk: Vec<u8> = buffer.drain(..utf8_boundary(buffer, chunk_size)).collect();
+            send(stream, stamp(json!({"stream": name(source), "chunk": String::from_utf8_lossy(&chunk)}), elapsed));
+        }
+    });
+    for source in [exec::Stream::Stdout, exec::Stream::Stderr] {
+        let rest = std::mem::take(&mut pending[source as usize]);
+        if !rest.is_empty() {
+            send(stream, stamp(json!({"stream": name(source), "chunk": String::from_utf8_lossy(&rest)}), last_output));
+        }
+    }
+    if let Some(e) = write_error {
+        return Err(e);
+    }
+    let mut exit = json!({"stream": "exit", "code": execution.result.get("exit_code").cloned().unwrap_or_default()});
+    if let Some(error) = execution.result.get("error") {
+        exit["error"] = error.clone();
+    }
+    http::write_chunk(stream, format!("{}\n", exit).as_bytes())?;
+    http::finish_chunked(stream)
+}
+
+// Where to cut `bytes` to take at most `max` of them without splitting a UTF-8 character;
+// a character longer than `max` goes out whole
+fn utf8_boundary(bytes: &[u8], max: usize) -> usize {
+    let is_continuation = |byte: u8| byte & 0xC0 == 0x80;
+    let mut end = max.min(bytes.len());
+    while end > 0 && end < bytes.len() && is_continuation(bytes[end]) {
+        end -= 1;
+    }
+    if end == 0 {
+        end = 1;
+        while end < bytes.len() && is_continuation(bytes[end]) {
+            end += 1;
+        }
+    }
+    end
+}
+
+// What a run produced, before it's shaped into a response
+struct Execution {
+    status: u32,
+    // The plain-text body, for clients that didn't ask for JSON
+    text: String,
+    result: serde_json::Value,
+    headers: Vec<(&'static str, String)>,
+}
+
+// Waits for a slot, runs the prepared script and records it in the history
+fn execute(
+    server: &Server,
+    run_request: &request::RunRequest,
+    script_dir: &ScriptDir,
+    script_path: &Path,
+    timeout: Duration,
+    job: Option<&jobs::Handle>,
+    // Also given how long the script has been running
+    on_output: &mut dyn FnMut(exec::Stream, &[u8], Duration),
+) -> Execution {
+    let permit = server.slots.acquire();
+    if let Some(job) = job {
+        server.jobs.running(&job.id);
+    }
+
+    // Run python
+    debug!("Executing python on {:?}", script_path);
+    let stats_path = script_dir.path.join("profile.out");
+    let mut args = Vec::new();
+    if run_request.profile {
+        let _ = fs::remove_file(&stats_path);
+        args.extend(profile::args(&stats_path));
+    }
+    // First, so the time to get to the script counts as startup whatever else wraps it
+    let marks_path = timing::marks_path(&script_dir.path);
+    if run_request.timing {
+        let _ = fs::remove_file(&marks_path);
+        match timing::install(&script_dir.path, &marks_path) {
+            Ok(harness_args) => args.extend(harness_args),
+            Err(e) => warn!("Failed to write the timing harness, running without it: {}", e),
+        }
+    }
+    let resources_path = resources::stats_path(&script_dir.path);
+    if run_request.track_resources {
+        let _ = fs::remove_file(&resources_path);
+        match resources::install(&script_dir.path, &resources_path, script_path) {
+            Ok(harness_args) => args.extend(harness_args),
+            Err(e) => warn!("Failed to write the resource-tracking harness, running without it: {}", e),
+        }
+    }
+    args.extend(script_dir.launch(script_path).into_iter().map(OsString::from));
+    args.extend(run_request.args.iter().map(OsString::from));
+    let (mut python, mut sandboxed) = server.script_command(script_dir, script_path, args.clone(), &run_request.env);
+    let command_line = server.config().debug_exec.then(|| command_line(&python));
+    // Of exactly what runs: the code as sent, or the file's contents as it starts
+    let script_sha256 = match run_request.source() {
+        Source::Command(command) => Some(sha256::hex(command.as_bytes())),
+        Source::File(_) => fs::read(script_path).ok().map(|contents| sha256::hex(&contents)),
+    };
+    let spawn_wait = server.spawn_rate.wait();
+    let started = Instant::now();
+    let mut retried = false;
+    let (output, spawned) = loop {
+        server.metrics.run();
+        let spawned = timing::now();
+        let output = exec::run_streaming(
+            python,
+            run_request.stdin.clone().map(String::into_bytes),
+            timeout,
+            job.map(|job| &*job.cancel),
+            run_request.merge_output,
+            // Docker doesn't pass on our fds
+            run_request.progress && server.config().sandbox != Some(Sandbox::Docker),
+            &mut |source, output| on_output(source, output, started.elapsed()),
+        );
+        if let Some(sandboxed) = &sandboxed {
+            if !output.as_ref().is_ok_and(|out| out.status.is_some()) {
+                sandboxed.clean_up();
+            }
+        }
+        let crashed = output.as_ref().ok().and_then(|out| out.status).and_then(exec::crash_signal);
+        match crashed {
+            Some(signal) if !retried && server.config().on_crash == OnCrash::Retry => {
+                warn!("Python crashed with signal {}, running it once more", signal);
+                retried = true;
+                (python, sandboxed) = server.script_command(script_dir, script_path, args.clone(), &run_request.env);
+                server.spawn_rate.wait();
+            }
+            _ => break (output, spawned),
+        }
+    };
+    let stats = if run_request.profile {
+        profile::read_stats(server.python(), &stats_path)
     } else {
-        Some(value.to_string())
+        None
+    };
+    let resources = run_request.track_resources.then(|| resources::read(&resources_path)).flatten();
+    let split = run_request.timing.then(|| timing::read(&marks_path, spawned)).flatten();
+
+    let timeout_secs = timeout.as_secs_f64();
+    let (status, text, mut result) = match output {
+        // A script may exit 125 too, so the code alone doesn't tell
+        Ok(out) if out.status.and_then(|status| status.code()) == Some(125) && sandboxed.as_ref().is_some_and(Sandboxed::failed) => {
+            let message = format!("Failed to start the sandbox: {}", String::from_utf8_lossy(&out.stderr).trim());
+            error!("{}", message);
+            (500, message.clone(), json!({"error": "sandbox_failed", "message": message}))
+        }
+        Ok(out) if out.cancelled => {
+            info!("Python was cancelled");
+            (409, "Cancelled".to_string(), json!({"error": "cancelled"}))
+        }
+        Ok(out) if out.timed_out() => {
+            warn!("Python timed out after {:?}", timeout);
+            // Whatever the script printed before it was killed
+            let partial_stdout = String::from_utf8_lossy(&out.stdout).to_string();
+            let partial_stderr = String::from_utf8_lossy(&out.stderr).to_string();
+            let mut message = format!("Timeout: script exceeded {} seconds and was terminated", timeout_secs);
+            if !partial_stdout.is_empty() {
+                message.push_str(&format!("\nPartial output: {}", partial_stdout));
+            }
+            (408, message, json!({
+                "error": "timeout",
+                "timeout": timeout_secs,
+                "terminated": true,
+                "partial_stdout": partial_stdout,
+                "partial_stderr": partial_stderr,
+            }))
+        }
+        Ok(out) if server.config().binary_output == BinaryOutput::Error && out.stdout.contains(&0) => {
+            let message = "Script wrote NUL bytes to stdout, which --binary-output error refuses to send".to_string();
+            info!("{}", message);
+            (500, message.clone(), json!({
+                "error": "binary_output",
+                "message": message,
+                "exit_code": out.status.and_then(|status| status.code()),
+            }))
+        }
+        Ok(out) => {
+            let (stdout, stdout_dropped) = run_request.limits().apply(&out.stdout);
+            let (stderr, stderr_dropped) = run_request.limits().apply(&out.stderr);
+            // Error was handled above
+            let binary = stdout.contains(&0).then_some(server.config().binary_output);
+            let stdout = match binary {
+                Some(BinaryOutput::Base64) => base64::encode(&stdout),
+                Some(_) => String::from_utf8_lossy(&stdout).replace('\0', "\u{fffd}"),
+                None => String::from_utf8_lossy(&stdout).to_string(),
+            };
+            let stderr = String::from_utf8_lossy(&stderr).to_string();
+            server.log_body("Python stdout", &stdout);
+            let succeeded = out.status.is_some_and(|status| status.success());
+            let silent = stdout.is_empty() && stderr.is_empty() && succeeded;
+            // Succeeded, but had something to say on stderr
+            let had_warnings = succeeded && !stderr.is_empty();
+            if had_warnings {
+                server.metrics.warning();
+            }
+            let mut result = json!({
+                "stdout": stdout,
+                "stderr": stderr,
+                "exit_code": out.status.and_then(|status| status.code()),
+                "had_warnings": had_warnings,
+                // Of the raw output, before invalid UTF-8 is replaced
+                "stdout_bytes": out.stdout.len(),
+                "stderr_bytes": out.stderr.len(),
+                "timeout": timeout_secs,
+            });
+            if let Some(max_rss_kb) = out.max_rss_kb {
+                result["max_rss_kb"] = json!(max_rss_kb);
+            }
+            // exit_code is null then
+            let signal = out.status.and_then(exec::signal);
+            if let Some(signal) = signal {
+                result["signal"] = json!(signal);
+            }
+            if let Some(binary) = binary {
+                // "base64" or "replace", so clients know how to read stdout
+                result["binary_output"] = json!(binary.name());
+            }
+            if let Some(dropped) = stdout_dropped {
+                result["stdout_truncated"] = dropped.to_json();
+            }
+            if let Some(dropped) = stderr_dropped {
+                result["stderr_truncated"] = dropped.to_json();
+            }
+            let mut text = if !stderr.is_empty() {
+                warn!("Python stderr: {}", stderr);
+                format!("Error: {}\nOutput: {}", stderr, stdout)
+            } else {
+                stdout
+            };
+            if let Some(stats) = stats {
+                text.push_str(&profile::format_text(&stats));
+                result["profile"] = stats;
+            }
+            if let Some(resources) = resources {
+                result["resources"] = resources;
+            }
+            if let Some(split) = split {
+                result["startup_ms"] = json!(split.startup_ms);
+                result["script_ms"] = json!(split.script_ms);
+            }
+            if !succeeded && out.stdout.is_empty() && out.stderr.is_empty() {
+                // An empty 200 would pass for success
+                let exit_code = out.status.and_then(|status| status.code());
+                let message = match exit_code {
+                    Some(code) => format!("Script exited with code {} without printing anything", code),
+                    None => match signal {
+                        Some(signal) => format!("Script was killed by signal {} without printing anything", signal),
+                        None => "Script was killed by a signal without printing anything".to_string(),
+                    },
+                };
+                info!("{}", message);
+                result["error"] = json!("nonzero_exit");
+                (500, message, result)
+            } else if silent && server.config().no_content_on_empty && result.get("profile").is_none() && result.get("resources").is_none() {
+                (204, text, result)
+            } else {
+                (200, text, result)
+            }
+        }
+        Err(e) => {
+            error!("Failed to execute python: {}", e);
+            let message = format!("Failed to execute python: {}", e);
+            (500, message.clone(), json!({"error": "spawn_failed", "message": message}))
+        }
+    };
+
+    if server.config().on_crash == OnCrash::Retry {
+        result["retried"] = json!(retried);
+    }
+    if let Some(interpreter) = server.interpreter_info() {
+        result["interpreter"] = interpreter;
+    }
+
+    let label = run_request.label.as_deref();
+    let tags = Tags { label, script_sha256: script_sha256.as_deref() };
+    server.history.lock().unwrap().record(&run_request.describe(), tags, status, started.elapsed());
+
+    let mut headers = vec![("X-Effective-Timeout", timeout_secs.to_string())];
+    if let Some(version) = result["interpreter"]["version"].as_str() {
+        headers.push(("X-Interpreter-Version", version.to_string()));
+    }
+    // Text responses have nowhere else to say it
+    if let Some(binary) = result.get("binary_output").and_then(|binary| binary.as_str()) {
+        headers.push(("X-Binary-Output", binary.to_string()));
+    }
+    if let Some(hash) = script_sha256 {
+        result["script_sha256"] = json!(hash);
+        headers.push(("X-Script-Hash", hash));
+    }
+    if let Some(label) = label {
+        info!("Run \"{}\" finished with {}", label, status);
+        result["label"] = json!(label);
+        headers.push(("X-Label", label.to_string()));
+    }
+    if let Some(queued) = &permit.queued {
+        headers.extend(queued.headers());
+    }
+    if !spawn_wait.is_zero() {
+        headers.push(("X-Spawn-Wait-Ms", spawn_wait.as_millis().to_string()));
+    }
+    if let Some(command_line) = command_line {
+        headers.push(("X-Exec-Command", exec_command_header(&command_line)));
+        result["command_line"] = command_line;
+    }
+    Execution { status, text, result, headers }
+}
+
+// {"argv": [...], "cwd": "..."} as the child is spawned, for --debug-exec
+fn command_line(command: &Command) -> serde_json::Value {
+    let cwd = command.get_current_dir().map(PathBuf::from).or_else(|| env::current_dir().ok());
+    json!({"argv": exec::argv(command), "cwd": cwd})
+}
+
+// Each arg quoted and escaped, so newlines and spaces inside one can't break the header
+fn exec_command_header(command_line: &serde_json::Value) -> String {
+    let argv = command_line["argv"].as_array().into_iter().flatten().filter_map(|arg| arg.as_str());
+    argv.map(|arg| format!("{:?}", arg)).collect::<Vec<_>>().join(" ")
+}
+
+// Runs a snippet in a session, which the watchdog restarts if it hangs
+fn handle_session_exec(stream: &mut Connection, request: &Request, server: &Server, id: &str) -> io::Result<()> {
+    let snippet = match request::parse_session_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected session body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    let timeout = match server.config().effective_session_timeout(snippet.timeout) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            info!("Rejected timeout: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+
+    match run_snippet(server, id, &snippet.code, timeout) {
+        Some((status, result)) => send_json(stream, status, &result),
+        None => send_response(stream, 404, &format!("Not Found: no session {:?}", id)),
+    }
+}
+
+// The status and JSON a snippet is answered with; None for an unknown session
+fn run_snippet(server: &Server, id: &str, code: &str, timeout: Duration) -> Option<(u32, serde_json::Value)> {
+    let started = Instant::now();
+    let timeout_secs = timeout.as_secs_f64();
+    let (status, result) = match server.sessions.exec(id, code, timeout, server.python())? {
+        Ok(sessions::Outcome::Replied(reply)) => (200, reply),
+        Ok(sessions::Outcome::TimedOut) => (408, json!({"error": "timeout", "timeout": timeout_secs, "restarted": true})),
+        Ok(sessions::Outcome::Died) => (500, json!({"error": "session_died", "restarted": true})),
+        Err(e) => {
+            error!("Failed to restart session {}: {}", id, e);
+            (500, json!({"error": "spawn_failed", "message": e.to_string()}))
+        }
+    };
+    server.history.lock().unwrap().record(code, Tags::default(), status, started.elapsed());
+    Some((status, result))
+}
+
+// Answers the error itself when the session can't be started
+fn create_session(stream: &mut Connection, server: &Server) -> io::Result<Option<String>> {
+    match server.sessions.create(server.python()) {
+        Ok(Some(id)) => {
+            info!("Started session {}", id);
+            Ok(Some(id))
+        }
+        Ok(None) => {
+            warn!("Refusing a new session, {} are already open", server.config().max_sessions.unwrap_or_default());
+            send_json(
+                stream,
+                503,
+                &json!({"error": "too_many_sessions", "message": "Close an existing session with DELETE /api/sessions/<session_id> first"}),
+            )?;
+            Ok(None)
+        }
+        Err(e) => {
+            error!("Failed to start a session: {}", e);
+            send_json(stream, 500, &json!({"error": "spawn_failed", "message": e.to_string()}))?;
+            Ok(None)
+        }
+    }
+}
+
+// GET /api/ws upgrades to a WebSocket on a session: the one named by ?session_id, or a new one
+// that's closed with the socket. Each text message is a snippet as for POST /api/sessions/<id>
+// and is answered with that endpoint's JSON, plus the HTTP status it would have had.
+fn handle_websocket(stream: &mut Connection, reader: &mut impl Read, request: &Request, server: &Server) -> io::Result<()> {
+    let has_token = |name: &str, token: &str| {
+        request.header(name).is_some_and(|value| value.split(',').any(|part| part.trim().eq_ignore_ascii_case(token)))
+    };
+    let key = request.header("sec-websocket-key").filter(|key| !key.trim().is_empty());
+    let Some(key) = key.filter(|_| has_token("upgrade", "websocket") && has_token("connection", "upgrade")) else {
+        info!("Not a WebSocket handshake");
+        return send_response(stream, 400, "Bad Request: expected a WebSocket upgrade");
+    };
+    if request.header("sec-websocket-version").map(str::trim) != Some("13") {
+        info!("Unsupported WebSocket version {:?}", request.header("sec-websocket-version"));
+        return send_response_with_headers(stream, 426, "Upgrade Required: only WebSocket version 13 is supported", &[("Sec-WebSocket-Version", "13".to_string())]);
     }
-}
\ No newline at end of file
+    let attached = request.query.iter().find(|(name, _)| name == "session_id").map(|(_, id)| id.clone());
+    let id = match &attached {
+        Some(id) if server.sessions.status(id).is_none() => {
+            return send_response(stream, 404, &format!("Not Found: no session {:?}", id));
+        }
+        Some(id) => id.clone(),
+        None => match create_session(stream, server)? {
+            Some(id) => id,
+            None => return Ok(()),
+        },
+    };
+
+    let accept = websocket::accept_key(key);
+    http::switch_protocols(stream, &[("Upgrade", "websocket".to_string()), ("Connection", "Upgrade".to_string()), ("Sec-WebSocket-Accept", accept)])?;
+    info!("WebSocket open on session {}", id);
+    let config = server.config();
+    stream.socket().set_read_timeout(Some(config.ws_idle_timeout))?;
+    let socket = stream.socket_mut();
+    let mut messages = websocket::Reader::new(config.max_body_bytes);
+    let result = websocket::send_text(socket, &json!({"session_id": id}).to_string()).and_then(|_| loop {
+        let snippet = match messages.read(reader) {
+            Ok(websocket::Message::Text(text)) => text,
+            Ok(websocket::Message::Ping(payload)) => {
+                websocket::send_pong(socket, &payload)?;
+                continue;
+            }
+            Ok(websocket::Message::Close(code)) => {
+                debug!("Client closed the WebSocket with {:?}", code);
+                break websocket::send_close(socket, websocket::NORMAL, "");
+            }
+            Err(websocket::Error::Protocol(code, reason)) => {
+                info!("Closing WebSocket on session {}: {}", id, reason);
+                break websocket::send_close(socket, code, reason);
+            }
+            Err(websocket::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                info!("Closing idle WebSocket on session {}", id);
+                break websocket::send_close(socket, websocket::GOING_AWAY, "idle");
+            }
+            Err(websocket::Error::Io(e)) => break Err(e),
+        };
+        let reply = match request::parse_session_request(&snippet).and_then(|snippet| {
+            let timeout = config.effective_session_timeout(snippet.timeout)?;
+            Ok((snippet, timeout))
+        }) {
+            Err(e) => json!({"status": 400, "error": "bad_request", "message": e}),
+            Ok((snippet, timeout)) => match run_snippet(server, &id, &snippet.code, timeout) {
+                Some((status, mut result)) => {
+                    result["status"] = json!(status);
+                    result
+                }
+                // Closed from elsewhere, e.g. DELETE /api/sessions/<id>
+                None => {
+                    break websocket::send_close(socket, websocket::GOING_AWAY, "session closed");
+                }
+            },
+        };
+        websocket::send_text(socket, &reply.to_string())?;
+    });
+    if attached.is_none() {
+        server.sessions.close(&id);
+    }
+    info!("WebSocket on session {} closed", id);
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Runs the code like /api/interpreter and compares its stdout with `expected_stdout`
+fn handle_assert(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let assertion = match request::parse_assert_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected assert body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    let timeout = match server.config().effective_timeout(assertion.timeout) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            info!("Rejected timeout: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    if let Some(message) = check_script_size(server, &assertion.command) {
+        info!("{}", message);
+        send_response(stream, 413, &format!("Payload Too Large: {}", message))?;
+        return Ok(());
+    }
+    let script_dir = match write_script(server, &assertion.command) {
+        Ok(dir) => dir,
+        Err(e) => {
+            send_storage_error(stream, &e)?;
+            return Ok(());
+        }
+    };
+    let script_path = script_dir.script();
+    let execution = execute(server, &assertion.run_request(), &script_dir, &script_path, timeout, None, &mut |_, _, _| {});
+
+    let mut result = execution.result;
+    // A run that timed out or failed to start has no stdout to compare and keeps its error status
+    let status = match result["stdout"].as_str() {
+        Some(stdout) => {
+            let diff = diff::unified(&assertion.expected_stdout, stdout);
+            result["passed"] = json!(diff.is_empty());
+            if !diff.is_empty() {
+                result["diff"] = json!(diff);
+            }
+            200
+        }
+        None => {
+            result["passed"] = json!(false);
+            execution.status
+        }
+    };
+    send_json_with_headers(stream, status, &result, &execution.headers)
+}
+
+// pip install, retried with backoff when pip couldn't reach the index
+fn handle_install(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let install = match request::parse_install_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected install body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    let config = server.config();
+    // The packages would land on the host, not in the containers scripts run in
+    if config.sandbox.is_some() {
+        return send_json(stream, 501, &json!({"error": "sandboxed", "message": "Installing packages isn't available with --sandbox"}));
+    }
+    let retry = packages::Retry { retries: config.pip_retries, backoff: config.pip_retry_backoff };
+    let _permit = server.slots.acquire();
+    info!("Installing {:?}", install.packages);
+    match server.packages.install(|| server.python(), &install.packages, &retry) {
+        Ok(attempts) => send_json(stream, 200, &json!({"installed": install.packages, "attempts": attempts})),
+        Err((attempts, network_error)) => {
+            warn!("pip install {:?} failed", install.packages);
+            let message = if network_error {
+                "pip couldn't reach the package index"
+            } else {
+                "pip couldn't install the packages, see the attempts for its output"
+            };
+            send_json(stream, 500, &json!({"error": "pip_failed", "network_error": network_error, "message": message, "attempts": attempts}))
+        }
+    }
+}
+
+fn handle_warmup(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let warmup = match request::parse_warmup_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected warmup body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    let modules = warmup.modules.unwrap_or_else(|| server.config().warmup_modules.clone());
+    if modules.is_empty() {
+        return send_json(stream, 200, &json!({"imported": [], "failed": {}}));
+    }
+    // Counts against the same limits as any other run
+    let _permit = server.slots.acquire();
+    server.spawn_rate.wait();
+    match warmup::import(server.python(), &modules, server.config().timeout) {
+        Ok(report) => send_json(stream, 200, &report),
+        Err(e) => {
+            error!("{}", e);
+            send_json(stream, 500, &json!({"error": "warmup_failed", "message": e}))
+        }
+    }
+}
+
+// Runs --linter over {"command": "..."} without running the code itself
+fn handle_lint(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let script = match request::parse_script_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected lint body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    if let Some(message) = check_script_size(server, &script.command) {
+        info!("{}", message);
+        send_response(stream, 413, &format!("Payload Too Large: {}", message))?;
+        return Ok(());
+    }
+    let dir = match write_script(server, &script.command) {
+        Ok(dir) => dir,
+        Err(e) => return send_storage_error(stream, &e),
+    };
+    let config = server.config();
+    // Counts against the same limits as any other run
+    let _permit = server.slots.acquire();
+    server.spawn_rate.wait();
+    match lint::run(config.linter, server.python(), &dir.script(), config.timeout) {
+        Ok(findings) => send_json(stream, 200, &json!({"linter": config.linter.name(), "clean": findings.is_empty(), "findings": findings})),
+        Err(lint::Error::Missing(message)) => {
+            warn!("{}", message);
+            send_json(stream, 501, &json!({"error": "linter_missing", "message": message}))
+        }
+        Err(lint::Error::Failed(message)) => {
+            error!("{}", message);
+            send_json(stream, 500, &json!({"error": "lint_failed", "message": message}))
+        }
+    }
+}
+
+// Runs --formatter over {"command": "..."}; code it can't format comes back as sent, with the error
+fn handle_format(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let script = match request::parse_script_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected format body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    if let Some(message) = check_script_size(server, &script.command) {
+        info!("{}", message);
+        send_response(stream, 413, &format!("Payload Too Large: {}", message))?;
+        return Ok(());
+    }
+    let config = server.config();
+    let name = config.formatter.name();
+    let _permit = server.slots.acquire();
+    server.spawn_rate.wait();
+    match formatter::run(config.formatter, server.python(), &script.command, config.timeout) {
+        Ok(formatted) => {
+            let changed = formatted != script.command;
+            send_json(stream, 200, &json!({"formatter": name, "formatted": formatted, "changed": changed}))
+        }
+        Err(formatter::Error::Invalid(message)) => {
+            info!("{} couldn't format the code: {}", name, message);
+            send_json(stream, 422, &json!({"error": "format_failed", "message": message, "formatter": name, "formatted": script.command, "changed": false}))
+        }
+        Err(formatter::Error::Missing(message)) => {
+            warn!("{}", message);
+            send_json(stream, 501, &json!({"error": "formatter_missing", "message": message}))
+        }
+        Err(formatter::Error::Failed(message)) => {
+            error!("{}", message);
+            send_json(stream, 500, &json!({"error": "format_failed", "message": message}))
+        }
+    }
+}
+
+// Runs each stage in turn, feeding its stdout to the next stage's stdin
+fn handle_pipeline(stream: &mut Connection, request: &Request, server: &Server) -> io::Result<()> {
+    let pipeline = match request::parse_pipeline_request(&request.body) {
+        Ok(req) => req,
+        Err(e) => {
+            info!("Rejected pipeline body: {}", e);
+            send_response(stream, 400, &format!("Bad Request: {}", e))?;
+            return Ok(());
+        }
+    };
+    if pipeline.stages.is_empty() {
+        info!("Rejected empty pipeline");
+        send_response(stream, 400, "Bad Request: stages must not be empty")?;
+        return Ok(());
+    }
+    let mut timeouts = Vec::new();
+    for (i, stage) in pipeline.stages.iter().enumerate() {
+        if let Some(message) = check_script_size(server, &stage.command) {
+            info!("Rejected stage {}: {}", i, message);
+            send_response(stream, 413, &format!("Payload Too Large: stages[{}]: {}", i, message))?;
+            return Ok(());
+        }
+        match server.config().effective_timeout(stage.timeout) {
+            Ok(timeout) => timeouts.push(timeout),
+            Err(e) => {
+                info!("Rejected timeout of stage {}: {}", i, e);
+                send_response(stream, 400, &format!("Bad Request: stages[{}]: {}", i, e))?;
+                return Ok(());
+            }
+        }
+    }
+
+    let permit = server.slots.acquire();
+    let started = Instant::now();
+    let commands: Vec<&str> = pipeline.stages.iter().map(|stage| stage.command.as_str()).collect();
+    let record = |status: u32| {
+        let tags = Tags { label: pipeline.label.as_deref(), ..Tags::default() };
+        server.history.lock().unwrap().record(&commands.join(" | "), tags, status, started.elapsed());
+    };
+    let mut input = pipeline.stdin.map(String::into_bytes);
+    let mut stages = Vec::new();
+    // Of the stage that stopped the pipeline, as /api/interpreter would have answered its run
+    let mut outcome = 200;
+    let mut spawn_wait = Duration::ZERO;
+    for (i, (stage, timeout)) in pipeline.stages.iter().zip(timeouts).enumerate() {
+        let script_dir = match write_script(server, &stage.command) {
+            Ok(dir) => dir,
+            Err(e) => {
+                send_storage_error(stream, &e)?;
+                return Ok(());
+            }
+        };
+        let script_path = script_dir.script();
+        debug!("Executing pipeline stage {} on {:?}", i, script_path);
+        let mut args: Vec<OsString> = script_dir.launch(&script_path).into_iter().map(OsString::from).collect();
+        args.extend(stage.args.iter().map(OsString::from));
+        let (python, sandboxed) = server.script_command(&script_dir, &script_path, args, &stage.env);
+        let command_line = server.config().debug_exec.then(|| command_line(&python));
+        spawn_wait += server.spawn_rate.wait();
+        server.metrics.run();
+        let out = exec::run(python, input.take(), timeout, None);
+        if let Some(sandboxed) = &sandboxed {
+            if !out.as_ref().is_ok_and(|out| out.status.is_some()) {
+                sandboxed.clean_up();
+            }
+        }
+        let out = match out {
+            Ok(out) => out,
+            Err(e) => {
+                error!("Failed to execute python: {}", e);
+                record(500);
+                send_response(stream, 500, &format!("Failed to execute python: {}", e))?;
+                return Ok(());
+            }
+        };
+        let exit_code = out.status.and_then(|status| status.code());
+        let timed_out = out.timed_out();
+        let mut stage_result = json!({
+            "exit_code": exit_code,
+            "timed_out": timed_out,
+            "stderr": String::from_utf8_lossy(&out.stderr),
+        });
+        if let Some(command_line) = command_line {
+            stage_result["command_line"] = command_line;
+        }
+        stages.push(stage_result);
+        input = Some(out.stdout);
+        if exit_code != Some(0) && !pipeline.continue_on_error {
+            info!("Pipeline stopped at stage {} (exit code {:?})", i, exit_code);
+            outcome = if timed_out { 408 } else { 500 };
+            break;
+        }
+    }
+    record(outcome);
+    if let Some(label) = &pipeline.label {
+        info!("Pipeline \"{}\" finished", label);
+    }
+
+    let stdout = input.unwrap_or_default();
+    let mut response = json!({
+        "stdout": String::from_utf8_lossy(&stdout),
+        "stages": stages,
+        "completed": outcome == 200,
+    });
+    let mut headers = permit.queued.as_ref().map(|queued| queued.headers().to_vec()).unwrap_or_default();
+    if let Some(label) = &pipeline.label {
+        response["label"] = json!(label);
+        headers.push(("X-Label", label.clone()));
+    }
+    if !spawn_wait.is_zero() {
+        headers.push(("X-Spawn-Wait-Ms", spawn_wait.as_millis().to_string()));
+    }
+    drop(permit);
+    send_json_with_headers(stream, 200, &response, &headers)
+}
+
+// Longer incoming ids are replaced rather than echoed
+const MAX_REQUEST_ID_LEN: usize = 200;
+
+const MAX_NONCE_LEN: usize = 200;
+
+static NEXT_SCRIPT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Under --pycache-dir, where stored scripts' sources and bytecode go by hash
+const STORED_BYTECODE: &str = "stored-scripts";
+
+// From POST /api/scripts; its directory goes away when the last reference does
+struct StoredScript {
+    dir: ScriptDir,
+    source: String,
+    // Under --pycache-dir; run in place of the source when nothing has to wrap it
+    bytecode: Option<PathBuf>,
+    limit: Option<scripts::RunLimit>,
+}
+
+// A per-request directory under the work dir holding the script, removed once the run is over
+struct ScriptDir {
+    path: PathBuf,
+    // Set with --restricted-builtins
+    harness: Option<PathBuf>,
+    // Set with --isolated-home; inside `path`, so it goes with it
+    home: Option<PathBuf>,
+}
+
+impl ScriptDir {
+    fn script(&self) -> PathBuf {
+        self.path.join("script.py")
+    }
+
+    // What goes on python's command line to run `script`
+    fn launch<'a>(&'a self, script: &'a Path) -> Vec<&'a Path> {
+        match &self.harness {
+            Some(harness) => vec![harness, script],
+            None => vec![script],
+        }
+    }
+}
+
+impl Drop for ScriptDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!("Failed to remove {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn create_run_dir(server: &Server) -> io::Result<ScriptDir> {
+    server.note_storage(make_run_dir(server))
+}
+
+fn make_run_dir(server: &Server) -> io::Result<ScriptDir> {
+    // Create <work dir>/<id> for this run
+    let temp_path = server.work_dir.join(format!("{}-{}", process::id(), NEXT_SCRIPT_ID.fetch_add(1, Ordering::Relaxed)));
+    let created = if server.config().no_auto_mkdir {
+        fs::create_dir(&temp_path)
+    } else {
+        fs::create_dir_all(&temp_path)
+    };
+    if let Err(e) = created {
+        error!("Failed to create temp dir: {}", e);
+        return Err(e);
+    }
+    debug!("Created temp dir: {:?}", temp_path);
+    let mut dir = ScriptDir { path: temp_path, harness: None, home: None };
+    if server.config().isolated_home {
+        let home = dir.path.join("home");
+        if let Err(e) = fs::create_dir(&home) {
+            error!("Failed to create the isolated home: {}", e);
+            return Err(e);
+        }
+        dir.home = Some(home);
+    }
+    #[cfg(unix)]
+    if let Some(run_as) = server.run_as {
+        // The dropped-privilege child writes next to its script (e.g. profile stats)
+        for path in std::iter::once(&dir.path).chain(&dir.home) {
+            if let Err(e) = run_as.chown(path) {
+                error!("Failed to hand temp dir to --run-as-user: {}", e);
+                return Err(e);
+            }
+        }
+    }
+    if server.config().restricted_builtins {
+        match restricted::install(&dir.path) {
+            Ok(harness) => dir.harness = Some(harness),
+            Err(e) => {
+                error!("Failed to write the restricted-builtins harness: {}", e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(dir)
+}
+
+// With --no-auto-mkdir the work dir must already be there, so find out at startup rather than on the first run
+fn check_work_dir(dir: &Path) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Err(format!("Work directory {:?} doesn't exist (--no-auto-mkdir is set)", dir));
+    }
+    let probe = dir.join(format!(".fpb-probe-{}", process::id()));
+    fs::write(&probe, b"").map_err(|e| format!("Work directory {:?} isn't writable: {}", dir, e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+// Checked before anything is written, so a body within --max-body-bytes still can't fill the disk
+fn check_script_size(server: &Server, command: &str) -> Option<String> {
+    let max = server.config().max_script_bytes?;
+    (command.len() > max).then(|| format!("script of {} bytes is over the {} byte limit", command.len(), max))
+}
+
+fn write_script(server: &Server, command: &str) -> io::Result<ScriptDir> {
+    let dir = create_run_dir(server)?;
+    server.note_storage(write_script_file(&dir.script(), command))?;
+    Ok(dir)
+}
+
+fn write_script_file(script_path: &Path, command: &str) -> io::Result<()> {
+    // Write code to file
+    let mut file = match File::create(script_path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed to create script file: {}", e);
+            return Err(e);
+        }
+    };
+    if let Err(e) = file.write_all(command.as_bytes()) {
+        error!("Failed to write to script file: {}", e);
+        return Err(e);
+    }
+    // On NFS or under a quota, ENOSPC and EDQUOT may only show up here, and dropping the file
+    // would lose them and leave python a truncated script
+    if let Err(e) = file.sync_data() {
+        error!("Failed to sync script file: {}", e);
+        return Err(e);
+    }
+    debug!("Wrote script to: {:?}", script_path);
+    Ok(())
+}
+
+// ENOSPC, or the Windows equivalent, and running out of disk quota
+fn is_disk_full(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded)
+}
+
+// For a run dir or script that couldn't be written: a full disk gets a 507, so operators
+// can tell it from a bug
+fn send_storage_error(stream: &mut Connection, e: &io::Error) -> io::Result<()> {
+    if is_disk_full(e) {
+        send_response(stream, 507, "Insufficient Storage: the work directory is out of space")
+    } else {
+        send_response(stream, 500, "Internal Server Error")
+    }
+}
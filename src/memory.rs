@@ -0,0 +1,38 @@
+// src/memory.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::time::Instant;
+use log::debug;
+
+// An in-memory store --max-server-memory keeps in check. Sizes are estimates, taken from each
+// entry's text as it's added, not what the allocator actually holds.
+pub trait Store {
+    fn name(&self) -> &'static str;
+    fn bytes(&self) -> usize;
+    // When the least recently used entry that may go was last used; None when there's none
+    fn oldest(&self) -> Option<Instant>;
+    // Drops that entry and returns about how much it held
+    fn evict_oldest(&self) -> usize;
+}
+
+// Evicts least recently used entries across `stores` until their total is back under `limit`
+pub fn trim(limit: usize, stores: &[&dyn Store]) {
+    loop {
+        let total: usize = stores.iter().map(|store| store.bytes()).sum();
+        if total <= limit {
+            return;
+        }
+        let oldest = stores.iter().filter_map(|store| Some((store.oldest()?, store))).min_by_key(|(used, _)| *used);
+        // Only entries still in use are left
+        let Some((_, store)) = oldest else {
+            return;
+        };
+        let freed = store.evict_oldest();
+        debug!("Evicted a {} entry of about {} bytes, {} of {} bytes in use (--max-server-memory)", store.name(), freed, total, limit);
+    }
+}
+
+// What an entry's text costs on top of the bookkeeping every entry has
+pub fn estimate(texts: &[&str]) -> usize {
+    const ENTRY_OVERHEAD: usize = 64;
+    ENTRY_OVERHEAD + texts.iter().map(|text| text.len()).sum::<usize>()
+}
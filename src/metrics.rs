@@ -0,0 +1,36 @@
+// src/metrics.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde_json::{json, Value};
+
+// Counters since startup
+#[derive(Default)]
+pub struct Metrics {
+    runs: AtomicU64,
+    // Runs that exited 0 but wrote to stderr
+    warnings: AtomicU64,
+    // Run dirs or scripts that couldn't be written for lack of space
+    disk_full: AtomicU64,
+}
+
+impl Metrics {
+    pub fn run(&self) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn warning(&self) {
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn disk_full(&self) {
+        self.disk_full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "runs": self.runs.load(Ordering::Relaxed),
+            "warnings": self.warnings.load(Ordering::Relaxed),
+            "disk_full_errors": self.disk_full.load(Ordering::Relaxed),
+        })
+    }
+}
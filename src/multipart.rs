@@ -0,0 +1,115 @@
+// src/multipart.rs for the Rust helper "FlurionsPythonBindings"
+
+use crate::request::RunRequest;
+
+pub struct Part {
+    pub name: String,
+    pub content: String,
+}
+
+// Returns the boundary if this is a multipart/form-data content type
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+pub fn parse(body: &str, boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{}", boundary);
+    let mut sections = body.split(delimiter.as_str());
+    // Anything before the first delimiter is preamble
+    sections.next();
+    let mut parts = Vec::new();
+    for section in sections {
+        if section.starts_with("--") {
+            return Ok(parts);
+        }
+        let section = section.strip_prefix("\r\n").ok_or("Malformed multipart body")?;
+        let (headers, content) = section.split_once("\r\n\r\n").ok_or("Malformed multipart part headers")?;
+        let name = headers
+            .split("\r\n")
+            .filter_map(|header| header.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))
+            .and_then(|(_, value)| disposition_name(value))
+            .ok_or("Multipart part without a name")?;
+        let content = content.strip_suffix("\r\n").unwrap_or(content);
+        parts.push(Part { name, content: content.to_string() });
+    }
+    Err("Multipart body is missing its closing delimiter".to_string())
+}
+
+// Uploads carry the script in a "file" (or "command") part and nothing else
+pub fn run_request(body: &str, boundary: &str) -> Result<RunRequest, String> {
+    let mut command = None;
+    for part in parse(body, boundary)? {
+        match part.name.as_str() {
+            "file" | "command" if command.is_none() => command = Some(part.content),
+            "file" | "command" => return Err("Expected a single file or command part".to_string()),
+            other => return Err(format!("Unknown multipart part `{}`", other)),
+        }
+    }
+    match command {
+        Some(command) => Ok(RunRequest { command: Some(command), ..Default::default() }),
+        None => Err("Missing file or command part".to_string()),
+    }
+}
+
+// form-data; name="file"; filename="script.py" -> file
+fn disposition_name(value: &str) -> Option<String> {
+    value
+        .split(';')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("name"))
+        .map(|(_, name)| name.trim().trim_matches('"').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "preamble\r\n--xyz\r\nContent-Disposition: form-data; name=\"file\"; filename=\"script.py\"\r\nContent-Type: text/x-python\r\n\r\nprint(1)\r\n--xyz--\r\n";
+
+    #[test]
+    fn finds_the_boundary() {
+        assert_eq!(boundary("multipart/form-data; boundary=xyz").as_deref(), Some("xyz"));
+        assert_eq!(boundary("Multipart/Form-Data; charset=utf-8; BOUNDARY=\"a b\"").as_deref(), Some("a b"));
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary=\"\""), None);
+        assert_eq!(boundary("application/json; boundary=xyz"), None);
+    }
+
+    #[test]
+    fn parses_parts() {
+        let parts = parse(BODY, "xyz").unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!((parts[0].name.as_str(), parts[0].content.as_str()), ("file", "print(1)"));
+        let two = "--b\r\nContent-Disposition: form-data; name=a\r\n\r\none\r\n--b\r\ncontent-disposition: form-data; name=\"b\"\r\n\r\ntwo\r\nlines\r\n--b--";
+        let names: Vec<_> = parse(two, "b").unwrap().into_iter().map(|part| (part.name, part.content)).collect();
+        assert_eq!(names, [("a".to_string(), "one".to_string()), ("b".to_string(), "two\r\nlines".to_string())]);
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        assert_eq!(parse("--xyz\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\nprint(1)\r\n", "xyz").err().as_deref(), Some("Multipart body is missing its closing delimiter"));
+        assert_eq!(parse("--xyz\r\nContent-Disposition: form-data\r\n\r\nprint(1)\r\n--xyz--", "xyz").err().as_deref(), Some("Multipart part without a name"));
+        assert_eq!(parse("--xyz\r\nno blank line--xyz--", "xyz").err().as_deref(), Some("Malformed multipart part headers"));
+        assert_eq!(parse("--xyzjunk--xyz--", "xyz").err().as_deref(), Some("Malformed multipart body"));
+    }
+
+    #[test]
+    fn takes_a_single_file_or_command_part() {
+        assert_eq!(run_request(BODY, "xyz").unwrap().command.as_deref(), Some("print(1)"));
+        let part = |name: &str| format!("--b\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\nprint(1)\r\n", name);
+        let body = |names: &[&str]| names.iter().map(|name| part(name)).collect::<String>() + "--b--";
+        assert_eq!(run_request(&body(&["command"]), "b").unwrap().command.as_deref(), Some("print(1)"));
+        assert_eq!(run_request(&body(&["file", "command"]), "b").err().as_deref(), Some("Expected a single file or command part"));
+        assert_eq!(run_request(&body(&["file", "args"]), "b").err().as_deref(), Some("Unknown multipart part `args`"));
+        assert_eq!(run_request(&body(&[]), "b").err().as_deref(), Some("Missing file or command part"));
+    }
+}
@@ -0,0 +1,148 @@
+// src/namespaces.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::ffi::CStr;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+// Exit code for a sandbox that couldn't be set up, the same one docker uses
+const SETUP_FAILED: i32 = 125;
+
+// A pipe the setup in the child writes to when it fails. Scripts may exit with SETUP_FAILED
+// too, but never get the write end: it's closed on exec.
+pub struct Setup {
+    // Read end first; the errno when the pipe couldn't be made, for spawn() to fail with
+    pipe: Result<(OwnedFd, OwnedFd), i32>,
+}
+
+impl Setup {
+    fn new() -> Setup {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            return Setup { pipe: Err(io::Error::last_os_error().raw_os_error().unwrap_or(libc::EMFILE)) };
+        }
+        Setup { pipe: Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }) }
+    }
+
+    // Once the run is over: whether it ended before the script got to run
+    pub fn failed(&self) -> bool {
+        let Ok((read, _)) = &self.pipe else {
+            return false;
+        };
+        let mut byte = 0u8;
+        unsafe { libc::read(read.as_raw_fd(), (&mut byte as *mut u8).cast(), 1) == 1 }
+    }
+}
+
+// For --sandbox namespaces: the child gets new mount, pid, IPC and UTS namespaces, and a
+// network namespace with nothing but a downed loopback unless `network` is set. Not root?
+// Then a user namespace too, mapping us to ourselves, which unprivileged users may create
+// where the kernel allows it.
+pub fn apply(command: &mut Command, network: bool) -> Setup {
+    let setup = Setup::new();
+    let status = setup.pipe.as_ref().map(|(_, write)| write.as_raw_fd()).map_err(|errno| *errno);
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let unprivileged = uid != 0;
+    let mut flags = libc::CLONE_NEWNS | libc::CLONE_NEWPID | libc::CLONE_NEWIPC | libc::CLONE_NEWUTS;
+    if !network {
+        flags |= libc::CLONE_NEWNET;
+    }
+    if unprivileged {
+        flags |= libc::CLONE_NEWUSER;
+    }
+    // Formatted out here: between fork and exec there must be no allocation
+    let uid_map = format!("{} {} 1", uid, uid);
+    let gid_map = format!("{} {} 1", gid, gid);
+    unsafe {
+        command.pre_exec(move || {
+            let status = status.map_err(io::Error::from_raw_os_error)?;
+            if libc::unshare(flags) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if unprivileged {
+                write_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+                write_file(c"/proc/self/setgroups", b"deny")?;
+                write_file(c"/proc/self/gid_map", gid_map.as_bytes())?;
+            }
+            // Nothing mounted in here shows up outside
+            if libc::mount(std::ptr::null(), c"/".as_ptr(), std::ptr::null(), libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // A new pid namespace only takes in children, so python runs in a fork of this
+            // process, which waits for it and ends the same way
+            match libc::fork() {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {}
+                pid => wait_and_exit(pid, status),
+            }
+            // Goes when the waiting parent does; a timeout kills the whole process group anyway
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+            // So the script sees only its own processes
+            if libc::mount(c"proc".as_ptr(), c"/proc".as_ptr(), c"proc".as_ptr(), libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC, std::ptr::null()) != 0 {
+                fail(status, b"fpb: failed to mount /proc in the sandbox\n");
+            }
+            Ok(())
+        });
+    }
+    setup
+}
+
+// Checks at startup that namespaces can be set up here, so a missing capability or a kernel
+// that forbids unprivileged user namespaces shows up then rather than on every run
+pub fn probe(mut program: Command) -> Result<(), String> {
+    let setup = apply(&mut program, false);
+    let out = program
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("--sandbox namespaces isn't available here (needs root or unprivileged user namespaces): {}", e))?;
+    if setup.failed() {
+        return Err(format!("--sandbox namespaces isn't available here: {}", String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    Ok(())
+}
+
+unsafe fn write_file(path: &CStr, contents: &[u8]) -> io::Result<()> {
+    let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+    libc::close(fd);
+    if written != contents.len() as isize {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+unsafe fn wait_and_exit(pid: libc::pid_t, setup_status: RawFd) -> ! {
+    // Every descriptor but the setup status goes, above all the one Command reads exec errors
+    // from, which would otherwise keep spawn() waiting until the script is done
+    let keep = setup_status as u32;
+    let closed = (keep == 0 || libc::syscall(libc::SYS_close_range, 0u32, keep - 1, 0u32) == 0)
+        && libc::syscall(libc::SYS_close_range, keep + 1, u32::MAX, 0u32) == 0;
+    if !closed {
+        for fd in (0..1024).filter(|fd| *fd != setup_status) {
+            libc::close(fd);
+        }
+    }
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) < 0 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            fail(setup_status, b"fpb: lost track of the sandboxed process\n");
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+unsafe fn fail(setup_status: RawFd, message: &[u8]) -> ! {
+    libc::write(2, message.as_ptr().cast(), message.len());
+    libc::write(setup_status, message.as_ptr().cast(), message.len());
+    libc::_exit(SETUP_FAILED)
+}
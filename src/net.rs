@@ -0,0 +1,230 @@
+// src/net.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub enum Address {
+    Tcp(String, u16),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(host, port) if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            Address::Tcp(host, port) => write!(f, "{}:{}", host, port),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// "127.0.0.1:6914", "[::1]:6914" or "unix:/run/fpb.sock"
+pub fn parse_address(raw: &str) -> Result<Address, String> {
+    if let Some(path) = raw.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return Ok(Address::Unix(PathBuf::from(path)));
+        #[cfg(not(unix))]
+        return Err(format!("Unix sockets like {:?} are only supported on Unix", path));
+    }
+    let (host, port) = raw.rsplit_once(':').ok_or_else(|| format!("Address {:?} must look like host:port or unix:/path", raw))?;
+    let port = port.parse().map_err(|_| format!("Address {:?} has an invalid port", raw))?;
+    let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+    Ok(Address::Tcp(host.to_string(), port))
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn bind(address: &Address) -> io::Result<Listener> {
+        match address {
+            Address::Tcp(host, port) => TcpListener::bind((host.as_str(), *port)).map(Listener::Tcp),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                // Left behind by an earlier run that didn't get to clean up
+                use std::os::unix::fs::FileTypeExt;
+                if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                UnixListener::bind(path).map(Listener::Unix)
+            }
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Socket::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Socket::Unix(stream)),
+        }
+    }
+
+    // The bound address, with the actual port when 0 was asked for
+    pub fn local_address(&self) -> io::Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let address = listener.local_addr()?;
+                Ok(format!("unix:{}", address.as_pathname().map(|path| path.display().to_string()).unwrap_or_default()))
+            }
+        }
+    }
+}
+
+// Set on every accepted connection; see Socket::configure
+#[derive(Debug, Clone, Copy)]
+pub struct SocketOptions {
+    // TCP only
+    pub nodelay: bool,
+    // SO_SNDBUF and SO_RCVBUF, which the OS may round or cap
+    #[cfg(unix)]
+    pub send_buffer: Option<usize>,
+    #[cfg(unix)]
+    pub recv_buffer: Option<usize>,
+}
+
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub fn try_clone(&self) -> io::Result<Socket> {
+        match self {
+            Socket::Tcp(stream) => stream.try_clone().map(Socket::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.try_clone().map(Socket::Unix),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn configure(&self, options: &SocketOptions) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_nodelay(options.nodelay)?,
+            #[cfg(unix)]
+            Socket::Unix(_) => {}
+        }
+        #[cfg(unix)]
+        {
+            let fd = self.raw_fd();
+            if let Some(bytes) = options.send_buffer {
+                set_buffer(fd, libc::SO_SNDBUF, bytes)?;
+            }
+            if let Some(bytes) = options.recv_buffer {
+                set_buffer(fd, libc::SO_RCVBUF, bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    // What the OS actually went with, for logs
+    pub fn options(&self) -> io::Result<SocketOptions> {
+        Ok(SocketOptions {
+            nodelay: match self {
+                Socket::Tcp(stream) => stream.nodelay()?,
+                #[cfg(unix)]
+                Socket::Unix(_) => false,
+            },
+            #[cfg(unix)]
+            send_buffer: Some(get_buffer(self.raw_fd(), libc::SO_SNDBUF)?),
+            #[cfg(unix)]
+            recv_buffer: Some(get_buffer(self.raw_fd(), libc::SO_RCVBUF)?),
+        })
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> std::os::fd::RawFd {
+        use std::os::fd::AsRawFd;
+        match self {
+            Socket::Tcp(stream) => stream.as_raw_fd(),
+            Socket::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+
+    // None for Unix sockets
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Socket::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.ip()),
+            #[cfg(unix)]
+            Socket::Unix(_) => None,
+        }
+    }
+
+    // For logs
+    pub fn peer(&self) -> String {
+        match self {
+            Socket::Tcp(stream) => stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|e| e.to_string()),
+            #[cfg(unix)]
+            Socket::Unix(_) => "a Unix socket client".to_string(),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_buffer(fd: std::os::fd::RawFd, option: libc::c_int, bytes: usize) -> io::Result<()> {
+    let value = libc::c_int::try_from(bytes).unwrap_or(libc::c_int::MAX);
+    let size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    if unsafe { libc::setsockopt(fd, libc::SOL_SOCKET, option, &value as *const libc::c_int as *const libc::c_void, size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn get_buffer(fd: std::os::fd::RawFd, option: libc::c_int) -> io::Result<usize> {
+    let mut value: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    if unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, option, &mut value as *mut libc::c_int as *mut libc::c_void, &mut size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value.max(0) as usize)
+}
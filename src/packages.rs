@@ -0,0 +1,133 @@
+// src/packages.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, info};
+use serde_json::{json, Value};
+use crate::exec;
+
+const PIP_TIMEOUT: Duration = Duration::from_secs(60);
+// Per attempt; installs download and build
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(300);
+// How much of pip's stderr each attempt reports
+const STDERR_TAIL_CHARS: usize = 2000;
+
+// Signs in pip's stderr that the index couldn't be reached, as opposed to it answering that a
+// package or version doesn't exist. Checked first: offline pip also ends up saying it found no
+// matching distribution, after its connection warnings.
+const NETWORK_ERRORS: &[&str] = &[
+    "NewConnectionError",
+    "ConnectionError",
+    "ConnectTimeoutError",
+    "ReadTimeoutError",
+    "Read timed out",
+    "Temporary failure in name resolution",
+    "Name or service not known",
+    "Network is unreachable",
+    "Connection reset",
+    "Connection refused",
+    "ProxyError",
+    "SSLError",
+    "IncompleteRead",
+];
+
+pub struct Retry {
+    // Attempts after the first
+    pub retries: usize,
+    // Before the first retry, doubling for each one after it
+    pub backoff: Duration,
+}
+
+// `pip list` output, kept for `ttl` since pip takes a while to start
+pub struct Packages {
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, Value)>>,
+}
+
+impl Packages {
+    pub fn new(ttl: Duration) -> Packages {
+        Packages { ttl, cached: Mutex::new(None) }
+    }
+
+    // The installed packages as [{"name", "version"}, ...]
+    pub fn list(&self, mut python: Command) -> Result<Value, String> {
+        // Held while pip runs, so concurrent requests share one run
+        let mut cached = self.cached.lock().unwrap();
+        if let Some((at, packages)) = &*cached {
+            if at.elapsed() < self.ttl {
+                return Ok(packages.clone());
+            }
+        }
+        debug!("Running pip list");
+        python.args(["-m", "pip", "list", "--format=json", "--disable-pip-version-check"]);
+        let out = exec::run(python, None, PIP_TIMEOUT, None).map_err(|e| format!("Failed to run pip: {}", e))?;
+        if out.timed_out() {
+            return Err(format!("pip list took longer than {} seconds", PIP_TIMEOUT.as_secs()));
+        }
+        if !out.status.is_some_and(|status| status.success()) {
+            return Err(format!("pip list failed: {}", String::from_utf8_lossy(&out.stderr).trim()));
+        }
+        let packages: Value = serde_json::from_slice(&out.stdout).map_err(|e| format!("Unexpected pip list output: {}", e))?;
+        *cached = Some((Instant::now(), packages.clone()));
+        Ok(packages)
+    }
+
+    // Whether there was a list to forget
+    pub fn flush(&self) -> bool {
+        self.cached.lock().unwrap().take().is_some()
+    }
+
+    // Runs `pip install` for `requirements`, trying again after network errors. Ok with the
+    // attempts when pip succeeded, Err with them (and whether it was the network) when it didn't.
+    pub fn install(&self, python: impl Fn() -> Command, requirements: &[String], retry: &Retry) -> Result<Value, (Value, bool)> {
+        let mut attempts = Vec::new();
+        let mut backoff = retry.backoff;
+        for attempt in 1..=retry.retries + 1 {
+            debug!("pip install {:?}, attempt {}", requirements, attempt);
+            let mut pip = python();
+            pip.args(["-m", "pip", "install", "--disable-pip-version-check", "--no-input"]).args(requirements);
+            let started = Instant::now();
+            let (ok, stderr, exit_code) = match exec::run(pip, None, INSTALL_TIMEOUT, None) {
+                Ok(out) if out.timed_out() => (false, format!("pip install took longer than {} seconds", INSTALL_TIMEOUT.as_secs()), None),
+                Ok(out) => (
+                    out.status.is_some_and(|status| status.success()),
+                    String::from_utf8_lossy(&out.stderr).to_string(),
+                    out.status.and_then(|status| status.code()),
+                ),
+                Err(e) => {
+                    attempts.push(json!({"attempt": attempt, "error": format!("Failed to run pip: {}", e)}));
+                    return Err((json!(attempts), false));
+                }
+            };
+            // A timeout counts as the network too: it's usually a download that stalled
+            let network_error = !ok && (exit_code.is_none() || NETWORK_ERRORS.iter().any(|sign| stderr.contains(sign)));
+            attempts.push(json!({
+                "attempt": attempt,
+                "exit_code": exit_code,
+                "network_error": network_error,
+                "duration_ms": started.elapsed().as_millis() as u64,
+                "stderr": tail(&stderr, STDERR_TAIL_CHARS),
+            }));
+            if ok {
+                // The list is out of date now
+                *self.cached.lock().unwrap() = None;
+                return Ok(json!(attempts));
+            }
+            if !network_error || attempt > retry.retries {
+                return Err((json!(attempts), network_error));
+            }
+            info!("pip install hit a network error, retrying in {:?}", backoff);
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+        unreachable!("the last attempt always returns")
+    }
+}
+
+// The end of `text`, where pip puts the error that matters
+fn tail(text: &str, max_chars: usize) -> &str {
+    let skip = text.chars().count().saturating_sub(max_chars);
+    text.char_indices().nth(skip).map_or(text, |(start, _)| &text[start..])
+}
@@ -0,0 +1,41 @@
+// src/paths.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Resolves a path from a request against `root`, which must be canonical. Relative paths are
+// taken relative to it. The result is canonical too, so `..`, absolute paths and symlinks that
+// lead elsewhere are all caught by the same prefix check. Every handler taking a path from a
+// client goes through here; the error is the text of the 400.
+pub fn within(root: &Path, field: &str, requested: &str) -> Result<PathBuf, String> {
+    if requested.is_empty() || requested.contains('\0') {
+        return Err(format!("`{}` must be a non-empty path without NUL bytes", field));
+    }
+    let joined = root.join(requested);
+    // Said up front, rather than as "doesn't exist" for a path that would be refused anyway
+    if !lexically_within(root, &joined) {
+        return Err(format!("`{}` {:?} is outside the allowed directory", field, requested));
+    }
+    let path = fs::canonicalize(&joined).map_err(|e| format!("Can't resolve `{}` {:?}: {}", field, requested, e))?;
+    if !path.starts_with(root) {
+        return Err(format!("`{}` {:?} is outside the allowed directory", field, requested));
+    }
+    Ok(path)
+}
+
+// Whether `path` stays under `root` going by its components alone, before symlinks are followed
+fn lexically_within(root: &Path, path: &Path) -> bool {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                if !normal.pop() {
+                    return false;
+                }
+            }
+            Component::CurDir => {}
+            other => normal.push(other),
+        }
+    }
+    normal.starts_with(root)
+}
@@ -0,0 +1,38 @@
+// src/priority.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::process::Command;
+
+// Runs the child at the given niceness (-20 to 19, higher yields more)
+pub fn apply(command: &mut Command, nice: i32) {
+    #[cfg(unix)]
+    unsafe {
+        use std::io;
+        use std::os::unix::process::CommandExt;
+        command.pre_exec(move || {
+            // Before any privilege drop, which may forbid raising priority
+            if libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // Windows has priority classes rather than a nice scale
+        const IDLE_PRIORITY_CLASS: u32 = 0x40;
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x4000;
+        const ABOVE_NORMAL_PRIORITY_CLASS: u32 = 0x8000;
+        const HIGH_PRIORITY_CLASS: u32 = 0x80;
+        let class = match nice {
+            15.. => IDLE_PRIORITY_CLASS,
+            1..=14 => BELOW_NORMAL_PRIORITY_CLASS,
+            0 => return,
+            -14..=-1 => ABOVE_NORMAL_PRIORITY_CLASS,
+            _ => HIGH_PRIORITY_CLASS,
+        };
+        command.creation_flags(class);
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = (command, nice);
+}
@@ -0,0 +1,56 @@
+// src/privileges.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::fs;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RunAs {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+impl RunAs {
+    pub fn lookup(name: &str) -> Result<RunAs, String> {
+        let c_name = CString::new(name).map_err(|_| format!("Invalid user name: {:?}", name))?;
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(c_name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+        };
+        if rc != 0 || result.is_null() {
+            return Err(format!("Unknown user for --run-as-user: {}", name));
+        }
+        let run_as = RunAs { uid: passwd.pw_uid, gid: passwd.pw_gid };
+        // Switching to another user needs root; running as ourselves is a no-op
+        let euid = unsafe { libc::geteuid() };
+        if euid != 0 && euid != run_as.uid {
+            return Err(format!("--run-as-user {} requires the helper to run as root", name));
+        }
+        Ok(run_as)
+    }
+
+    pub fn chown(self, path: &Path) -> io::Result<()> {
+        fs::chown(path, Some(self.uid), Some(self.gid))
+    }
+
+    pub fn apply(self, command: &mut Command) {
+        let RunAs { uid, gid } = self;
+        if unsafe { libc::geteuid() } == uid {
+            return;
+        }
+        unsafe {
+            command.pre_exec(move || {
+                // Supplementary groups first, then gid, then uid: after setuid we can't change the others
+                if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+}
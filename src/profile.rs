@@ -0,0 +1,61 @@
+// src/profile.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use log::{debug, warn};
+use serde_json::Value;
+use crate::exec;
+
+const TOP_FUNCTIONS: usize = 20;
+
+// Turns a cProfile dump into JSON rows, most expensive first
+const STATS_SCRIPT: &str = r#"
+import json, pstats, sys
+stats = pstats.Stats(sys.argv[1]).stats
+rows = sorted(stats.items(), key=lambda item: item[1][3], reverse=True)[:int(sys.argv[2])]
+print(json.dumps([
+    {"function": "%s:%d(%s)" % key, "calls": value[1], "total_time": value[2], "cumulative_time": value[3]}
+    for key, value in rows
+]))
+"#;
+
+pub fn args(stats_path: &Path) -> Vec<OsString> {
+    vec!["-m".into(), "cProfile".into(), "-o".into(), stats_path.into()]
+}
+
+pub fn read_stats(mut python: Command, stats_path: &Path) -> Option<Value> {
+    if !stats_path.exists() {
+        debug!("No profile stats at {:?}", stats_path);
+        return None;
+    }
+    python.arg("-c").arg(STATS_SCRIPT).arg(stats_path).arg(TOP_FUNCTIONS.to_string());
+    let out = match exec::run(python, None, Duration::from_secs(10), None) {
+        Ok(out) => out,
+        Err(e) => {
+            warn!("Failed to read profile stats: {}", e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&out.stdout) {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            warn!("Failed to parse profile stats: {} ({})", e, String::from_utf8_lossy(&out.stderr));
+            None
+        }
+    }
+}
+
+pub fn format_text(stats: &Value) -> String {
+    let mut text = String::from("\nProfile:\n");
+    for row in stats.as_array().into_iter().flatten() {
+        text.push_str(&format!(
+            "{:>10.6}s {:>8} calls  {}\n",
+            row["cumulative_time"].as_f64().unwrap_or(0.0),
+            row["calls"].as_u64().unwrap_or(0),
+            row["function"].as_str().unwrap_or("?"),
+        ));
+    }
+    text
+}
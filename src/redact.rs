@@ -0,0 +1,90 @@
+// src/redact.rs for the Rust helper "FlurionsPythonBindings"
+
+// Masks what looks like a secret before a body goes to the log. Heuristic by nature: values of
+// keys named like "password" or "token", Bearer credentials, and long opaque strings.
+
+const MASK: &str = "[redacted]";
+// Matched case-insensitively inside a key, e.g. DB_PASSWORD or "apiKey"
+const SECRET_KEYS: &[&str] = &["password", "passwd", "secret", "token", "apikey", "api_key", "auth", "credential", "private_key"];
+// Runs of token characters at least this long that mix letters and digits
+const OPAQUE_LEN: usize = 32;
+
+// The redacted text, cut to `limit` characters
+pub fn redact(text: &str, limit: usize) -> String {
+    let masked = mask_opaque(&mask_assignments(text));
+    match masked.char_indices().nth(limit) {
+        Some((end, _)) => format!("{}... ({} bytes in all)", &masked[..end], text.len()),
+        None => masked,
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+// `key = value`, `key: value`, `"key": "value"` and `Bearer value`
+fn mask_assignments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(is_word) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+        let (word, after) = rest.split_at(end);
+        out.push_str(word);
+        rest = after;
+
+        let lower = word.to_ascii_lowercase();
+        let value_start = if lower == "bearer" {
+            (rest.starts_with(' ')).then(|| 1 + rest[1..].len() - rest[1..].trim_start().len())
+        } else if SECRET_KEYS.iter().any(|key| lower.contains(key)) {
+            // Closing quote of the key, spaces, then the separator
+            let skipped = rest.trim_start_matches(['"', '\'']).trim_start();
+            skipped
+                .strip_prefix(['=', ':'])
+                // Not a comparison like `token == other`
+                .filter(|value| !value.starts_with('='))
+                .map(|value| rest.len() - value.trim_start().len())
+        } else {
+            None
+        };
+        let Some(value_start) = value_start else { continue };
+        out.push_str(&rest[..value_start]);
+        rest = &rest[value_start..];
+        match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let len = rest[1..].find(quote).map_or(rest.len(), |len| len + 2);
+                out.push(quote);
+                out.push_str(MASK);
+                out.push(quote);
+                rest = &rest[len..];
+            }
+            _ => {
+                let len = rest.find(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '}' | ')' | '&')).unwrap_or(rest.len());
+                if len > 0 {
+                    out.push_str(MASK);
+                    rest = &rest[len..];
+                }
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn mask_opaque(text: &str) -> String {
+    let is_token = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '_' | '-');
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(is_token) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_token(c)).unwrap_or(rest.len());
+        let (run, after) = rest.split_at(end);
+        let opaque = run.len() >= OPAQUE_LEN && run.contains(|c: char| c.is_ascii_digit()) && run.contains(|c: char| c.is_ascii_alphabetic());
+        out.push_str(if opaque { MASK } else { run });
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
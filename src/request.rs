@@ -0,0 +1,318 @@
+// src/request.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::HashMap;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use crate::history;
+use crate::limits::Limits;
+
+const MAX_LABEL_CHARS: usize = 100;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunRequest {
+    // Exactly one of command or file
+    pub command: Option<String>,
+    pub file: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub stdin: Option<String>,
+    // Seconds; clamped to --max-timeout
+    pub timeout: Option<f64>,
+    // Run under cProfile and return the top functions by cumulative time
+    #[serde(default)]
+    pub profile: bool,
+    // Lines executed, peak memory and CPU time as measured inside python; see resources.rs
+    #[serde(default)]
+    pub track_resources: bool,
+    // Interpreter startup and the script itself timed separately; see timing.rs
+    #[serde(default)]
+    pub timing: bool,
+    // Only for people reading logs, history and responses; see clean_label
+    pub label: Option<String>,
+    // stderr into stdout, interleaved as a terminal would show them
+    #[serde(default)]
+    pub merge_output: bool,
+    // NDJSON output in blocks of this many bytes instead of a line at a time
+    pub chunk_size: Option<usize>,
+    // NDJSON lines carry "t_ms", when they were printed relative to the script's start
+    #[serde(default)]
+    pub timestamps: bool,
+    // Output past either of these is cut off with a marker; see limits.rs
+    pub max_lines: Option<usize>,
+    pub max_bytes: Option<usize>,
+    // Send the output with Content-Disposition: attachment, so browsers save it
+    #[serde(default)]
+    pub as_attachment: bool,
+    // For the attachment; output.txt or output.json when unset
+    pub filename: Option<String>,
+    // Answer 202 with a job id right away instead of waiting for the output
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+    // Not from the body: set for NDJSON streams, which pass on what the script writes to fd 3
+    #[serde(skip)]
+    pub progress: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineRequest {
+    pub stages: Vec<PipelineStage>,
+    // Fed to the first stage
+    pub stdin: Option<String>,
+    #[serde(default)]
+    pub continue_on_error: bool,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStage {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub timeout: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionRequest {
+    pub code: String,
+    // Past this the session is restarted
+    pub timeout: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssertRequest {
+    pub command: String,
+    pub expected_stdout: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub stdin: Option<String>,
+    pub timeout: Option<f64>,
+}
+
+impl AssertRequest {
+    // The run itself, as /api/interpreter would take it
+    pub fn run_request(&self) -> RunRequest {
+        RunRequest {
+            command: Some(self.command.clone()),
+            args: self.args.clone(),
+            env: self.env.clone(),
+            stdin: self.stdin.clone(),
+            timeout: self.timeout,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptRequest {
+    pub command: String,
+}
+
+// For POST /api/scripts
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewScriptRequest {
+    pub command: String,
+    // Runs of this script beyond that in any minute get a 429
+    pub max_per_minute: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstallRequest {
+    // Requirement specifiers, e.g. "requests" or "numpy>=1.26"
+    pub packages: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WarmupRequest {
+    // --warmup-modules when missing
+    pub modules: Option<Vec<String>>,
+}
+
+pub enum Source<'a> {
+    Command(&'a str),
+    File(&'a str),
+}
+
+impl RunRequest {
+    pub fn source(&self) -> Source<'_> {
+        match (&self.file, &self.command) {
+            (Some(file), _) => Source::File(file),
+            (None, command) => Source::Command(command.as_deref().unwrap_or_default()),
+        }
+    }
+
+    // The first option asked for that works by wrapping the script in python code
+    pub fn python_only_option(&self) -> Option<&'static str> {
+        [("profile", self.profile), ("track_resources", self.track_resources), ("timing", self.timing)]
+            .into_iter()
+            .find_map(|(name, set)| set.then_some(name))
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits { max_lines: self.max_lines, max_bytes: self.max_bytes }
+    }
+
+    // How logs and history refer to this run
+    pub fn describe(&self) -> String {
+        match self.source() {
+            Source::Command(command) => command.to_string(),
+            Source::File(file) => format!("file: {}", file),
+        }
+    }
+}
+
+pub fn parse_run_request(body: &str) -> Result<RunRequest, String> {
+    let request = check_run_options(parse(body)?)?;
+    if request.command.is_some() == request.file.is_some() {
+        return Err("Expected exactly one of `command` or `file`".to_string());
+    }
+    Ok(request)
+}
+
+pub fn parse_script_request(body: &str) -> Result<ScriptRequest, String> {
+    parse(body)
+}
+
+pub fn parse_new_script_request(body: &str) -> Result<NewScriptRequest, String> {
+    let request: NewScriptRequest = parse(body)?;
+    if request.max_per_minute == Some(0) {
+        return Err("Invalid field `max_per_minute`: must be at least 1".to_string());
+    }
+    Ok(request)
+}
+
+// A run of a stored script: everything /api/interpreter takes but the code itself. An empty
+// body runs it without args or stdin.
+pub fn parse_script_run_request(body: &str) -> Result<RunRequest, String> {
+    if body.trim().is_empty() {
+        return Ok(RunRequest::default());
+    }
+    let request = check_run_options(parse(body)?)?;
+    if request.command.is_some() || request.file.is_some() {
+        return Err("A stored script is run by its id, without `command` or `file`".to_string());
+    }
+    Ok(request)
+}
+
+// What /api/interpreter and stored script runs have in common past the code itself
+fn check_run_options(mut request: RunRequest) -> Result<RunRequest, String> {
+    request.label = request.label.as_deref().map(clean_label);
+    if request.chunk_size == Some(0) {
+        return Err("Invalid field `chunk_size`: must be at least 1".to_string());
+    }
+    if request.max_lines == Some(0) {
+        return Err("Invalid field `max_lines`: must be at least 1".to_string());
+    }
+    if let Some(filename) = &request.filename {
+        if !request.as_attachment {
+            return Err("`filename` only applies with `as_attachment`".to_string());
+        }
+        // It goes in a header as is, and browsers take it as a name to save under
+        let bad = |c: char| c.is_control() || matches!(c, '"' | '/' | '\\');
+        if filename.trim().is_empty() || filename.chars().any(bad) || filename.len() > 255 {
+            return Err("Invalid field `filename`: must be a plain file name without quotes, slashes or control characters".to_string());
+        }
+    }
+    Ok(request)
+}
+
+pub fn parse_pipeline_request(body: &str) -> Result<PipelineRequest, String> {
+    let mut request: PipelineRequest = parse(body)?;
+    request.label = request.label.as_deref().map(clean_label);
+    Ok(request)
+}
+
+// Labels end up in log lines and an X-Label header, so they're kept short and on one line
+fn clean_label(label: &str) -> String {
+    let label: String = label.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    history::truncate(label.trim(), MAX_LABEL_CHARS)
+}
+
+pub fn parse_assert_request(body: &str) -> Result<AssertRequest, String> {
+    parse(body)
+}
+
+// An empty body is fine and means the configured modules
+pub fn parse_warmup_request(body: &str) -> Result<WarmupRequest, String> {
+    if body.trim().is_empty() {
+        return Ok(WarmupRequest::default());
+    }
+    parse(body)
+}
+
+pub fn parse_install_request(body: &str) -> Result<InstallRequest, String> {
+    let request: InstallRequest = parse(body)?;
+    if request.packages.is_empty() {
+        return Err("Invalid field `packages`: must not be empty".to_string());
+    }
+    // They go on pip's command line, where these would be options
+    if let Some(i) = request.packages.iter().position(|package| package.trim().is_empty() || package.starts_with('-')) {
+        return Err(format!("Invalid field `packages[{}]`: must be a requirement like \"requests>=2\", not an option", i));
+    }
+    Ok(request)
+}
+
+pub fn parse_session_request(body: &str) -> Result<SessionRequest, String> {
+    parse(body)
+}
+
+// Returns the text sent back with the 400 on failure, naming the offending field where possible.
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, String> {
+    let deserializer = &mut serde_json::Deserializer::from_str(body);
+    let value = serde_path_to_error::deserialize(&mut *deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        let inner = e.into_inner();
+        if inner.is_syntax() || inner.is_eof() {
+            format!("Invalid JSON: {}", inner)
+        } else if path == "command" || path.ends_with(".command") || path == "code" {
+            // Never stringify numbers or objects into code
+            format!("{} must be a string", path)
+        } else if path == "." || inner.to_string().starts_with("unknown field") {
+            inner.to_string()
+        } else {
+            format!("Invalid field `{}`: {}", path, inner)
+        }
+    })?;
+    // Anything but whitespace after the object
+    deserializer.end().map_err(|e| format!("Invalid JSON: {}", e))?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_non_string_commands() {
+        for command in ["123", "1.5", "true", r#"["echo", "hi"]"#, r#"{"a": 1}"#] {
+            let body = format!(r#"{{"command": {}}}"#, command);
+            assert_eq!(parse_run_request(&body).err().as_deref(), Some("command must be a string"), "{}", body);
+        }
+        let pipeline = r#"{"stages": [{"command": "echo hi"}, {"command": 42}]}"#;
+        assert_eq!(parse_pipeline_request(pipeline).err().as_deref(), Some("stages[1].command must be a string"));
+        assert!(parse_run_request(r#"{"command": "print(123)"}"#).is_ok());
+    }
+
+    #[test]
+    fn rejects_trailing_characters() {
+        for body in [r#"{"command": "x"} garbage"#, r#"{"command": "x"}{"command": "y"}"#, r#"{"command": "x"},"#] {
+            let error = parse_run_request(body).unwrap_err();
+            assert!(error.starts_with("Invalid JSON: trailing characters"), "{}: {}", body, error);
+        }
+        assert!(parse_run_request("  {\"command\": \"x\"}\r\n").is_ok());
+    }
+}
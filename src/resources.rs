@@ -0,0 +1,87 @@
+// src/resources.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use log::{debug, warn};
+use serde_json::Value;
+
+// Runs argv[3:] with in-process counters going: lines executed in the user's own file (argv[2]),
+// peak traced memory and CPU time. They go to the file in argv[1] rather than stdout, which
+// stays the script's. Tracing every line is slow, hence opt-in.
+const HARNESS: &str = r#"
+import json, os, runpy, sys, threading, time, tracemalloc, traceback
+
+out_path, script = sys.argv[1], sys.argv[2]
+sys.argv = sys.argv[3:]
+sys.path[0] = os.path.dirname(os.path.abspath(sys.argv[0]))
+lines = 0
+
+def local(frame, event, arg):
+    global lines
+    if event == "line":
+        lines += 1
+    return local
+
+def tracer(frame, event, arg):
+    # Only the user's code; the standard library would swamp the count
+    return local if frame.f_code.co_filename == script else None
+
+def report():
+    sys.settrace(None)
+    cpu_time = time.process_time() - started
+    peak = tracemalloc.get_traced_memory()[1]
+    tracemalloc.stop()
+    with open(out_path, "w") as f:
+        json.dump({"lines_executed": lines, "peak_memory_bytes": peak, "cpu_time": cpu_time}, f)
+
+tracemalloc.start()
+started = time.process_time()
+threading.settrace(tracer)
+sys.settrace(tracer)
+try:
+    runpy.run_path(sys.argv[0], run_name="__main__")
+except SystemExit:
+    report()
+    raise
+except BaseException as e:
+    report()
+    # Start the traceback at what was run, leaving this harness and runpy out
+    tb = e.__traceback__
+    while tb is not None and tb.tb_frame.f_code.co_filename != sys.argv[0]:
+        tb = tb.tb_next
+    traceback.print_exception(type(e), e, tb)
+    sys.exit(1)
+else:
+    report()
+"#;
+
+// Writes the harness into a run directory and returns what goes before the launch args
+pub fn install(dir: &Path, stats_path: &Path, script: &Path) -> io::Result<Vec<OsString>> {
+    let harness = dir.join("resources.py");
+    fs::write(&harness, HARNESS)?;
+    Ok(vec![harness.into(), stats_path.into(), script.into()])
+}
+
+pub fn stats_path(dir: &Path) -> PathBuf {
+    dir.join("resources.json")
+}
+
+// None when the script never got as far as reporting, e.g. it was killed
+pub fn read(stats_path: &Path) -> Option<Value> {
+    let contents = match fs::read(stats_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug!("No resource stats at {:?}: {}", stats_path, e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&contents) {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            warn!("Failed to parse resource stats: {}", e);
+            None
+        }
+    }
+}
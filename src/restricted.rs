@@ -0,0 +1,40 @@
+// src/restricted.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Runs argv[1] with the risky builtins swapped for stubs that raise PermissionError.
+// Guards against accidents, not attackers: introspection can still reach the real builtins.
+const HARNESS: &str = r#"
+import builtins, sys, traceback
+
+BLOCKED = ("open", "exec", "eval", "compile", "__import__", "input", "breakpoint", "help")
+
+def blocked(name):
+    def stub(*args, **kwargs):
+        raise PermissionError("%s() is not available with --restricted-builtins" % name)
+    return stub
+
+path = sys.argv[1]
+sys.argv = sys.argv[1:]
+with open(path, encoding="utf-8") as f:
+    code = compile(f.read(), path, "exec")
+safe = {name: value for name, value in vars(builtins).items() if name not in BLOCKED}
+safe.update({name: blocked(name) for name in BLOCKED})
+try:
+    exec(code, {"__builtins__": safe, "__name__": "__main__", "__file__": path})
+except SystemExit:
+    raise
+except BaseException as e:
+    # Leave this harness out of the traceback
+    traceback.print_exception(type(e), e, e.__traceback__.tb_next)
+    sys.exit(1)
+"#;
+
+// Writes the harness into a run directory
+pub fn install(dir: &Path) -> io::Result<PathBuf> {
+    let path = dir.join("harness.py");
+    fs::write(&path, HARNESS)?;
+    Ok(path)
+}
@@ -0,0 +1,121 @@
+// src/scripts.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::debug;
+use crate::{ids, memory};
+
+// Scripts submitted once and then run by id. Each is forgotten `ttl` after it was last used, and
+// the least recently used goes to make room past `max`; a run that is still going keeps its own
+// reference, so expiry never pulls a script out from under it.
+pub struct Scripts<T> {
+    scripts: Mutex<HashMap<String, Stored<T>>>,
+    ttl: Duration,
+    max: usize,
+}
+
+struct Stored<T> {
+    script: Arc<T>,
+    used: Instant,
+    size: usize,
+}
+
+impl<T> Scripts<T> {
+    pub fn new(ttl: Duration, max: usize) -> Scripts<T> {
+        Scripts { scripts: Mutex::new(HashMap::new()), ttl, max }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    // `source` is only for sizing the entry
+    pub fn add(&self, script: T, source: &str) -> String {
+        let mut scripts = self.scripts.lock().unwrap();
+        self.expire(&mut scripts);
+        while scripts.len() >= self.max {
+            let Some(id) = least_recently_used(&scripts) else {
+                break;
+            };
+            debug!("Forgetting stored script {} to stay within --max-stored-scripts", id);
+            scripts.remove(&id);
+        }
+        let id = loop {
+            // Unguessable, so one client can't run another's scripts
+            let id = ids::random();
+            if !scripts.contains_key(&id) {
+                break id;
+            }
+        };
+        scripts.insert(id.clone(), Stored { script: Arc::new(script), used: Instant::now(), size: memory::estimate(&[&id, source]) });
+        id
+    }
+
+    // None once the script is unknown or expired; otherwise its clock starts over
+    pub fn get(&self, id: &str) -> Option<Arc<T>> {
+        let mut scripts = self.scripts.lock().unwrap();
+        self.expire(&mut scripts);
+        let stored = scripts.get_mut(id)?;
+        stored.used = Instant::now();
+        Some(Arc::clone(&stored.script))
+    }
+
+    fn expire(&self, scripts: &mut HashMap<String, Stored<T>>) {
+        scripts.retain(|_, stored| stored.used.elapsed() < self.ttl);
+    }
+}
+
+// A script's own max_per_minute: at most `max` runs start in any minute
+pub struct RunLimit {
+    max: usize,
+    started: Mutex<VecDeque<Instant>>,
+}
+
+impl RunLimit {
+    pub fn per_minute(max: usize) -> RunLimit {
+        RunLimit { max, started: Mutex::new(VecDeque::new()) }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    // Counts this run if there's room for it, otherwise says how long until there is
+    pub fn try_start(&self) -> Result<(), Duration> {
+        const WINDOW: Duration = Duration::from_secs(60);
+        let mut started = self.started.lock().unwrap();
+        while started.front().is_some_and(|start| start.elapsed() >= WINDOW) {
+            started.pop_front();
+        }
+        if started.len() >= self.max {
+            // Checked to be at least 1 in request.rs, so there's a front
+            return Err(WINDOW.saturating_sub(started[0].elapsed()));
+        }
+        started.push_back(Instant::now());
+        Ok(())
+    }
+}
+
+fn least_recently_used<T>(scripts: &HashMap<String, Stored<T>>) -> Option<String> {
+    scripts.iter().min_by_key(|(_, stored)| stored.used).map(|(id, _)| id.clone())
+}
+
+impl<T> memory::Store for Scripts<T> {
+    fn name(&self) -> &'static str {
+        "stored script"
+    }
+
+    fn bytes(&self) -> usize {
+        self.scripts.lock().unwrap().values().map(|stored| stored.size).sum()
+    }
+
+    fn oldest(&self) -> Option<Instant> {
+        self.scripts.lock().unwrap().values().map(|stored| stored.used).min()
+    }
+
+    fn evict_oldest(&self) -> usize {
+        let mut scripts = self.scripts.lock().unwrap();
+        least_recently_used(&scripts).and_then(|id| scripts.remove(&id)).map_or(0, |stored| stored.size)
+    }
+}
@@ -0,0 +1,174 @@
+// src/sessions.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use crate::{exec, ids};
+
+// A REPL that keeps its globals between snippets. Takes one JSON request per line on stdin
+// and answers each with one JSON line on stdout; the snippet's own output is captured.
+const HARNESS: &str = r#"
+import io, json, sys, traceback
+channel = sys.stdout
+namespace = {"__name__": "__main__"}
+for line in sys.stdin:
+    code = json.loads(line)["code"]
+    out, err = io.StringIO(), io.StringIO()
+    sys.stdout, sys.stderr = out, err
+    ok = True
+    try:
+        exec(compile(code, "<session>", "exec"), namespace)
+    except BaseException as e:
+        traceback.print_exception(type(e), e, e.__traceback__.tb_next)
+        ok = False
+    finally:
+        sys.stdout, sys.stderr = channel, sys.__stderr__
+    channel.write(json.dumps({"stdout": out.getvalue(), "stderr": err.getvalue(), "ok": ok}) + "\n")
+    channel.flush()
+"#;
+
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Arc<Mutex<Session>>>>,
+    max: Mutex<Option<usize>>,
+}
+
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    replies: Receiver<String>,
+    last_activity: Instant,
+}
+
+pub enum Outcome {
+    Replied(Value),
+    // The watchdog replaced the interpreter, losing its state, after it
+    // didn't answer in time or went away
+    TimedOut,
+    Died,
+}
+
+impl Sessions {
+    pub fn new(max: Option<usize>) -> Sessions {
+        Sessions { sessions: Mutex::new(HashMap::new()), max: Mutex::new(max) }
+    }
+
+    // For /admin/reload; sessions over a lowered limit stay open
+    pub fn set_max(&self, max: Option<usize>) {
+        *self.max.lock().unwrap() = max;
+    }
+
+    // None when `max` sessions are already open
+    pub fn create(&self, python: Command) -> io::Result<Option<String>> {
+        // Held while spawning, so concurrent creates can't overshoot the limit
+        let mut sessions = self.sessions.lock().unwrap();
+        if self.max.lock().unwrap().is_some_and(|max| sessions.len() >= max) {
+            return Ok(None);
+        }
+        let session = spawn(python)?;
+        let id = ids::random();
+        sessions.insert(id.clone(), Arc::new(Mutex::new(session)));
+        Ok(Some(id))
+    }
+
+    // None for an unknown session. `python` is only used if the session has to be restarted.
+    pub fn exec(&self, id: &str, code: &str, timeout: Duration, python: Command) -> Option<io::Result<Outcome>> {
+        let session = self.get(id)?;
+        // One snippet at a time per session
+        let mut session = session.lock().unwrap();
+        session.last_activity = Instant::now();
+        let request = json!({"code": code}).to_string();
+        let sent = writeln!(session.stdin, "{}", request).and_then(|_| session.stdin.flush());
+        let outcome = match sent.map(|_| session.replies.recv_timeout(timeout)) {
+            Ok(Ok(line)) => match serde_json::from_str(&line) {
+                Ok(reply) => {
+                    session.last_activity = Instant::now();
+                    return Some(Ok(Outcome::Replied(reply)));
+                }
+                Err(e) => {
+                    warn!("Session {} sent an unreadable reply: {}", id, e);
+                    Outcome::Died
+                }
+            },
+            Ok(Err(RecvTimeoutError::Timeout)) => Outcome::TimedOut,
+            Ok(Err(RecvTimeoutError::Disconnected)) | Err(_) => Outcome::Died,
+        };
+
+        match outcome {
+            Outcome::TimedOut => warn!("Restarting session {}, which didn't answer within {:?}", id, timeout),
+            _ => warn!("Restarting session {}, whose interpreter went away", id),
+        }
+        // Dropping the old session kills it
+        match spawn(python) {
+            Ok(fresh) => *session = fresh,
+            Err(e) => {
+                drop(session);
+                self.close(id);
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(outcome))
+    }
+
+    // None for an unknown session
+    pub fn status(&self, id: &str) -> Option<Value> {
+        let session = self.get(id)?;
+        // A snippet holds the lock for as long as it runs
+        let status = match session.try_lock() {
+            Ok(session) => json!({"session_id": id, "busy": false, "idle_ms": session.last_activity.elapsed().as_millis() as u64}),
+            Err(_) => json!({"session_id": id, "busy": true}),
+        };
+        Some(status)
+    }
+
+    // False for an unknown session
+    pub fn close(&self, id: &str) -> bool {
+        // The interpreter is killed when the last holder lets go of it
+        self.sessions.lock().unwrap().remove(id).is_some()
+    }
+
+    // Stops every session not in the middle of a snippet; those go when their snippet ends
+    pub fn close_all(&self) {
+        self.sessions.lock().unwrap().clear();
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<Mutex<Session>>> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        exec::kill(&mut self.child);
+        exec::untrack(&self.child);
+    }
+}
+
+fn spawn(mut python: Command) -> io::Result<Session> {
+    python.arg("-c").arg(HARNESS).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        python.process_group(0);
+    }
+    let mut child = python.spawn()?;
+    exec::track(&child);
+    let stdin = child.stdin.take().ok_or_else(|| io::Error::other("session has no stdin"))?;
+    let stdout = child.stdout.take().ok_or_else(|| io::Error::other("session has no stdout"))?;
+    let (sender, replies) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    debug!("Started session interpreter {}", child.id());
+    Ok(Session { child, stdin, replies, last_activity: Instant::now() })
+}
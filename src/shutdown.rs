@@ -0,0 +1,46 @@
+// src/shutdown.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::process;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use log::{error, info, warn};
+use crate::{exec, logfile, Server};
+
+// Takes SIGTERM and SIGINT off every thread and handles them on one of its own: running scripts
+// get SIGTERM, then SIGKILL after --shutdown-grace, and the helper exits. With --log-file, SIGHUP
+// reopens the log instead; otherwise it's left alone. Must be called before any other thread is
+// started, as those inherit the signal mask.
+pub fn install(server: Arc<Server>) {
+    let mut signals: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        if logfile::is_open() {
+            libc::sigaddset(&mut signals, libc::SIGHUP);
+        }
+        // Children get a clean mask again when they're spawned
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+    }
+    thread::spawn(move || {
+        let mut signal = 0;
+        loop {
+            if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+                error!("Failed to wait for signals, shutting down without stopping scripts gracefully");
+                return;
+            }
+            if signal != libc::SIGHUP {
+                break;
+            }
+            match logfile::reopen() {
+                Ok(()) => info!("Reopened the log file on SIGHUP"),
+                Err(e) => warn!("Failed to reopen the log file, still writing to the old one: {}", e),
+            }
+        }
+        info!("Shutting down on signal {}", signal);
+        server.sessions.close_all();
+        exec::terminate_all(server.config().shutdown_grace);
+        process::exit(0);
+    });
+}
@@ -0,0 +1,91 @@
+// src/timing.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+use serde::Deserialize;
+
+// Runs argv[2:] and writes when it started and ended, in nanoseconds since the epoch, to
+// argv[1]. Goes first on the command line, so whatever comes before the script's first line
+// (interpreter start, site imports) is startup and everything after is the script.
+const HARNESS: &str = r#"
+import json, os, runpy, sys, time, traceback
+started = time.time_ns()
+out_path = sys.argv[1]
+sys.argv = sys.argv[2:]
+sys.path[0] = os.path.dirname(os.path.abspath(sys.argv[0]))
+
+def report():
+    with open(out_path, "w") as f:
+        json.dump({"started": started, "ended": time.time_ns()}, f)
+
+try:
+    runpy.run_path(sys.argv[0], run_name="__main__")
+except SystemExit:
+    report()
+    raise
+except BaseException as e:
+    report()
+    # Start the traceback at what was run, leaving this harness and runpy out
+    tb = e.__traceback__
+    while tb is not None and tb.tb_frame.f_code.co_filename != sys.argv[0]:
+        tb = tb.tb_next
+    traceback.print_exception(type(e), e, tb)
+    sys.exit(1)
+else:
+    report()
+"#;
+
+#[derive(Deserialize)]
+struct Marks {
+    started: u128,
+    ended: u128,
+}
+
+pub struct Split {
+    pub startup_ms: f64,
+    pub script_ms: f64,
+}
+
+// Writes the harness into a run directory and returns what goes before the launch args
+pub fn install(dir: &Path, marks_path: &Path) -> io::Result<Vec<OsString>> {
+    let harness = dir.join("timing.py");
+    fs::write(&harness, HARNESS)?;
+    Ok(vec![harness.into(), marks_path.into()])
+}
+
+pub fn marks_path(dir: &Path) -> PathBuf {
+    dir.join("timing.json")
+}
+
+// Wall clock, as the harness's time.time_ns() is
+pub fn now() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or_default()
+}
+
+// `spawned` is now() just before python started. None when the script never got as far as
+// reporting, e.g. it was killed.
+pub fn read(marks_path: &Path, spawned: u128) -> Option<Split> {
+    let contents = match fs::read(marks_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug!("No timing marks at {:?}: {}", marks_path, e);
+            return None;
+        }
+    };
+    let marks: Marks = match serde_json::from_slice(&contents) {
+        Ok(marks) => marks,
+        Err(e) => {
+            warn!("Failed to parse timing marks: {}", e);
+            return None;
+        }
+    };
+    let ms = |nanos: u128| nanos as f64 / 1e6;
+    Some(Split {
+        startup_ms: ms(marks.started.saturating_sub(spawned)),
+        script_ms: ms(marks.ended.saturating_sub(marks.started)),
+    })
+}
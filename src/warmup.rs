@@ -0,0 +1,35 @@
+// src/warmup.rs for the Rust helper "FlurionsPythonBindings"
+
+use std::process::Command;
+use std::time::Duration;
+use log::debug;
+use serde_json::{json, Value};
+use crate::exec;
+
+// Imports each module named in argv[1] (a JSON list) and reports how that went
+const IMPORTER: &str = r#"
+import importlib, json, sys
+imported, failed = [], {}
+for name in json.loads(sys.argv[1]):
+    try:
+        importlib.import_module(name)
+        imported.append(name)
+    except BaseException as e:
+        failed[name] = "%s: %s" % (type(e).__name__, e)
+print(json.dumps({"imported": imported, "failed": failed}))
+"#;
+
+// There's no pool of warm interpreters to import into, so this primes what a fresh one reuses:
+// the modules' bytecode caches and the OS's file cache. {"imported": [...], "failed": {name: error}}
+pub fn import(mut python: Command, modules: &[String], timeout: Duration) -> Result<Value, String> {
+    debug!("Warming up {:?}", modules);
+    python.arg("-c").arg(IMPORTER).arg(json!(modules).to_string());
+    let out = exec::run(python, None, timeout, None).map_err(|e| format!("Failed to run python: {}", e))?;
+    if out.timed_out() {
+        return Err(format!("Importing took longer than {:?}", timeout));
+    }
+    // The last line, in case a module printed something while being imported
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let report = stdout.lines().last().unwrap_or_default();
+    serde_json::from_str(report).map_err(|_| format!("Warm-up failed: {}", String::from_utf8_lossy(&out.stderr).trim()))
+}
@@ -0,0 +1,163 @@
+// src/websocket.rs for the Rust helper "FlurionsPythonBindings"
+
+// RFC 6455 framing, enough for a text-only conversation with one client
+
+use std::io::{self, Read, Write};
+use crate::{base64, sha1};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// Close codes
+pub const NORMAL: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+const UNSUPPORTED: u16 = 1003;
+const INVALID_TEXT: u16 = 1007;
+const TOO_BIG: u16 = 1009;
+
+// What the handshake answers a Sec-WebSocket-Key with
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1::digest(format!("{}{}", key.trim(), GUID).as_bytes()))
+}
+
+pub enum Message {
+    Text(String),
+    Ping(Vec<u8>),
+    // With the client's close code, when it gave one
+    Close(Option<u16>),
+}
+
+pub enum Error {
+    Io(io::Error),
+    // The client broke the protocol; the connection is closed with this code and reason
+    Protocol(u16, &'static str),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+// Reads whole messages, putting fragments back together; messages over `max_len` bytes are refused
+pub struct Reader {
+    max_len: usize,
+    partial: Option<Vec<u8>>,
+}
+
+impl Reader {
+    pub fn new(max_len: usize) -> Reader {
+        Reader { max_len, partial: None }
+    }
+
+    pub fn read(&mut self, input: &mut impl Read) -> Result<Message, Error> {
+        loop {
+            let mut head = [0u8; 2];
+            input.read_exact(&mut head)?;
+            let fin = head[0] & 0x80 != 0;
+            let opcode = head[0] & 0x0f;
+            if head[0] & 0x70 != 0 {
+                return Err(Error::Protocol(PROTOCOL_ERROR, "reserved bits set"));
+            }
+            if head[1] & 0x80 == 0 {
+                return Err(Error::Protocol(PROTOCOL_ERROR, "client frames must be masked"));
+            }
+            let len = match head[1] & 0x7f {
+                126 => {
+                    let mut len = [0u8; 2];
+                    input.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as u64
+                }
+                127 => {
+                    let mut len = [0u8; 8];
+                    input.read_exact(&mut len)?;
+                    u64::from_be_bytes(len)
+                }
+                len => len as u64,
+            };
+            if opcode >= CLOSE && (!fin || len > 125) {
+                return Err(Error::Protocol(PROTOCOL_ERROR, "control frames must be short and unfragmented"));
+            }
+            let so_far = self.partial.as_ref().map_or(0, Vec::len) as u64;
+            if len.saturating_add(so_far) > self.max_len as u64 {
+                return Err(Error::Protocol(TOO_BIG, "message too big"));
+            }
+            let mut mask = [0u8; 4];
+            input.read_exact(&mut mask)?;
+            let mut payload = vec![0u8; len as usize];
+            input.read_exact(&mut payload)?;
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+
+            let whole = match (opcode, self.partial.take()) {
+                (TEXT, None) => payload,
+                (CONTINUATION, Some(mut partial)) => {
+                    partial.extend_from_slice(&payload);
+                    partial
+                }
+                (TEXT, Some(_)) => return Err(Error::Protocol(PROTOCOL_ERROR, "expected a continuation frame")),
+                (CONTINUATION, None) => return Err(Error::Protocol(PROTOCOL_ERROR, "continuation frame without a message")),
+                (BINARY, _) => return Err(Error::Protocol(UNSUPPORTED, "only text messages are supported")),
+                (CLOSE, _) => return Ok(Message::Close(payload.get(..2).map(|code| u16::from_be_bytes([code[0], code[1]])))),
+                (PING, partial) => {
+                    // Control frames can come between a message's fragments
+                    self.partial = partial;
+                    return Ok(Message::Ping(payload));
+                }
+                (PONG, partial) => {
+                    self.partial = partial;
+                    continue;
+                }
+                _ => return Err(Error::Protocol(PROTOCOL_ERROR, "unknown opcode")),
+            };
+            if !fin {
+                self.partial = Some(whole);
+                continue;
+            }
+            return match String::from_utf8(whole) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(Error::Protocol(INVALID_TEXT, "text message isn't UTF-8")),
+            };
+        }
+    }
+}
+
+pub fn send_text(output: &mut impl Write, text: &str) -> io::Result<()> {
+    write_frame(output, TEXT, text.as_bytes())
+}
+
+pub fn send_pong(output: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    write_frame(output, PONG, payload)
+}
+
+pub fn send_close(output: &mut impl Write, code: u16, reason: &str) -> io::Result<()> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    write_frame(output, CLOSE, &payload)
+}
+
+// Server frames go unmasked, in one piece
+fn write_frame(output: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    output.write_all(&frame)
+}
@@ -0,0 +1,1998 @@
+// tests/server.rs for the Rust helper "FlurionsPythonBindings"
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A helper instance on a free port, running scripts through tests/stub_python.sh
+struct TestServer {
+    child: Child,
+    port: u16,
+    // Everything logged after the listening line
+    log: Arc<Mutex<String>>,
+}
+
+impl TestServer {
+    fn start(args: &[&str]) -> TestServer {
+        TestServer::start_with_env(args, &[])
+    }
+
+    fn start_with_env(args: &[&str], env: &[(&str, &str)]) -> TestServer {
+        let mut helper = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"));
+        helper.envs(env.iter().copied());
+        TestServer::start_command(helper, args)
+    }
+
+    // `command` runs the helper with the usual test flags, then `args`, appended
+    fn start_command(mut command: Command, args: &[&str]) -> TestServer {
+        let stub = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/stub_python.sh");
+        let mut child = command
+            .args(["--info", "--port", "0", "--python", stub])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to start the helper");
+
+        let mut log = BufReader::new(child.stderr.take().unwrap());
+        let mut line = String::new();
+        let port = loop {
+            line.clear();
+            if log.read_line(&mut line).unwrap() == 0 {
+                panic!("helper exited before listening");
+            }
+            if let Some((_, addr)) = line.trim().split_once("listening on ") {
+                break addr.rsplit(':').next().unwrap().parse().unwrap();
+            }
+        };
+        // Keep draining the log so the helper never blocks on a full pipe
+        let kept = Arc::new(Mutex::new(String::new()));
+        let sink = Arc::clone(&kept);
+        thread::spawn(move || {
+            for line in log.lines() {
+                let Ok(line) = line else { break };
+                let mut sink = sink.lock().unwrap();
+                sink.push_str(&line);
+                sink.push('\n');
+            }
+        });
+        TestServer { child, port, log: kept }
+    }
+
+    // Sends raw bytes and returns the whole response; the helper closes after one response
+    fn send(&self, raw: &str) -> Response {
+        self.send_bytes(raw.as_bytes())
+    }
+
+    fn send_bytes(&self, raw: &[u8]) -> Response {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream.write_all(raw).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        Response::parse(&response)
+    }
+
+    fn post(&self, path: &str, body: &str) -> Response {
+        self.send(&format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", path, body.len(), body))
+    }
+
+    fn get(&self, path: &str) -> Response {
+        self.send(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Response {
+    status: u32,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    fn parse(raw: &str) -> Response {
+        let (head, body) = raw.split_once("\r\n\r\n").expect("response without a header terminator");
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+        Response { status, headers, body: body.to_string() }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+#[test]
+fn root_page_says_helper_is_running() {
+    let server = TestServer::start(&[]);
+    let response = server.get("/");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("text/html"));
+    assert!(response.body.contains("Helper is running."));
+}
+
+#[test]
+fn sends_configurable_headers_with_the_root_page_only() {
+    let server = TestServer::start(&[]);
+    let root = server.get("/");
+    assert_eq!(root.header("cache-control"), Some("no-store"));
+    assert_eq!(root.header("x-content-type-options"), Some("nosniff"));
+    let api = server.post("/api/interpreter", r#"{"command": "true"}"#);
+    assert_eq!(api.header("cache-control"), None);
+
+    let server = TestServer::start(&["--no-root-headers", "--root-header", "Cache-Control: max-age=60"]);
+    let root = server.get("/");
+    assert_eq!(root.header("cache-control"), Some("max-age=60"));
+    assert_eq!(root.header("x-content-type-options"), None);
+}
+
+#[test]
+fn runs_a_valid_command() {
+    let server = TestServer::start(&[]);
+    let response = server.post("/api/interpreter", r#"{"command": "echo hello"}"#);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "hello\n");
+}
+
+#[test]
+fn rejects_invalid_json() {
+    let server = TestServer::start(&[]);
+    let response = server.post("/api/interpreter", r#"{"command": "#);
+    assert_eq!(response.status, 400);
+    assert!(response.body.starts_with("Bad Request: Invalid JSON"), "{}", response.body);
+    // A complete object isn't enough when something follows it
+    let trailing = server.post("/api/interpreter", r#"{"command": "echo hi"} garbage"#);
+    assert_eq!(trailing.status, 400);
+    assert!(trailing.body.starts_with("Bad Request: Invalid JSON"), "{}", trailing.body);
+}
+
+#[test]
+fn rejects_a_missing_body() {
+    let server = TestServer::start(&[]);
+    let response = server.send("POST /api/interpreter HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 400);
+    assert_eq!(response.body, "Bad Request: Missing body");
+}
+
+#[test]
+fn rejects_non_string_commands() {
+    let server = TestServer::start(&[]);
+    for command in ["123", "[\"echo\"]", "{\"a\": 1}"] {
+        let response = server.post("/api/interpreter", &format!(r#"{{"command": {}}}"#, command));
+        assert_eq!(response.status, 400);
+        assert_eq!(response.body, "Bad Request: command must be a string");
+    }
+}
+
+#[test]
+fn rejects_malformed_request_lines() {
+    let server = TestServer::start(&[]);
+    for line in ["GET /", "GET  / HTTP/1.1", "GET / HTTP/1.1 extra", ""] {
+        let response = server.send(&format!("{}\r\n\r\n", line));
+        assert_eq!(response.status, 400, "{:?}", line);
+        assert!(response.headers.iter().any(|(name, _)| name == "content-length"));
+    }
+}
+
+#[test]
+fn silent_runs_return_an_empty_200_by_default() {
+    let server = TestServer::start(&[]);
+    let response = server.post("/api/interpreter", r#"{"command": "true"}"#);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "");
+}
+
+#[test]
+fn silent_runs_can_return_204() {
+    let server = TestServer::start(&["--no-content-on-empty"]);
+    let response = server.post("/api/interpreter", r#"{"command": "true"}"#);
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("content-length"), None);
+    assert_eq!(response.body, "");
+
+    let response = server.post("/api/interpreter", r#"{"command": "echo failing; exit 1"}"#);
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn pretty_prints_json_on_request() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"stages": [{"command": "echo hi"}]}"#;
+    let compact = server.post("/api/pipeline", body);
+    assert!(!compact.body.contains('\n'), "{}", compact.body);
+    let pretty = server.post("/api/pipeline?pretty=1", body);
+    assert_eq!(pretty.status, 200);
+    assert!(pretty.body.contains("\n  \"completed\": true"), "{}", pretty.body);
+}
+
+#[test]
+fn cancels_a_running_job() {
+    let server = TestServer::start(&[]);
+    let started = server.post("/api/interpreter", r#"{"command": "sleep 30", "async": true}"#);
+    assert_eq!(started.status, 202);
+    let job: serde_json::Value = serde_json::from_str(&started.body).unwrap();
+    let id = job["job_id"].as_str().unwrap();
+
+    let cancelled = server.post(&format!("/api/cancel/{}", id), "");
+    assert_eq!(cancelled.status, 200, "{}", cancelled.body);
+    // Once the child is gone the job is no longer cancellable
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.post(&format!("/api/cancel/{}", id), "").status != 404 {
+        assert!(Instant::now() < deadline, "job was still running after cancel");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(server.post("/api/cancel/unknown", "").status, 404);
+}
+
+#[test]
+fn polls_a_job_until_it_is_done() {
+    let server = TestServer::start(&[]);
+    let started = server.post("/api/jobs", r#"{"command": "echo from a job"}"#);
+    assert_eq!(started.status, 202);
+    let job: serde_json::Value = serde_json::from_str(&started.body).unwrap();
+    let path = format!("/api/jobs/{}", job["job_id"].as_str().unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        let response = server.get(&path);
+        assert_eq!(response.status, 200);
+        let status: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        if status["status"] == "done" {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "job never finished: {}", response.body);
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(status["result"]["stdout"], "from a job\n");
+    assert_eq!(server.get("/api/jobs/unknown").status, 404);
+}
+
+#[test]
+fn accepts_a_body_with_a_bom() {
+    let server = TestServer::start(&[]);
+    let response = server.post("/api/interpreter", "\u{feff}{\"command\": \"echo hello\"}");
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body, "hello\n");
+}
+
+#[test]
+fn rejects_too_many_header_lines() {
+    let server = TestServer::start(&["--max-header-lines", "3"]);
+    let headers = "X-Filler: 1\r\n".repeat(3);
+    let response = server.send(&format!("GET /api/jobs/unknown HTTP/1.1\r\n{}\r\n", headers));
+    assert_eq!(response.status, 404);
+    let response = server.send(&format!("GET /api/jobs/unknown HTTP/1.1\r\n{}X-One-Too-Many: 1\r\n\r\n", headers));
+    assert_eq!(response.status, 431);
+}
+
+#[test]
+fn timeouts_keep_partial_output() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "echo started; sleep 30", "timeout": 0.5}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    assert_eq!(response.status, 408);
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["partial_stdout"], "started\n");
+    assert_eq!(result["terminated"], true);
+}
+
+#[test]
+fn records_how_a_pipeline_ended_in_the_history() {
+    let server = TestServer::start(&["--token", "secret", "--timeout", "0.3"]);
+    let authorized = |request: &str, body: &str| {
+        server.send(&format!("{} HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}", request, body.len(), body))
+    };
+    assert_eq!(authorized("POST /api/pipeline", r#"{"stages": [{"command": "echo a"}, {"command": "cat"}]}"#).status, 200);
+    assert_eq!(authorized("POST /api/pipeline", r#"{"stages": [{"command": "echo a"}, {"command": "exit 3"}]}"#).status, 200);
+    assert_eq!(authorized("POST /api/pipeline", r#"{"stages": [{"command": "sleep 5"}]}"#).status, 200);
+    let history: serde_json::Value = serde_json::from_str(&authorized("GET /admin/history", "").body).unwrap();
+    let statuses: Vec<_> = history["entries"].as_array().unwrap().iter().map(|entry| entry["status"].clone()).collect();
+    // Newest first
+    assert_eq!(statuses, [408, 500, 200]);
+}
+
+#[test]
+fn echoes_or_generates_the_request_id() {
+    let server = TestServer::start(&["--request-id-header", "X-Correlation-Id"]);
+    let response = server.send("GET /admin/history HTTP/1.1\r\nX-Correlation-Id: abc-123\r\n\r\n");
+    assert_eq!(response.header("x-correlation-id"), Some("abc-123"));
+    let response = server.get("/admin/history");
+    assert_eq!(response.header("x-correlation-id").map(str::len), Some(16));
+}
+
+#[test]
+fn spaces_out_spawns() {
+    let server = TestServer::start(&["--spawn-rate", "2"]);
+    let first = server.post("/api/interpreter", r#"{"command": "true"}"#);
+    assert_eq!(first.header("x-spawn-wait-ms"), None);
+    let second = server.post("/api/interpreter", r#"{"command": "true"}"#);
+    let waited: u64 = second.header("x-spawn-wait-ms").expect("second spawn didn't wait").parse().unwrap();
+    assert!(waited > 0 && waited <= 500, "{}", waited);
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut child = Command::new("gzip").arg("-c").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(data).unwrap();
+    child.wait_with_output().unwrap().stdout
+}
+
+fn post_gzip(server: &TestServer, body: &[u8]) -> Response {
+    let mut raw = format!("POST /api/interpreter HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+    raw.extend_from_slice(body);
+    server.send_bytes(&raw)
+}
+
+#[test]
+fn advertises_request_size_limits() {
+    let server = TestServer::start(&["--max-body-bytes", "1000"]);
+    let response = server.post("/api/interpreter", r#"{"command": "echo hi"}"#);
+    assert_eq!(response.header("x-max-body-bytes"), Some("1000"));
+    assert_eq!(response.header("x-max-script-bytes"), None);
+    // Only where there's a body to size
+    assert_eq!(server.get("/api/ping").header("x-max-body-bytes"), None);
+
+    let server = TestServer::start(&["--max-script-bytes", "500"]);
+    let response = server.post("/api/pipeline", r#"{"stages": [{"command": "echo hi"}]}"#);
+    assert_eq!(response.header("x-max-script-bytes"), Some("500"));
+}
+
+#[test]
+fn runs_scripts_uploaded_as_multipart_form_data() {
+    let server = TestServer::start(&[]);
+    // What `curl -F file=@script.sh` sends
+    let boundary = "------------------------d74496d66958873e";
+    let upload = |name: &str| {
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"script.sh\"\r\nContent-Type: application/octet-stream\r\n\r\necho uploaded\n\r\n--{b}--\r\n",
+            name,
+            b = boundary
+        );
+        server.send(&format!(
+            "POST /api/interpreter HTTP/1.1\r\nContent-Type: multipart/form-data; boundary={}\r\nContent-Length: {}\r\n\r\n{}",
+            boundary,
+            body.len(),
+            body
+        ))
+    };
+    let response = upload("file");
+    assert_eq!((response.status, response.body.as_str()), (200, "uploaded\n"));
+    let unknown = upload("script");
+    assert_eq!((unknown.status, unknown.body.as_str()), (400, "Bad Request: Unknown multipart part `script`"));
+}
+
+#[test]
+fn accepts_gzip_bodies() {
+    let server = TestServer::start(&["--max-body-bytes", "1000"]);
+    let response = post_gzip(&server, &gzip(br#"{"command": "echo unpacked"}"#));
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(response.body, "unpacked\n");
+
+    let response = post_gzip(&server, b"not gzip at all");
+    assert_eq!(response.status, 400);
+    // Compresses to far less than the limit but unpacks to far more
+    let padding = " ".repeat(100_000);
+    let response = post_gzip(&server, &gzip(format!(r#"{{"command": "echo"}}{}"#, padding).as_bytes()));
+    assert_eq!(response.status, 413);
+}
+
+#[test]
+fn flags_and_counts_successful_runs_with_stderr() {
+    let server = TestServer::start(&["--token", "secret"]);
+    let run = |command: &str| {
+        let body = format!(r#"{{"command": "{}"}}"#, command);
+        let response = server.send(&format!(
+            "POST /api/interpreter HTTP/1.1\r\nAuthorization: Bearer secret\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        serde_json::from_str::<serde_json::Value>(&response.body).unwrap()
+    };
+    assert_eq!(run("echo careful >&2")["had_warnings"], true);
+    assert_eq!(run("echo failed >&2; exit 1")["had_warnings"], false);
+    assert_eq!(run("echo fine")["had_warnings"], false);
+
+    let metrics = server.send("GET /admin/metrics HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    let metrics: serde_json::Value = serde_json::from_str(&metrics.body).unwrap();
+    assert_eq!(metrics["runs"], 3);
+    assert_eq!(metrics["warnings"], 1);
+}
+
+#[test]
+fn runs_scripts_at_the_configured_niceness() {
+    let server = TestServer::start(&["--nice", "5"]);
+    let response = server.post("/api/interpreter", r#"{"command": "ps -o nice= -p $$"}"#);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body.trim(), "5");
+}
+
+#[test]
+fn refuses_to_start_without_an_existing_work_dir() {
+    let missing = std::env::temp_dir().join(format!("fpb-test-missing-{}", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"))
+        .args(["--port", "0", "--no-auto-mkdir", "--work-dir"])
+        .arg(&missing)
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(2));
+    assert!(!missing.exists());
+}
+
+#[test]
+fn reports_output_sizes() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "printf 'caf\\303\\251'; printf de >&2"}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["stdout"], "café");
+    assert_eq!(result["stdout_bytes"], 5);
+    assert_eq!(result["stderr_bytes"], 2);
+    assert!(result["max_rss_kb"].as_u64().is_some_and(|kb| kb > 0), "{}", response.body);
+}
+
+// Sessions need a real interpreter for their harness
+fn python3() -> Option<&'static str> {
+    let found = Command::new("python3").arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status();
+    found.is_ok_and(|status| status.success()).then_some("python3")
+}
+
+#[test]
+fn restarts_a_session_that_stops_answering() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python]);
+    let created = server.post("/api/sessions", "");
+    assert_eq!(created.status, 201, "{}", created.body);
+    let session: serde_json::Value = serde_json::from_str(&created.body).unwrap();
+    let path = format!("/api/sessions/{}", session["session_id"].as_str().unwrap());
+
+    assert_eq!(server.post(&path, r#"{"code": "x = 41"}"#).status, 200);
+    let reply: serde_json::Value = serde_json::from_str(&server.post(&path, r#"{"code": "print(x + 1)"}"#).body).unwrap();
+    assert_eq!(reply["stdout"], "42\n");
+
+    let stuck = server.post(&path, r#"{"code": "input()", "timeout": 0.5}"#);
+    assert_eq!(stuck.status, 408);
+    // The replacement interpreter starts from scratch
+    let reply: serde_json::Value = serde_json::from_str(&server.post(&path, r#"{"code": "print('x' in globals())"}"#).body).unwrap();
+    assert_eq!(reply["stdout"], "False\n");
+}
+
+// Undoes Transfer-Encoding: chunked
+fn dechunk(mut body: &str) -> String {
+    let mut decoded = String::new();
+    loop {
+        let (size, rest) = body.split_once("\r\n").expect("chunk without a size line");
+        let size = usize::from_str_radix(size, 16).unwrap();
+        if size == 0 {
+            return decoded;
+        }
+        decoded.push_str(&rest[..size]);
+        body = rest[size..].strip_prefix("\r\n").expect("chunk without a trailing CRLF");
+    }
+}
+
+#[test]
+fn streams_output_as_ndjson() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "echo one; echo two >&2; printf three; exit 3"}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/x-ndjson\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("application/x-ndjson"));
+    assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+    let events: Vec<serde_json::Value> = dechunk(&response.body).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let stdout: Vec<&str> = events.iter().filter(|event| event["stream"] == "stdout").map(|event| event["line"].as_str().unwrap()).collect();
+    assert_eq!(stdout, ["one", "three"]);
+    assert!(events.iter().any(|event| event["stream"] == "stderr" && event["line"] == "two"));
+    assert_eq!(events.last().unwrap(), &serde_json::json!({"stream": "exit", "code": 3}));
+}
+
+#[test]
+fn clean_env_only_passes_the_allowlist() {
+    let server = TestServer::start(&["--clean-env", "--env-passthrough", "PATH"]);
+    let body = r#"{"command": "echo ${PATH:+path} ${HOME-no-home} $GREETING", "env": {"GREETING": "hi"}}"#;
+    let response = server.post("/api/interpreter", body);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "path no-home hi\n");
+}
+
+#[test]
+fn a_no_auth_unix_listener_skips_the_token() {
+    let socket = std::env::temp_dir().join(format!("fpb-test-{}.sock", std::process::id()));
+    let unix = format!("unix:{},no-auth", socket.display());
+    let server = TestServer::start(&["--listen", "127.0.0.1:0", "--listen", &unix, "--token", "secret"]);
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo hello"}"#).status, 401);
+
+    // The Unix listener is bound right after the TCP one is logged
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !socket.exists() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let mut stream = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+    let body = r#"{"command": "echo hello"}"#;
+    write!(stream, "POST /api/interpreter HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+    let response = Response::parse(&raw);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "hello\n");
+    drop(server);
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn reports_config_sources_without_the_token() {
+    let server = TestServer::start(&["--token", "secret", "--timeout", "5"]);
+    let response = server.send("GET /admin/config HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    assert_eq!(response.status, 200);
+    assert!(!response.body.contains("secret"), "{}", response.body);
+    let config: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let options = &config["options"];
+    assert_eq!(options["timeout"], serde_json::json!({"value": 5.0, "source": "cli"}));
+    assert_eq!(options["token"], serde_json::json!({"value": "[redacted]", "source": "cli"}));
+    assert_eq!(options["max-timeout"]["source"], "default");
+}
+
+#[test]
+fn caps_the_number_of_sessions() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python, "--max-sessions", "1"]);
+    let created = server.post("/api/sessions", "");
+    assert_eq!(created.status, 201, "{}", created.body);
+    let refused = server.post("/api/sessions", "");
+    assert_eq!(refused.status, 503);
+    assert!(refused.body.contains("too_many_sessions"), "{}", refused.body);
+
+    let session: serde_json::Value = serde_json::from_str(&created.body).unwrap();
+    let path = format!("/api/sessions/{}", session["session_id"].as_str().unwrap());
+    assert_eq!(server.send(&format!("DELETE {} HTTP/1.1\r\n\r\n", path)).status, 200);
+    assert_eq!(server.post("/api/sessions", "").status, 201);
+}
+
+#[test]
+fn reloads_the_timeout_from_the_config_file() {
+    let file = std::env::temp_dir().join(format!("fpb-test-{}.conf", std::process::id()));
+    std::fs::write(&file, "# a comment\ntimeout 5\n").unwrap();
+    let server = TestServer::start(&["--token", "secret", "--config", file.to_str().unwrap()]);
+    let config = server.send("GET /admin/config HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    let config: serde_json::Value = serde_json::from_str(&config.body).unwrap();
+    assert_eq!(config["options"]["timeout"], serde_json::json!({"value": 5.0, "source": "file"}));
+
+    std::fs::write(&file, "timeout 7\nhost 0.0.0.0\n").unwrap();
+    let reloaded = server.send("POST /admin/reload HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    assert_eq!(reloaded.status, 200, "{}", reloaded.body);
+    let reloaded: serde_json::Value = serde_json::from_str(&reloaded.body).unwrap();
+    assert_eq!(reloaded["applied"], serde_json::json!(["timeout"]));
+    // The default listener follows --host, so it counts as changed too
+    assert_eq!(reloaded["ignored"], serde_json::json!(["host", "listen"]));
+
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}",
+        r#"{"command": "true"}"#.len(),
+        r#"{"command": "true"}"#
+    ));
+    assert_eq!(response.header("x-effective-timeout"), Some("7"));
+    let _ = std::fs::remove_file(&file);
+}
+
+#[test]
+fn negotiates_the_output_format() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "echo hi"}"#;
+    let request = |accept: &str| {
+        server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: {}\r\nContent-Length: {}\r\n\r\n{}", accept, body.len(), body))
+    };
+    assert_eq!(request("*/*").body, "hi\n");
+    assert_eq!(request("text/plain").body, "hi\n");
+    assert!(request("application/json, text/plain").body.starts_with('{'));
+    assert_eq!(request("application/json;q=0.5, text/*").body, "hi\n");
+    let refused = request("image/png");
+    assert_eq!(refused.status, 406);
+    assert_eq!(request("text/plain;q=0, */*;q=0").status, 406);
+}
+
+#[test]
+fn does_not_wait_for_background_processes_past_the_timeout() {
+    let server = TestServer::start(&[]);
+    // Same group: killed as soon as the script exits
+    let started = Instant::now();
+    let response = server.post("/api/interpreter", r#"{"command": "sleep 8 & echo hi", "timeout": 5}"#);
+    assert_eq!((response.status, response.body.as_str()), (200, "hi\n"));
+    assert!(started.elapsed() < Duration::from_secs(3), "{:?}", started.elapsed());
+
+    // Out of our reach in a session of its own, but no longer waited on past the timeout
+    if Command::new("setsid").arg("true").status().is_ok_and(|status| status.success()) {
+        let started = Instant::now();
+        let response = server.post("/api/interpreter", r#"{"command": "setsid sleep 8 & echo hi", "timeout": 1}"#);
+        assert_eq!(response.status, 200);
+        assert!(response.body.starts_with("hi\n"), "{}", response.body);
+        assert!(started.elapsed() < Duration::from_secs(3), "{:?}", started.elapsed());
+    }
+}
+
+#[test]
+fn stops_running_scripts_on_sigterm() {
+    let marker = std::env::temp_dir().join(format!("fpb-test-{}.survived", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+    let mut server = TestServer::start(&["--shutdown-grace", "0.3"]);
+    // Ignores SIGTERM, so only the SIGKILL after the grace period stops it
+    let body = format!(r#"{{"command": "trap '' TERM; sleep 1; echo survived > {}", "async": true}}"#, marker.display());
+    let started = server.post("/api/interpreter", &body);
+    let job: serde_json::Value = serde_json::from_str(&started.body).unwrap();
+    let path = format!("/api/jobs/{}", job["job_id"].as_str().unwrap());
+    while !server.get(&path).body.contains("running") {
+        thread::sleep(Duration::from_millis(20));
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    Command::new("kill").arg(server.child.id().to_string()).status().unwrap();
+    let status = server.child.wait().unwrap();
+    assert!(status.success(), "{:?}", status);
+    thread::sleep(Duration::from_millis(1500));
+    assert!(!marker.exists(), "the script outlived the helper");
+}
+
+#[test]
+fn reopens_the_log_file_on_sighup() {
+    let log = std::env::temp_dir().join(format!("fpb-test-{}.log", std::process::id()));
+    let rotated = log.with_extension("log.1");
+    let _ = std::fs::remove_file(&log);
+    let _ = std::fs::remove_file(&rotated);
+    let stub = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/stub_python.sh");
+    let child = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"))
+        .args(["--info", "--port", "0", "--python", stub, "--log-file"])
+        .arg(&log)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let port = loop {
+        let logged = std::fs::read_to_string(&log).unwrap_or_default();
+        if let Some((_, rest)) = logged.split_once("listening on ") {
+            break rest.lines().next().unwrap().rsplit(':').next().unwrap().parse().unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "the helper never logged where it listens");
+        thread::sleep(Duration::from_millis(20));
+    };
+    let server = TestServer { child, port, log: Arc::new(Mutex::new(String::new())) };
+
+    std::fs::rename(&log, &rotated).unwrap();
+    Command::new("kill").args(["-HUP", &server.child.id().to_string()]).status().unwrap();
+    let started = Instant::now();
+    while !log.exists() {
+        assert!(started.elapsed() < Duration::from_secs(10), "the log file wasn't reopened");
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo hi"}"#).status, 200);
+    assert!(std::fs::read_to_string(&log).unwrap().contains("/api/interpreter"));
+    assert!(!std::fs::read_to_string(&rotated).unwrap().contains("/api/interpreter"));
+    let _ = std::fs::remove_file(&log);
+    let _ = std::fs::remove_file(&rotated);
+}
+
+#[test]
+fn runs_one_script_from_the_command_line() {
+    let stub = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/stub_python.sh");
+    let output = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"))
+        .args(["--python", stub, "--exec", "echo hi; exit 3"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["stdout"], "hi\n");
+    assert_eq!(result["exit_code"], 3);
+}
+
+#[test]
+fn answers_405_with_allow_for_other_methods() {
+    let server = TestServer::start(&[]);
+    for (request, allow) in [
+        ("TRACE / HTTP/1.1", "GET"),
+        ("CONNECT /api/interpreter HTTP/1.1", "POST"),
+        ("PUT /api/sessions/abc HTTP/1.1", "GET, POST, DELETE"),
+    ] {
+        let response = server.send(&format!("{}\r\n\r\n", request));
+        assert_eq!(response.status, 405, "{}", request);
+        assert_eq!(response.header("allow"), Some(allow), "{}", request);
+    }
+    assert_eq!(server.send("TRACE /nowhere HTTP/1.1\r\n\r\n").status, 404);
+}
+
+#[test]
+fn logs_bodies_only_when_asked_and_redacted() {
+    let body = r#"{"command": "password = 'hunter2'; echo 0123456789abcdef0123456789abcdef"}"#;
+    let quiet = TestServer::start(&["--debug"]);
+    quiet.post("/api/interpreter", body);
+    let logged = TestServer::start(&["--debug", "--log-bodies"]);
+    logged.post("/api/interpreter", body);
+    // The helper logs the run before answering, but give the log thread a moment
+    thread::sleep(Duration::from_millis(100));
+
+    assert!(!quiet.log.lock().unwrap().contains("echo"));
+    let log = logged.log.lock().unwrap();
+    assert!(log.contains("password = '[redacted]'; echo [redacted]"), "{}", log);
+    assert!(!log.contains("hunter2") && !log.contains("0123456789abcdef0123"), "{}", log);
+}
+
+#[test]
+fn asserts_stdout_with_a_diff() {
+    let server = TestServer::start(&[]);
+    let passed = server.post("/api/assert", r#"{"command": "echo a; echo b", "expected_stdout": "a\nb\n"}"#);
+    assert_eq!(passed.status, 200);
+    let passed: serde_json::Value = serde_json::from_str(&passed.body).unwrap();
+    assert_eq!(passed["passed"], true);
+    assert!(passed.get("diff").is_none());
+
+    let failed = server.post("/api/assert", r#"{"command": "echo a; echo c", "expected_stdout": "a\nb\n"}"#);
+    let failed: serde_json::Value = serde_json::from_str(&failed.body).unwrap();
+    assert_eq!(failed["passed"], false);
+    assert_eq!(failed["diff"], "--- expected\n+++ actual\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+}
+
+#[test]
+fn rejects_scripts_over_the_script_limit() {
+    let server = TestServer::start(&["--max-body-bytes", "1000", "--max-script-bytes", "100"]);
+    let command = format!("echo {}", "x".repeat(200));
+    let body = format!(r#"{{"command": "{}"}}"#, command);
+    assert!(body.len() < 1000);
+    let response = server.post("/api/interpreter", &body);
+    assert_eq!(response.status, 413);
+    assert!(response.body.contains("script of 205 bytes"), "{}", response.body);
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo ok"}"#).body, "ok\n");
+}
+
+#[test]
+fn warms_up_modules_and_reports_failures() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python, "--warmup-modules", "json,colorsys"]);
+    let defaults: serde_json::Value = serde_json::from_str(&server.post("/api/warmup", "").body).unwrap();
+    assert_eq!(defaults["imported"], serde_json::json!(["json", "colorsys"]));
+
+    let response = server.post("/api/warmup", r#"{"modules": ["json", "no_such_module_here"]}"#);
+    assert_eq!(response.status, 200, "{}", response.body);
+    let report: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(report["imported"], serde_json::json!(["json"]));
+    assert!(report["failed"]["no_such_module_here"].as_str().unwrap().starts_with("ModuleNotFoundError"));
+}
+
+#[test]
+fn rejects_a_body_shorter_than_its_content_length() {
+    let server = TestServer::start(&["--read-timeout", "0.3"]);
+    let truncated = "POST /api/interpreter HTTP/1.1\r\nContent-Length: 100\r\n\r\n{\"command\": ";
+    // Stalls with the connection open
+    let started = Instant::now();
+    let response = server.send(truncated);
+    assert_eq!(response.status, 400);
+    assert_eq!(response.body, "Bad Request: body shorter than Content-Length");
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // Closes its side early
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    stream.write_all(truncated.as_bytes()).unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+    assert_eq!(Response::parse(&raw).status, 400);
+}
+
+#[test]
+fn runs_sandboxed_scripts_through_docker() {
+    // A docker that prints how it was called
+    let bin = std::env::temp_dir().join(format!("fpb-test-{}-bin", std::process::id()));
+    std::fs::create_dir_all(&bin).unwrap();
+    let docker = bin.join("docker");
+    std::fs::write(&docker, "#!/bin/sh\nprintf '%s\\n' \"$@\"\n").unwrap();
+    std::fs::set_permissions(&docker, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+    let server = TestServer::start_with_env(&["--sandbox", "docker", "--sandbox-image", "python:3.12"], &[("PATH", &path)]);
+
+    let response = server.post("/api/interpreter", r#"{"command": "print(1)", "args": ["a"], "env": {"GREETING": "hi"}}"#);
+    assert_eq!(response.status, 200, "{}", response.body);
+    let args: Vec<&str> = response.body.lines().collect();
+    assert_eq!(args[..5], ["run", "--rm", "-i", "--network", "none"]);
+    assert!(args.iter().any(|arg| arg.ends_with(":/fpb/script.py:ro")), "{:?}", args);
+    assert!(args.windows(2).any(|pair| pair == ["-e", "GREETING"]), "{:?}", args);
+    assert_eq!(args[args.len() - 4..], ["python:3.12", "python", "/fpb/script.py", "a"]);
+    assert_eq!(server.post("/api/sessions", "").status, 501);
+    let _ = std::fs::remove_dir_all(&bin);
+}
+
+#[test]
+fn tells_docker_failures_from_scripts_exiting_125() {
+    // A docker that exits 125 either way, having created the container only when asked to
+    let bin = std::env::temp_dir().join(format!("fpb-test-{}-docker-125", std::process::id()));
+    std::fs::create_dir_all(&bin).unwrap();
+    let docker = bin.join("docker");
+    let script = "#!/bin/sh\nwhile [ \"$1\" != --cidfile ]; do shift; done\nif [ -n \"$CREATED\" ]; then echo abc > \"$2\"; echo ran; else echo 'docker: no such image' >&2; fi\nexit 125\n";
+    std::fs::write(&docker, script).unwrap();
+    std::fs::set_permissions(&docker, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+    let server = TestServer::start_with_env(&["--sandbox", "docker"], &[("PATH", &path)]);
+
+    let failed = server.post("/api/interpreter", r#"{"command": "print(1)"}"#);
+    assert_eq!(failed.status, 500);
+    assert_eq!(failed.body, "Failed to start the sandbox: docker: no such image");
+    let exited = server.post("/api/interpreter", r#"{"command": "print(1)", "env": {"CREATED": "1"}}"#);
+    assert_eq!((exited.status, exited.body.as_str()), (200, "ran\n"));
+    let _ = std::fs::remove_dir_all(&bin);
+}
+
+#[test]
+fn merges_stderr_into_stdout_in_order() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "echo a; echo b >&2; echo c", "merge_output": true}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["stdout"], "a\nb\nc\n");
+    assert_eq!(result["stderr"], "");
+}
+
+#[test]
+fn echoes_and_records_the_label() {
+    let server = TestServer::start(&["--token", "secret"]);
+    let label = format!("nightly\n{}", "x".repeat(300));
+    let body = serde_json::json!({"command": "echo hi", "label": label}).to_string();
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAuthorization: Bearer secret\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let echoed = result["label"].as_str().unwrap();
+    assert!(echoed.starts_with("nightly x") && echoed.chars().count() < 110, "{}", echoed);
+    assert_eq!(response.header("x-label"), Some(echoed));
+    assert_eq!(result["stdout"], "hi\n");
+
+    let history = server.send("GET /admin/history HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    let history: serde_json::Value = serde_json::from_str(&history.body).unwrap();
+    assert_eq!(history["entries"][0]["label"], echoed);
+}
+
+#[test]
+fn runs_a_stored_script_by_id_until_it_expires() {
+    let server = TestServer::start(&["--script-ttl", "1"]);
+    let created = server.post("/api/scripts", r#"{"command": "echo \"$1\"; cat"}"#);
+    assert_eq!(created.status, 201, "{}", created.body);
+    let created: serde_json::Value = serde_json::from_str(&created.body).unwrap();
+    let path = format!("/api/scripts/{}", created["script_id"].as_str().unwrap());
+
+    assert_eq!(server.post(&path, r#"{"args": ["one"], "stdin": "in"}"#).body, "one\nin");
+    assert_eq!(server.post(&path, r#"{"args": ["two"]}"#).body, "two\n");
+    assert_eq!(server.post(&path, r#"{"command": "echo other"}"#).status, 400);
+
+    thread::sleep(Duration::from_millis(1200));
+    assert_eq!(server.post(&path, "{}").status, 404);
+    assert_eq!(server.post("/api/scripts/unknown", "{}").status, 404);
+}
+
+#[test]
+fn rejects_files_outside_the_exec_root() {
+    let base = std::env::temp_dir().join(format!("fpb-test-{}-root", std::process::id()));
+    let root = base.join("scripts");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("ok.sh"), "echo inside\n").unwrap();
+    std::fs::write(base.join("secret.sh"), "echo outside\n").unwrap();
+    std::os::unix::fs::symlink(base.join("secret.sh"), root.join("link.sh")).unwrap();
+    let server = TestServer::start(&["--allow-file-exec", "--file-exec-root", root.to_str().unwrap()]);
+
+    assert_eq!(server.post("/api/interpreter", r#"{"file": "ok.sh"}"#).body, "inside\n");
+    let outside = base.join("secret.sh");
+    for file in ["../secret.sh", "../../../../etc/passwd", "link.sh", outside.to_str().unwrap(), "/etc/passwd"] {
+        let response = server.post("/api/interpreter", &serde_json::json!({ "file": file }).to_string());
+        assert_eq!(response.status, 400, "{}", file);
+        assert!(response.body.contains("outside the allowed directory"), "{}: {}", file, response.body);
+    }
+    let _ = std::fs::remove_dir_all(&base);
+}
+
+#[test]
+fn lists_capabilities_from_the_config() {
+    let server = TestServer::start(&["--max-script-bytes", "500"]);
+    let plain: serde_json::Value = serde_json::from_str(&server.get("/api/capabilities").body).unwrap();
+    assert_eq!(plain["features"]["sessions"], true);
+    assert_eq!(plain["features"]["sandbox"], false);
+    assert_eq!(plain["features"]["tls"], false);
+    assert_eq!(plain["limits"]["max_script_bytes"], 500);
+
+    let server = TestServer::start(&["--sandbox", "docker", "--restricted-builtins"]);
+    let sandboxed: serde_json::Value = serde_json::from_str(&server.get("/api/capabilities").body).unwrap();
+    assert_eq!(sandboxed["sandbox"], "docker");
+    assert_eq!(sandboxed["features"]["sessions"], false);
+    assert_eq!(sandboxed["features"]["restricted_builtins"], true);
+}
+
+#[test]
+fn serves_routes_under_the_prefix_only() {
+    let server = TestServer::start(&["--route-prefix", "/fpb/"]);
+    assert!(server.get("/fpb").body.contains("Helper is running."));
+    assert!(server.get("/fpb/").body.contains("Helper is running."));
+    assert_eq!(server.post("/fpb/api/interpreter", r#"{"command": "echo hi"}"#).body, "hi\n");
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo hi"}"#).status, 404);
+    assert_eq!(server.get("/fpbx/").status, 404);
+    assert_eq!(server.get("/").status, 404);
+}
+
+#[test]
+fn reports_the_command_line_with_debug_exec() {
+    let server = TestServer::start(&[]);
+    let response = server.post("/api/interpreter", r#"{"command": "true"}"#);
+    assert_eq!(response.header("x-exec-command"), None);
+
+    let server = TestServer::start(&["--debug-exec"]);
+    let body = r#"{"command": "true", "args": ["a b"]}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let argv: Vec<&str> = result["command_line"]["argv"].as_array().unwrap().iter().map(|arg| arg.as_str().unwrap()).collect();
+    assert!(argv[0].ends_with("stub_python.sh"), "{:?}", argv);
+    assert!(argv[1].ends_with("script.py"), "{:?}", argv);
+    assert_eq!(argv[2], "a b");
+    assert!(result["command_line"]["cwd"].is_string());
+    assert!(response.header("x-exec-command").unwrap().ends_with(r#"script.py" "a b""#), "{:?}", response.headers);
+}
+
+#[test]
+fn a_reload_leaves_running_scripts_alone() {
+    let file = std::env::temp_dir().join(format!("fpb-test-{}-inflight.conf", std::process::id()));
+    std::fs::write(&file, "timeout 3\n").unwrap();
+    let server = Arc::new(TestServer::start(&["--config", file.to_str().unwrap(), "--token", "secret"]));
+    let run = |server: &TestServer, command: &str| {
+        let body = format!(r#"{{"command": "{}"}}"#, command);
+        server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}", body.len(), body))
+    };
+    let running = {
+        let server = Arc::clone(&server);
+        thread::spawn(move || run(&server, "sleep 1.5; echo survived"))
+    };
+    thread::sleep(Duration::from_millis(300));
+    std::fs::write(&file, "timeout 0.5\n").unwrap();
+    let reloaded = server.send("POST /admin/reload HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    assert_eq!(reloaded.status, 200, "{}", reloaded.body);
+
+    let running = running.join().unwrap();
+    assert_eq!(running.body, "survived\n");
+    assert_eq!(running.header("x-effective-timeout"), Some("3"));
+    assert_eq!(run(&server, "sleep 1.5").status, 408);
+    let _ = std::fs::remove_file(&file);
+}
+
+#[test]
+fn takes_a_raw_script_body_by_content_type() {
+    let server = TestServer::start(&[]);
+    let script = "echo one\necho \"{not json}\"\n";
+    for content_type in ["text/x-python", "text/plain; charset=utf-8"] {
+        let response = server.send(&format!(
+            "POST /api/interpreter HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            content_type,
+            script.len(),
+            script
+        ));
+        assert_eq!(response.status, 200, "{}", content_type);
+        assert_eq!(response.body, "one\n{not json}\n");
+    }
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        script.len(),
+        script
+    ));
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn exits_after_going_idle() {
+    let mut server = TestServer::start(&["--idle-exit", "0.6"]);
+    thread::sleep(Duration::from_millis(400));
+    // Keeps it alive past the first deadline
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "sleep 0.4; echo hi"}"#).body, "hi\n");
+    thread::sleep(Duration::from_millis(300));
+    assert!(server.child.try_wait().unwrap().is_none());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = server.child.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "still running");
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success());
+}
+
+#[test]
+fn a_silent_nonzero_exit_is_an_error() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python]);
+    let body = r#"{"command": "import sys; sys.exit(2)"}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    assert_eq!(response.status, 500);
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["error"], "nonzero_exit");
+    assert_eq!(result["exit_code"], 2);
+    assert_eq!(result["stdout"], "");
+    assert_eq!(result["stderr"], "");
+
+    let text = server.post("/api/interpreter", body);
+    assert_eq!(text.body, "Script exited with code 2 without printing anything");
+}
+
+#[test]
+fn replays_the_response_for_a_repeated_idempotency_key() {
+    let server = TestServer::start(&[]);
+    let send = |key: &str, body: &str| {
+        server.send(&format!("POST /api/interpreter HTTP/1.1\r\nIdempotency-Key: {}\r\nContent-Length: {}\r\n\r\n{}", key, body.len(), body))
+    };
+    // Prints something different every time it really runs
+    let body = r#"{"command": "date +%s%N"}"#;
+    let first = send("k1", body);
+    assert_eq!(first.status, 200);
+    assert_eq!(first.header("x-idempotent-replay"), None);
+    let second = send("k1", body);
+    assert_eq!(second.body, first.body);
+    assert_eq!(second.header("x-idempotent-replay"), Some("true"));
+
+    assert_ne!(send("k2", body).body, first.body);
+    assert_eq!(send("k1", r#"{"command": "echo other"}"#).status, 422);
+    // A rejected request doesn't use up its key
+    assert_eq!(send("k3", "{").status, 400);
+    assert_eq!(send("k3", r#"{"command": "echo ok"}"#).body, "ok\n");
+}
+
+#[test]
+fn streams_ndjson_in_fixed_size_chunks() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "echo abc; printf 'defgxyzé!'", "chunk_size": 4}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/x-ndjson\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    let events: Vec<serde_json::Value> = dechunk(&response.body).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let chunks: Vec<&str> = events.iter().filter(|event| event["stream"] == "stdout").map(|event| event["chunk"].as_str().unwrap()).collect();
+    // The two-byte é isn't split across chunks
+    assert_eq!(chunks, ["abc\n", "defg", "xyz", "é!"]);
+    assert_eq!(events.last().unwrap(), &serde_json::json!({"stream": "exit", "code": 0}));
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "true", "chunk_size": 0}"#).status, 400);
+}
+
+#[test]
+fn closes_silently_when_the_client_sends_nothing() {
+    let server = TestServer::start(&[]);
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert!(response.is_empty(), "{:?}", String::from_utf8_lossy(&response));
+
+    // Still serving, and nothing was logged about the probe
+    assert_eq!(server.get("/").status, 200);
+    thread::sleep(Duration::from_millis(100));
+    let log = server.log.lock().unwrap();
+    assert!(!log.contains("Malformed") && !log.contains("ERROR"), "{}", log);
+}
+
+#[test]
+fn retries_pip_installs_only_after_network_errors() {
+    // A pip that can't connect on its first try, and knows no package called "missing"
+    let dir = std::env::temp_dir().join(format!("fpb-test-{}-pip", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pip = dir.join("python");
+    let script = format!(
+        "#!/bin/sh\ncase \"$*\" in *missing*) echo 'ERROR: No matching distribution found for missing' >&2; exit 1;; esac\n\
+         if [ ! -e {0}/tried ]; then touch {0}/tried; echo 'WARNING: Retrying after NewConnectionError' >&2; exit 1; fi\n\
+         echo 'Successfully installed'\n",
+        dir.display()
+    );
+    std::fs::write(&pip, script).unwrap();
+    std::fs::set_permissions(&pip, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let server = TestServer::start(&["--python", pip.to_str().unwrap(), "--pip-retry-backoff", "0.05"]);
+
+    let installed = server.post("/api/packages", r#"{"packages": ["requests"]}"#);
+    assert_eq!(installed.status, 200, "{}", installed.body);
+    let installed: serde_json::Value = serde_json::from_str(&installed.body).unwrap();
+    let attempts = installed["attempts"].as_array().unwrap();
+    assert_eq!(attempts.len(), 2);
+    assert_eq!(attempts[0]["network_error"], true);
+    assert_eq!(attempts[1]["exit_code"], 0);
+
+    let failed = server.post("/api/packages", r#"{"packages": ["missing"]}"#);
+    assert_eq!(failed.status, 500);
+    let failed: serde_json::Value = serde_json::from_str(&failed.body).unwrap();
+    assert_eq!(failed["network_error"], false);
+    assert_eq!(failed["attempts"].as_array().unwrap().len(), 1);
+
+    assert_eq!(server.post("/api/packages", r#"{"packages": ["--index-url=http://evil"]}"#).status, 400);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn returns_the_sha256_of_the_script() {
+    let server = TestServer::start(&["--token", "secret"]);
+    let run = |command: &str| {
+        let body = serde_json::json!({ "command": command }).to_string();
+        server.send(&format!(
+            "POST /api/interpreter HTTP/1.1\r\nAuthorization: Bearer secret\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ))
+    };
+    let response = run("echo hi");
+    let hash = "56a79f3b115448072387c2480044bfa2cf8f90e4f5fddd8c943b4e051b81f80b";
+    assert_eq!(response.header("x-script-hash"), Some(hash));
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["script_sha256"], hash);
+    // Longer than one block, and not all ASCII
+    let long = run(&format!("echo {}é", "x".repeat(100)));
+    assert_eq!(long.header("x-script-hash"), Some("eb39453140a5ef8bd5503457ccf75712c9202380ea27c162b171091d3abc8ac1"));
+
+    let history = server.send("GET /admin/history HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    let history: serde_json::Value = serde_json::from_str(&history.body).unwrap();
+    assert_eq!(history["entries"][1]["script_sha256"], hash);
+}
+
+#[test]
+fn sessions_have_their_own_default_and_maximum_timeout() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python, "--timeout", "0.3", "--session-timeout", "1", "--max-session-timeout", "1.5"]);
+    let slow = "import time; time.sleep(0.6)";
+    assert_eq!(server.post("/api/interpreter", &serde_json::json!({ "command": slow }).to_string()).status, 408);
+
+    let created: serde_json::Value = serde_json::from_str(&server.post("/api/sessions", "").body).unwrap();
+    let path = format!("/api/sessions/{}", created["session_id"].as_str().unwrap());
+    assert_eq!(server.post(&path, &serde_json::json!({ "code": slow }).to_string()).status, 200);
+    let stuck = server.post(&path, r#"{"code": "input()", "timeout": 100}"#);
+    assert_eq!(stuck.status, 408);
+    let stuck: serde_json::Value = serde_json::from_str(&stuck.body).unwrap();
+    assert_eq!(stuck["timeout"], 1.5);
+}
+
+#[test]
+fn reports_the_current_load() {
+    let server = Arc::new(TestServer::start(&["--max-concurrency", "1"]));
+    let load = |server: &TestServer| -> serde_json::Value { serde_json::from_str(&server.get("/api/load").body).unwrap() };
+    let idle = load(&server);
+    assert_eq!(idle["in_flight"], 0);
+    assert_eq!(idle["queued"], 0);
+    assert_eq!(idle["max_concurrency"], 1);
+
+    let runs: Vec<_> = (0..2)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.post("/api/interpreter", r#"{"command": "sleep 0.8; echo done"}"#))
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(300));
+    let busy = load(&server);
+    assert_eq!(busy["in_flight"], 1);
+    assert_eq!(busy["queued"], 1);
+    for run in runs {
+        assert_eq!(run.join().unwrap().body, "done\n");
+    }
+    assert_eq!(load(&server)["in_flight"], 0);
+}
+
+#[test]
+fn gives_each_run_its_own_home_with_isolated_home() {
+    let work_dir = std::env::temp_dir().join(format!("fpb-test-{}-homes", std::process::id()));
+    let server = TestServer::start(&["--isolated-home", "--work-dir", work_dir.to_str().unwrap()]);
+    let home = server.post("/api/interpreter", r#"{"command": "mkdir -p $HOME/.cache && echo $HOME"}"#).body;
+    let home = home.trim_end();
+    assert!(Path::new(home).starts_with(&work_dir), "{}", home);
+    assert_ne!(server.post("/api/interpreter", r#"{"command": "echo $HOME"}"#).body.trim_end(), home);
+
+    let timed_out = server.post("/api/interpreter", r#"{"command": "touch $HOME/left; sleep 5", "timeout": 0.5}"#);
+    assert_eq!(timed_out.status, 408);
+    // Nothing left behind, the timed-out run's home included; removal follows the response
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while std::fs::read_dir(&work_dir).unwrap().count() > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(std::fs::read_dir(&work_dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&work_dir);
+}
+
+#[test]
+fn rejects_duplicate_keys_with_strict_json() {
+    // Fields were always refused twice; maps such as `env` kept the last value
+    let duplicated = r#"{"command": "echo $A", "env": {"A": "safe", "A": "evil"}}"#;
+    let lenient = TestServer::start(&[]);
+    assert_eq!(lenient.post("/api/interpreter", duplicated).body, "evil\n");
+    assert!(lenient.post("/api/interpreter", r#"{"command": "echo safe", "command": "echo evil"}"#).body.contains("duplicate field `command`"));
+
+    let server = TestServer::start(&["--strict-json"]);
+    let response = server.post("/api/interpreter", duplicated);
+    assert_eq!(response.status, 400);
+    assert!(response.body.contains("duplicate key `A`"), "{}", response.body);
+    let stages = server.post("/api/pipeline", r#"{"stages": [{"command": "echo a", "env": {"B": "1", "B": "2"}}]}"#);
+    assert_eq!(stages.status, 400);
+    assert!(server.post("/api/interpreter", r#"{"command": "echo safe", "command": "echo evil"}"#).body.contains("duplicate key `command`"));
+
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo ok", "env": {"A": "1", "B": "2"}}"#).body, "ok\n");
+    // Malformed JSON keeps its usual message
+    assert!(server.post("/api/interpreter", r#"{"command": "#).body.contains("Invalid JSON"));
+}
+
+#[test]
+fn tracks_resources_inside_the_interpreter() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python]);
+    let run = |body: &str| -> serde_json::Value {
+        let response = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+        serde_json::from_str(&response.body).unwrap()
+    };
+    let plain = run(r#"{"command": "print(1)"}"#);
+    assert!(plain.get("resources").is_none());
+
+    let tracked = run(r#"{"command": "import sys\nblob = bytearray(2000000)\nfor i in range(10):\n    pass\nprint(sys.argv[1:])", "args": ["a"], "track_resources": true}"#);
+    // stdout is the script's alone
+    assert_eq!(tracked["stdout"], "['a']\n");
+    let resources = &tracked["resources"];
+    assert!(resources["lines_executed"].as_u64().unwrap() >= 14, "{}", resources);
+    assert!(resources["peak_memory_bytes"].as_u64().unwrap() >= 2_000_000, "{}", resources);
+    assert!(resources["cpu_time"].as_f64().unwrap() >= 0.0);
+
+    let failed = run(r#"{"command": "raise ValueError('boom')", "track_resources": true}"#);
+    assert_eq!(failed["exit_code"], 1);
+    assert!(failed["resources"]["lines_executed"].as_u64().is_some());
+    let stderr = failed["stderr"].as_str().unwrap();
+    assert!(stderr.contains("ValueError: boom") && !stderr.contains("runpy"), "{}", stderr);
+}
+
+#[test]
+fn filters_connections_by_peer_address() {
+    // Turned away before anything is read, so there's no need to send a request
+    let greeting = |server: &TestServer| {
+        let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let allowed = TestServer::start(&["--allow-ip", "10.0.0.0/8", "--allow-ip", "127.0.0.0/8"]);
+    assert!(allowed.get("/").body.contains("Helper is running."));
+    let elsewhere = TestServer::start(&["--allow-ip", "10.0.0.0/8"]);
+    let refused = Response::parse(&greeting(&elsewhere));
+    assert_eq!(refused.status, 403);
+    assert!(refused.body.contains("--allow-ip"), "{}", refused.body);
+    let dropped = TestServer::start(&["--allow-ip", "10.0.0.0/8", "--reject-connections", "drop"]);
+    assert_eq!(greeting(&dropped), "");
+
+    let capped = TestServer::start(&["--max-connections-per-ip", "1"]);
+    let held = TcpStream::connect(("127.0.0.1", capped.port)).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(Response::parse(&greeting(&capped)).status, 403);
+    drop(held);
+    thread::sleep(Duration::from_millis(200));
+    assert!(capped.get("/").body.contains("Helper is running."));
+
+    let limited = TestServer::start(&["--connection-rate", "2"]);
+    assert_eq!(limited.get("/").status, 200);
+    assert_eq!(limited.get("/").status, 200);
+    let too_fast = Response::parse(&greeting(&limited));
+    assert_eq!(too_fast.status, 403);
+    assert!(too_fast.body.contains("--connection-rate"), "{}", too_fast.body);
+}
+
+#[test]
+fn splits_startup_from_script_time() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python]);
+    let run = |body: &str| -> serde_json::Value {
+        let response = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+        serde_json::from_str(&response.body).unwrap()
+    };
+    assert!(run(r#"{"command": "print(1)"}"#).get("script_ms").is_none());
+
+    let timed = run(r#"{"command": "import time, sys\ntime.sleep(0.3)\nprint(sys.argv[1])", "args": ["x"], "timing": true}"#);
+    assert_eq!(timed["stdout"], "x\n");
+    assert!(timed["script_ms"].as_f64().unwrap() >= 300.0, "{}", timed);
+    let startup = timed["startup_ms"].as_f64().unwrap();
+    assert!(startup > 0.0 && startup < 300.0, "{}", timed);
+
+    let exited = run(r#"{"command": "import sys; sys.exit(3)", "timing": true, "track_resources": true}"#);
+    assert_eq!(exited["exit_code"], 3);
+    assert!(exited["script_ms"].as_f64().is_some() && exited["resources"].is_object(), "{}", exited);
+}
+
+#[test]
+fn runs_scripts_in_namespaces() {
+    let stub = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/stub_python.sh");
+    let supported = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"))
+        .args(["--python", stub, "--sandbox", "namespaces", "--exec", "true"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    if !cfg!(target_os = "linux") || supported.code() == Some(2) {
+        eprintln!("skipping: namespaces aren't available here");
+        return;
+    }
+    let server = TestServer::start(&["--sandbox", "namespaces"]);
+    // The script is pid 1 of its own namespace, with its own /proc, hostname and no network
+    let processes = server.post("/api/interpreter", r#"{"command": "echo $$; ls /proc | grep -c '^[0-9]'; tail -n +3 /proc/net/dev | cut -d: -f1 | tr -d ' '"}"#);
+    assert_eq!(processes.status, 200, "{}", processes.body);
+    let lines: Vec<&str> = processes.body.lines().collect();
+    assert_eq!(lines[0], "1", "{}", processes.body);
+    assert!(lines[1].parse::<u32>().unwrap() < 5, "{}", processes.body);
+    assert_eq!(&lines[2..], ["lo"], "{}", processes.body);
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo out; echo err >&2; exit 3"}"#).body, "Error: err\n\nOutput: out\n");
+    // docker's code for a sandbox that didn't come up, but the script's own here
+    let exited = server.post("/api/interpreter", r#"{"command": "echo out; exit 125"}"#);
+    assert_eq!((exited.status, exited.body.as_str()), (200, "out\n"));
+
+    let timed_out = server.post("/api/interpreter", r#"{"command": "sleep 5", "timeout": 0.5}"#);
+    assert_eq!(timed_out.status, 408);
+    assert_eq!(server.post("/api/sessions", "").status, 501);
+}
+
+#[test]
+fn sends_output_as_an_attachment_on_request() {
+    let server = TestServer::start(&[]);
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo hi"}"#).header("content-disposition"), None);
+    let saved = server.post("/api/interpreter", r#"{"command": "echo hi", "as_attachment": true}"#);
+    assert_eq!(saved.body, "hi\n");
+    assert_eq!(saved.header("content-disposition"), Some(r#"attachment; filename="output.txt""#));
+    let named = server.post("/api/interpreter", r#"{"command": "echo hi", "as_attachment": true, "filename": "résumé.csv"}"#);
+    assert_eq!(named.header("content-disposition"), Some(r#"attachment; filename="r_sum_.csv"; filename*=UTF-8''r%C3%A9sum%C3%A9.csv"#));
+
+    let failed = server.post("/api/interpreter", r#"{"command": "exit 1", "as_attachment": true}"#);
+    assert_eq!(failed.status, 500);
+    assert_eq!(failed.header("content-disposition"), None);
+    for body in [r#"{"command": "true", "filename": "a.txt"}"#, r#"{"command": "true", "as_attachment": true, "filename": "../a.txt"}"#] {
+        assert_eq!(server.post("/api/interpreter", body).status, 400, "{}", body);
+    }
+}
+
+#[test]
+fn evicts_least_recently_used_entries_past_the_memory_limit() {
+    let capped = TestServer::start(&["--max-stored-scripts", "1"]);
+    let store = |server: &TestServer, command: &str| {
+        let created: serde_json::Value = serde_json::from_str(&server.post("/api/scripts", &format!(r#"{{"command": "{}"}}"#, command)).body).unwrap();
+        format!("/api/scripts/{}", created["script_id"].as_str().unwrap())
+    };
+    let first = store(&capped, "echo first");
+    let second = store(&capped, "echo second");
+    assert_eq!(capped.post(&first, "").status, 404);
+    assert_eq!(capped.post(&second, "").body, "second\n");
+
+    let server = TestServer::start(&["--max-server-memory", "1500", "--token", "secret"]);
+    let authorized = |request: &str, body: &str| {
+        server.send(&format!("{} HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}", request, body.len(), body))
+    };
+    let script = format!(r#"{{"command": "echo stored #{}"}}"#, "x".repeat(600));
+    let created: serde_json::Value = serde_json::from_str(&authorized("POST /api/scripts", &script).body).unwrap();
+    let stored = format!("POST /api/scripts/{}", created["script_id"].as_str().unwrap());
+    for i in 0..8 {
+        let body = format!(r#"{{"command": "echo {} #{}"}}"#, i, "y".repeat(250));
+        assert_eq!(authorized("POST /api/interpreter", &body).status, 200);
+    }
+    // The script was the least recently used, then the oldest runs
+    assert_eq!(authorized(&stored, "").status, 404);
+    let history: serde_json::Value = serde_json::from_str(&authorized("GET /admin/history", "").body).unwrap();
+    let entries = history["entries"].as_array().unwrap();
+    assert!(!entries.is_empty() && entries.len() < 8, "{}", entries.len());
+    assert!(entries[0]["command"].as_str().unwrap().starts_with("echo 7"));
+}
+
+#[test]
+fn runs_scripts_with_an_interpreter_template() {
+    let server = TestServer::start(&["--interpreter-template", "/bin/sh -e {script} first {args}"]);
+    let response = server.post("/api/interpreter", r#"{"command": "echo \"$@\"", "args": ["a", "b"]}"#);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "first a b\n");
+    let failed = server.post("/api/interpreter", r#"{"command": "false\necho not reached"}"#);
+    assert_eq!(failed.status, 500);
+    assert!(!failed.body.contains("not reached"));
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "true", "profile": true}"#).status, 400);
+
+    for template in ["/bin/sh", "/bin/sh {args} {args} {script}", "{script}"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"))
+            .args(["--port", "0", "--interpreter-template", template])
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(2), "{}", template);
+    }
+}
+
+#[test]
+fn truncates_output_at_max_lines_or_max_bytes() {
+    let server = TestServer::start(&[]);
+    let lines = server.post("/api/interpreter", r#"{"command": "seq 1 10", "max_lines": 3}"#);
+    assert_eq!(lines.status, 200);
+    assert_eq!(lines.body, "1\n2\n3\n[truncated: 7 more lines, 15 more bytes]\n");
+
+    // Ten two-byte characters on one line; the byte cap comes first
+    let body = r#"{"command": "printf 'é%.0s' $(seq 1 10)", "max_lines": 5, "max_bytes": 5}"#;
+    let response = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["stdout"], "éé\n[truncated: 1 more lines, 16 more bytes]\n");
+    assert_eq!(result["stdout_truncated"], serde_json::json!({"lines": 1, "bytes": 16}));
+    assert_eq!(result["stdout_bytes"], 20);
+    assert!(result.get("stderr_truncated").is_none());
+
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "seq 1 3", "max_lines": 3}"#).body, "1\n2\n3\n");
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "true", "max_lines": 0}"#).status, 400);
+}
+
+#[test]
+fn retries_once_after_a_crash_signal() {
+    let run = |server: &TestServer, body: &str| {
+        let response = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+        let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        (response.status, result)
+    };
+    // Crashes whenever the marker isn't there yet
+    let marker = std::env::temp_dir().join(format!("fpb-test-{}.crashed", std::process::id()));
+    let body = format!(r#"{{"command": "if [ ! -e {0} ]; then touch {0}; kill -SEGV $$; fi; echo ok"}}"#, marker.display());
+    let _ = std::fs::remove_file(&marker);
+    let (status, result) = run(&TestServer::start(&[]), &body);
+    assert_eq!(status, 500, "{}", result);
+    assert_eq!((result["exit_code"].is_null(), result["signal"].as_i64()), (true, Some(11)));
+    assert!(result.get("retried").is_none());
+
+    let _ = std::fs::remove_file(&marker);
+    let server = TestServer::start(&["--on-crash", "retry"]);
+    let (status, result) = run(&server, &body);
+    assert_eq!(status, 200, "{}", result);
+    assert_eq!((result["stdout"].as_str(), result["retried"].as_bool()), (Some("ok\n"), Some(true)));
+    let (_, result) = run(&server, r#"{"command": "exit 3"}"#);
+    assert_eq!((result["exit_code"].as_i64(), result["retried"].as_bool()), (Some(3), Some(false)));
+    let _ = std::fs::remove_file(&marker);
+}
+
+#[test]
+fn exposes_allowlisted_headers_as_env() {
+    let server = TestServer::start_with_env(&["--expose-headers-as-env", "X-User-Id"], &[("HTTP_X_USER_ID", "inherited")]);
+    let body = r#"{"command": "echo \"$HTTP_X_USER_ID|$HTTP_AUTHORIZATION\"", "env": {"HTTP_X_USER_ID": "spoofed"}}"#;
+    let request = |headers: &str| {
+        server.send(&format!("POST /api/interpreter HTTP/1.1\r\n{}Authorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}", headers, body.len(), body))
+    };
+    assert_eq!(request("X-User-Id: 42\r\n").body, "42|\n");
+    assert_eq!(request("").body, "|\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings")).args(["--expose-headers-as-env", "proxy"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn limits_runs_of_a_stored_script_per_minute() {
+    let server = TestServer::start(&[]);
+    assert_eq!(server.post("/api/scripts", r#"{"command": "echo hi", "max_per_minute": 0}"#).status, 400);
+    let stored: serde_json::Value = serde_json::from_str(&server.post("/api/scripts", r#"{"command": "echo hi", "max_per_minute": 2}"#).body).unwrap();
+    assert_eq!(stored["max_per_minute"], 2);
+    let path = format!("/api/scripts/{}", stored["script_id"].as_str().unwrap());
+    assert_eq!(server.post(&path, "").status, 200);
+    assert_eq!(server.post(&path, "").status, 200);
+    let refused = server.post(&path, "");
+    assert_eq!(refused.status, 429);
+    let retry_after: u64 = refused.header("retry-after").unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after), "{}", retry_after);
+    let result: serde_json::Value = serde_json::from_str(&refused.body).unwrap();
+    assert_eq!(result["error"], "rate_limited");
+
+    // Others are unaffected
+    let other: serde_json::Value = serde_json::from_str(&server.post("/api/scripts", r#"{"command": "echo hi"}"#).body).unwrap();
+    assert_eq!(server.post(&format!("/api/scripts/{}", other["script_id"].as_str().unwrap()), "").status, 200);
+}
+
+#[test]
+fn does_not_count_refused_or_replayed_runs_against_max_per_minute() {
+    let server = TestServer::start(&[]);
+    let stored: serde_json::Value = serde_json::from_str(&server.post("/api/scripts", r#"{"command": "echo hi", "max_per_minute": 1}"#).body).unwrap();
+    let path = format!("/api/scripts/{}", stored["script_id"].as_str().unwrap());
+    let run = |headers: &str| server.send(&format!("POST {} HTTP/1.1\r\n{}Content-Length: 0\r\n\r\n", path, headers));
+    assert_eq!(run("Accept: image/png\r\n").status, 406);
+    assert_eq!(run("Idempotency-Key: k\r\n").status, 200);
+    let replay = run("Idempotency-Key: k\r\n");
+    assert_eq!(replay.status, 200);
+    assert_eq!(replay.header("x-idempotent-replay"), Some("true"));
+    assert_eq!(run("Idempotency-Key: other\r\n").status, 429);
+}
+
+#[test]
+fn answers_pings_without_a_token() {
+    let server = TestServer::start(&["--token", "secret"]);
+    let first = server.get("/api/ping?nonce=abc");
+    assert_eq!(first.status, 200, "{}", first.body);
+    assert_eq!(first.header("cache-control"), Some("no-store"));
+    let first: serde_json::Value = serde_json::from_str(&first.body).unwrap();
+    assert_eq!((first["ok"].as_bool(), first["nonce"].as_str()), (Some(true), Some("abc")));
+    thread::sleep(Duration::from_millis(20));
+    let second: serde_json::Value = serde_json::from_str(&server.get("/api/ping").body).unwrap();
+    assert!(second["monotonic_ms"].as_u64().unwrap() >= first["monotonic_ms"].as_u64().unwrap() + 20);
+    assert!(second.get("nonce").is_none());
+    assert_eq!(server.get(&format!("/api/ping?nonce={}", "x".repeat(201))).status, 400);
+    assert_eq!(server.get("/api/load").status, 401);
+}
+
+#[test]
+fn reads_bodies_to_eof_when_asked() {
+    // No Content-Length; the body ends where the client stops sending
+    let send_and_close = |server: &TestServer, body: &str| {
+        let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+        write!(stream, "POST /api/interpreter HTTP/1.1\r\nHost: localhost\r\n\r\n{}", body).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        Response::parse(&response)
+    };
+    let body = r#"{"command": "echo hi"}"#;
+    assert_eq!(send_and_close(&TestServer::start(&[]), body).status, 400);
+
+    let server = TestServer::start(&["--read-to-eof", "--max-body-bytes", "30"]);
+    let response = send_and_close(&server, body);
+    assert_eq!((response.status, response.body.as_str()), (200, "hi\n"));
+    assert_eq!(send_and_close(&server, r#"{"command": "echo this is too long for the limit"}"#).status, 413);
+    // An explicit Content-Length still wins
+    assert_eq!(server.send("POST /api/interpreter HTTP/1.1\r\nContent-Length: 0\r\n\r\n").status, 400);
+}
+
+#[test]
+fn applies_socket_options_to_connections() {
+    let args = ["--debug", "--token", "secret", "--no-tcp-nodelay", "--socket-send-buffer", "65536", "--socket-recv-buffer", "32768"];
+    let server = TestServer::start(&args);
+    let response = server.send("GET /admin/config HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    assert_eq!(response.status, 200, "{}", response.body);
+    let config: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(config["options"]["no-tcp-nodelay"]["value"], true);
+    assert_eq!(config["options"]["socket-send-buffer"]["value"], 65536);
+    assert_eq!(config["options"]["socket-recv-buffer"]["value"], 32768);
+    // As read back from the accepted socket; Linux doubles buffer sizes for its own bookkeeping
+    let log = server.log.lock().unwrap().clone();
+    let applied = log.lines().find(|line| line.contains("Socket options for")).expect(&log);
+    assert!(applied.contains("nodelay: false"), "{}", applied);
+    assert!(applied.contains("send_buffer: Some(65536)") || applied.contains("send_buffer: Some(131072)"), "{}", applied);
+    assert!(applied.contains("recv_buffer: Some(32768)") || applied.contains("recv_buffer: Some(65536)"), "{}", applied);
+    assert!(!log.contains("Failed to set socket options"));
+
+    // Would run the script and exit 0 if it were taken
+    let stub = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/stub_python.sh");
+    let rejected = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"))
+        .args(["--python", stub, "--socket-send-buffer", "0", "--exec", "true"])
+        .output()
+        .unwrap();
+    assert_eq!(rejected.status.code(), Some(2));
+}
+
+#[test]
+fn handles_nul_bytes_on_stdout_by_policy() {
+    let script = r#"{"command": "printf 'a\\000b'"}"#;
+    let server = TestServer::start(&[]);
+    let encoded = server.post("/api/interpreter", script);
+    assert_eq!(encoded.status, 200);
+    assert_eq!(encoded.body, "YQBi");
+    assert_eq!(encoded.header("x-binary-output"), Some("base64"));
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo text"}"#).header("x-binary-output"), None);
+
+    let replaced = TestServer::start(&["--binary-output", "replace"]).post("/api/interpreter", script);
+    assert_eq!(replaced.body, "a\u{fffd}b");
+    assert_eq!(replaced.header("x-binary-output"), Some("replace"));
+
+    let refused = TestServer::start(&["--binary-output", "error"]).post("/api/interpreter", script);
+    assert_eq!(refused.status, 500);
+    assert!(refused.body.contains("NUL bytes"), "{}", refused.body);
+}
+
+#[test]
+fn flushes_caches_and_stale_temp_dirs() {
+    let work_dir = std::env::temp_dir().join(format!("fpb-test-{}-flush", std::process::id()));
+    // Named like a run dir of a process that can't exist, and a dir that isn't one
+    std::fs::create_dir_all(work_dir.join("999999999-1")).unwrap();
+    std::fs::create_dir_all(work_dir.join("keep")).unwrap();
+    let server = TestServer::start(&["--token", "secret", "--work-dir", work_dir.to_str().unwrap()]);
+    let authorized = |request: &str, extra: &str, body: &str| {
+        server.send(&format!("{} HTTP/1.1\r\nAuthorization: Bearer secret\r\n{}Content-Length: {}\r\n\r\n{}", request, extra, body.len(), body))
+    };
+    assert_eq!(authorized("POST /api/interpreter", "", r#"{"command": "echo one"}"#).status, 200);
+    assert_eq!(authorized("POST /api/interpreter", "Idempotency-Key: k\r\n", r#"{"command": "echo two"}"#).status, 200);
+
+    assert_eq!(server.post("/admin/flush", "").status, 401);
+    let flushed: serde_json::Value = serde_json::from_str(&authorized("POST /admin/flush", "", "").body).unwrap();
+    assert_eq!(flushed, serde_json::json!({"history": 2, "idempotency": 1, "package_list": 0, "temp_dirs": 1}));
+    assert!(!work_dir.join("999999999-1").exists());
+    assert!(work_dir.join("keep").exists());
+    let history: serde_json::Value = serde_json::from_str(&authorized("GET /admin/history", "", "").body).unwrap();
+    assert_eq!(history["entries"], serde_json::json!([]));
+    // The key is free again, so this runs instead of replaying the first response
+    let rerun = authorized("POST /api/interpreter", "Idempotency-Key: k\r\n", r#"{"command": "echo three"}"#);
+    assert_eq!(rerun.body, "three\n");
+    let _ = std::fs::remove_dir_all(&work_dir);
+}
+
+#[test]
+fn answers_410_for_jobs_past_max_completed_jobs() {
+    let server = TestServer::start(&["--max-completed-jobs", "1"]);
+    let finished = |command: &str| {
+        let started: serde_json::Value = serde_json::from_str(&server.post("/api/jobs", &format!(r#"{{"command": "{}"}}"#, command)).body).unwrap();
+        let path = format!("/api/jobs/{}", started["job_id"].as_str().unwrap());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !server.get(&path).body.contains(r#""status":"done""#) {
+            assert!(Instant::now() < deadline, "job never finished");
+            thread::sleep(Duration::from_millis(20));
+        }
+        path
+    };
+    let first = finished("echo first");
+    let second = finished("echo second");
+    let gone = server.get(&first);
+    assert_eq!(gone.status, 410);
+    assert!(gone.body.contains("expired"), "{}", gone.body);
+    assert_eq!(server.get(&second).status, 200);
+    assert_eq!(server.get("/api/jobs/unknown").status, 404);
+}
+
+#[test]
+fn drives_a_session_over_a_websocket() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python]);
+    let handshake = |version: &str| {
+        format!("GET /api/ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: {}\r\n\r\n", version)
+    };
+    assert_eq!(server.get("/api/ws").status, 400);
+    assert_eq!(server.send(&handshake("8")).status, 426);
+
+    let mut socket = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    socket.write_all(handshake("13").as_bytes()).unwrap();
+    let mut reader = BufReader::new(socket.try_clone().unwrap());
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        reader.read_line(&mut head).unwrap();
+    }
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    // The example from RFC 6455
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "{}", head);
+
+    let mut send = |opcode: u8, payload: &[u8]| {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        socket.write_all(&frame).unwrap();
+    };
+    let mut receive = || {
+        let mut head = [0u8; 2];
+        reader.read_exact(&mut head).unwrap();
+        let mut payload = vec![0u8; (head[1] & 0x7f) as usize];
+        reader.read_exact(&mut payload).unwrap();
+        (head[0] & 0x0f, payload)
+    };
+    let (_, opened) = receive();
+    let opened: serde_json::Value = serde_json::from_slice(&opened).unwrap();
+    assert!(opened["session_id"].is_string());
+
+    send(0x1, br#"{"code": "x = 41"}"#);
+    let (_, first) = receive();
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&first).unwrap()["status"], 200);
+    send(0x1, br#"{"code": "print(x + 1)"}"#);
+    let (opcode, second) = receive();
+    assert_eq!(opcode, 0x1);
+    let second: serde_json::Value = serde_json::from_slice(&second).unwrap();
+    assert_eq!(second["stdout"], "42\n");
+    send(0x9, b"hi");
+    assert_eq!(receive(), (0xa, b"hi".to_vec()));
+    send(0x1, b"not json");
+    let (_, refused) = receive();
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&refused).unwrap()["status"], 400);
+
+    send(0x8, &1000u16.to_be_bytes());
+    assert_eq!(receive(), (0x8, 1000u16.to_be_bytes().to_vec()));
+    // The session was the socket's own, so it went with it
+    let path = format!("/api/sessions/{}", opened["session_id"].as_str().unwrap());
+    assert_eq!(server.get(&path).status, 404);
+}
+
+#[test]
+fn answers_507_when_the_work_dir_is_full() {
+    // A tiny tmpfs, filled up, in a mount namespace of the helper's own
+    let work_dir = std::env::temp_dir().join(format!("fpb-test-{}-full", std::process::id()));
+    std::fs::create_dir_all(&work_dir).unwrap();
+    let mount = format!("mount -t tmpfs -o size=16k none {0} && head -c 16384 /dev/zero > {0}/fill; exec \"$@\"", work_dir.display());
+    let supported = Command::new("unshare").args(["-Urm", "sh", "-c", &mount, "sh", "true"]).stderr(Stdio::null()).status();
+    if !supported.is_ok_and(|status| status.success()) {
+        eprintln!("skipping: can't mount a tmpfs in a user namespace");
+        return;
+    }
+    let mut helper = Command::new("unshare");
+    helper.args(["-Urm", "sh", "-c", &mount, "sh", env!("CARGO_BIN_EXE_FlurionsPythonBindings")]);
+    let server = TestServer::start_command(helper, &["--work-dir", work_dir.to_str().unwrap()]);
+    let full = server.post("/api/interpreter", &format!(r#"{{"command": "echo {}"}}"#, "x".repeat(8192)));
+    assert_eq!(full.status, 507);
+    assert!(full.body.contains("out of space"), "{}", full.body);
+    let load: serde_json::Value = serde_json::from_str(&server.get("/api/load").body).unwrap();
+    assert_eq!(load["storage"], "full");
+    let _ = std::fs::remove_dir_all(&work_dir);
+}
+
+#[test]
+fn reads_options_from_fpb_environment_variables() {
+    let env = [("FPB_TOKEN", "secret"), ("FPB_MAX_TIMEOUT", "70"), ("FPB_PRETTY", "true"), ("FPB_NO_CONTENT_ON_EMPTY", "0")];
+    let server = TestServer::start_with_env(&["--max-timeout", "90"], &env);
+    assert_eq!(server.get("/admin/config").status, 401);
+    let config = server.send("GET /admin/config HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+    // Pretty-printed, since FPB_PRETTY turned --pretty on
+    assert!(config.body.contains("\n  "), "{}", config.body);
+    let config: serde_json::Value = serde_json::from_str(&config.body).unwrap();
+    assert_eq!(config["options"]["token"]["source"], "env");
+    // The command line wins
+    assert_eq!(config["options"]["max-timeout"]["value"], 90.0);
+    assert_eq!(config["options"]["max-timeout"]["source"], "cli");
+    assert_eq!(config["options"]["no-content-on-empty"]["value"], false);
+
+    for (name, value) in [("FPB_PORT", "not-a-port"), ("FPB_PRETTY", "maybe"), ("FPB_NO_SUCH_OPTION", "1")] {
+        let output = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings")).env(name, value).output().unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}={}", name, value);
+        assert!(String::from_utf8_lossy(&output.stderr).contains(name), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+#[test]
+fn takes_repeated_options_from_numbered_variables() {
+    let env = [("FPB_TOKEN", "secret"), ("FPB_ALLOW_IP_10", "fd00::/8"), ("FPB_ALLOW_IP_2", "10.0.0.0/8"), ("FPB_ALLOW_IP", "::1"), ("FPB_ALLOW_IP_1", "127.0.0.1")];
+    let server = TestServer::start_with_env(&[], &env);
+    let config: serde_json::Value = serde_json::from_str(&server.send("GET /admin/config HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n").body).unwrap();
+    assert_eq!(config["options"]["allow-ip"]["value"], serde_json::json!(["::1/128", "127.0.0.1/32", "10.0.0.0/8", "fd00::/8"]));
+    assert_eq!(config["options"]["allow-ip"]["source"], "env");
+
+    // Only repeatable options are numbered
+    let output = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings")).env("FPB_TIMEOUT_2", "5").output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn starts_with_non_utf8_variables_in_the_environment() {
+    use std::os::unix::ffi::OsStrExt;
+    let weird = std::ffi::OsStr::from_bytes(b"\xff\xfe");
+    let mut helper = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings"));
+    helper.env("WEIRD", weird);
+    let server = TestServer::start_command(helper, &[]);
+    assert_eq!(server.post("/api/interpreter", r#"{"command": "echo hi"}"#).body, "hi\n");
+
+    // Ours have to be readable, though
+    let output = Command::new(env!("CARGO_BIN_EXE_FlurionsPythonBindings")).env("FPB_TOKEN", weird).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("FPB_TOKEN is not valid UTF-8"), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn keeps_fpb_variables_from_scripts() {
+    let server = TestServer::start_with_env(&[], &[("FPB_TOKEN", "s3cret")]);
+    let body = r#"{"command": "echo token=$FPB_TOKEN"}"#;
+    let response = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    assert_eq!((response.status, response.body.as_str()), (200, "token=\n"));
+}
+
+#[test]
+fn streams_progress_written_to_fd_3() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "echo one; echo '{\"done\": 1}' >&3; echo 'not json' >&3; echo two"}"#;
+    let response = server.send(&format!(
+        "POST /api/interpreter HTTP/1.1\r\nAccept: application/x-ndjson\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    let events: Vec<serde_json::Value> = dechunk(&response.body).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert!(events.contains(&serde_json::json!({"progress": {"done": 1}})), "{:?}", events);
+    let progress: Vec<_> = events.iter().filter(|event| event.get("progress").is_some()).collect();
+    assert_eq!(progress.len(), 1);
+    let lines: Vec<_> = events.iter().filter_map(|event| event["line"].as_str()).collect();
+    assert_eq!(lines, ["one", "two"]);
+
+    // Closed everywhere else
+    let result = server.post("/api/interpreter", r#"{"command": "echo x >&3 || echo closed"}"#);
+    assert!(result.body.contains("closed"), "{}", result.body);
+}
+
+#[test]
+fn stamps_streamed_lines_with_timestamps() {
+    let server = TestServer::start(&[]);
+    let body = r#"{"command": "echo early; sleep 0.3; echo late", "timestamps": true}"#;
+    let response = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/x-ndjson\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    let events: Vec<serde_json::Value> = dechunk(&response.body).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let stamps: Vec<u64> = events.iter().filter(|event| event["stream"] == "stdout").map(|event| event["t_ms"].as_u64().unwrap()).collect();
+    assert_eq!(stamps.len(), 2, "{:?}", events);
+    assert!(stamps[1] >= stamps[0] + 250, "{:?}", stamps);
+    assert!(events.last().unwrap().get("t_ms").is_none());
+
+    let body = r#"{"command": "echo plain"}"#;
+    let plain = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/x-ndjson\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    assert!(!dechunk(&plain.body).contains("t_ms"));
+}
+
+#[test]
+fn keeps_imported_bytecode_in_the_pycache_dir() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let base = std::env::temp_dir().join(format!("fpb-test-{}-pycache", std::process::id()));
+    let _ = std::fs::remove_dir_all(&base);
+    let modules = base.join("modules");
+    std::fs::create_dir_all(&modules).unwrap();
+    std::fs::write(modules.join("fpb_cached.py"), "VALUE = 1\n").unwrap();
+    let cache = base.join("cache");
+    let server = TestServer::start(&["--python", python, "--pycache-dir", cache.to_str().unwrap()]);
+    let code = format!("import sys\nsys.path.insert(0, {:?})\nimport fpb_cached\nprint(fpb_cached.VALUE)", modules.display().to_string());
+    let response = server.post("/api/interpreter", &serde_json::json!({"command": code}).to_string());
+    assert_eq!((response.status, response.body.as_str()), (200, "1\n"));
+    assert!(!modules.join("__pycache__").exists());
+    let mirrored = cache.join(modules.canonicalize().unwrap().strip_prefix("/").unwrap());
+    let cached: Vec<_> = std::fs::read_dir(&mirrored).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+    assert!(cached.iter().any(|name| name.starts_with("fpb_cached.") && name.ends_with(".pyc")), "{:?}", cached);
+    let _ = std::fs::remove_dir_all(&base);
+}
+
+#[test]
+fn runs_stored_scripts_from_bytecode_kept_in_the_pycache_dir() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let cache = std::env::temp_dir().join(format!("fpb-test-{}-stored-pycache", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache);
+    let args = ["--python", python, "--pycache-dir", cache.to_str().unwrap()];
+    let body = serde_json::json!({"command": "import sys\nprint(sys.argv[0].endswith('.pyc'))\nprint(sys.argv[1])"}).to_string();
+    let store = |server: &TestServer| {
+        let created: serde_json::Value = serde_json::from_str(&server.post("/api/scripts", &body).body).unwrap();
+        format!("/api/scripts/{}", created["script_id"].as_str().unwrap())
+    };
+    let bytecode = || -> Vec<_> {
+        std::fs::read_dir(cache.join("stored-scripts"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "pyc"))
+            .collect()
+    };
+
+    let server = TestServer::start(&args);
+    let path = store(&server);
+    let response = server.post(&path, r#"{"args": ["failed"]}"#);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "True\nfailed\n");
+    let compiled = bytecode();
+    assert_eq!(compiled.len(), 1, "{:?}", compiled);
+    let modified = std::fs::metadata(&compiled[0]).unwrap().modified().unwrap();
+    // Wrapping it in a harness means running the source
+    assert_eq!(server.post(&path, r#"{"args": ["wrapped"], "timing": true}"#).body, "False\nwrapped\n");
+    drop(server);
+
+    // The same script after a restart reuses it
+    let server = TestServer::start(&args);
+    let response = server.post(&store(&server), r#"{"args": ["again"]}"#);
+    assert_eq!(response.body, "True\nagain\n");
+    assert_eq!(bytecode(), compiled);
+    assert_eq!(std::fs::metadata(&compiled[0]).unwrap().modified().unwrap(), modified);
+    let _ = std::fs::remove_dir_all(&cache);
+}
+
+#[test]
+fn tags_results_with_the_interpreter_version() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let reported = Command::new(python).arg("--version").output().unwrap();
+    let version = String::from_utf8_lossy(&reported.stdout).trim().strip_prefix("Python ").unwrap().to_string();
+    let server = TestServer::start(&["--python", python]);
+    let body = r#"{"command": "print(1)"}"#;
+    let response = server.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    assert_eq!(response.header("x-interpreter-version"), Some(version.as_str()));
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["interpreter"], serde_json::json!({"path": python, "version": version}));
+
+    // Nothing to report for something that isn't python
+    let stub = TestServer::start(&[]);
+    let body = r#"{"command": "id"}"#;
+    let response = stub.send(&format!("POST /api/interpreter HTTP/1.1\r\nAccept: application/json\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    assert_eq!(response.header("x-interpreter-version"), None);
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert!(result["interpreter"]["version"].is_null(), "{}", result);
+}
+
+#[test]
+fn lints_code_without_running_it() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python]);
+    assert_eq!(server.post("/api/lint", r#"{"code": "x"}"#).status, 400);
+    let response = server.post("/api/lint", r#"{"command": "import os\nopen('ran', 'w')\n"}"#);
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let installed = Command::new(python).args(["-m", "ruff", "--version"]).stdout(Stdio::null()).stderr(Stdio::null()).status();
+    if !installed.is_ok_and(|status| status.success()) {
+        assert_eq!(response.status, 501, "{}", response.body);
+        assert_eq!(result["error"], "linter_missing");
+        return;
+    }
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!(result["clean"], false);
+    let finding = &result["findings"][0];
+    assert_eq!((finding["code"].as_str(), finding["line"].as_u64(), finding["col"].as_u64()), (Some("F401"), Some(1), Some(8)));
+}
+
+#[test]
+fn formats_code_and_says_whether_it_changed() {
+    let Some(python) = python3() else {
+        eprintln!("skipping: no python3 on PATH");
+        return;
+    };
+    let server = TestServer::start(&["--python", python]);
+    assert_eq!(server.post("/api/format", r#"{"code": "x"}"#).status, 400);
+    let response = server.post("/api/format", r#"{"command": "x=[1,2]\n"}"#);
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let installed = Command::new(python).args(["-m", "black", "--version"]).stdout(Stdio::null()).stderr(Stdio::null()).status();
+    if !installed.is_ok_and(|status| status.success()) {
+        assert_eq!(response.status, 501, "{}", response.body);
+        assert_eq!(result["error"], "formatter_missing");
+        return;
+    }
+    assert_eq!(response.status, 200, "{}", response.body);
+    assert_eq!((result["formatted"].as_str(), result["changed"].as_bool()), (Some("x = [1, 2]\n"), Some(true)));
+    let response = server.post("/api/format", r#"{"command": "x = [1, 2]\n"}"#);
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(result["changed"], false);
+    let response = server.post("/api/format", r#"{"command": "def (:\n"}"#);
+    assert_eq!(response.status, 422, "{}", response.body);
+    let result: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!((result["error"].as_str(), result["formatted"].as_str()), (Some("format_failed"), Some("def (:\n")));
+}
@@ -0,0 +1,3 @@
+#!/bin/sh
+# Stands in for python in the integration tests: the "script" is run as shell code
+exec /bin/sh "$@"